@@ -0,0 +1,47 @@
+// Made-up documents of a chosen size that still look like real ones: definitions with
+// properties, nested layouts, setters of every value type and the odd comment. Deterministic,
+// so runs compare against each other.
+
+const WIDGETS: &[&str] = &["GtkBox", "GtkLabel", "GtkButton", "GtkImage", "GtkEntry"];
+
+/// A document with `widgets` objects spread over a few top-level definitions.
+pub fn document(widgets: usize) -> String {
+    let mut text = String::from("#include \"gtk-4.0\"\n\n// Shared pieces\n@Card -> GtkBox {\n    @InlineArg(\"title\", String)\n    @InlineProp(\"subtitle\", String)\n    @ChildProp(\"expanded\", Bool)\n}\n");
+    let mut written = 0;
+    let mut definition = 0;
+    while written < widgets {
+        text.push_str(&format!("\n@Page{} -> GtkBox {{\n", definition));
+        // A few hundred widgets per definition, like a large window
+        let in_this = (widgets - written).min(300);
+        let mut depth = 1;
+        for index in 0..in_this {
+            let indent = "    ".repeat(depth);
+            if index % 7 == 0 {
+                text.push_str(&format!("{}Card(\"Item {}\") {{\n", indent, written + index));
+                depth += 1;
+                continue;
+            }
+            let widget = WIDGETS[(written + index) % WIDGETS.len()];
+            text.push_str(&format!("{}{} {{}}\n", indent, widget));
+            text.push_str(&format!("{}    .margin-top({})\n", indent, index % 24));
+            text.push_str(&format!("{}    .visible({})\n", indent, if index % 2 == 0 { "true" } else { "false" }));
+            if index % 3 == 0 {
+                text.push_str(&format!("{}    .label(\"Text with an \\\"escape\\\" and ünïcödé\") // note {}\n", indent, index));
+            }
+            // Close the open cards now and then so the nesting stays shallow
+            if index % 7 == 6 && depth > 1 {
+                depth -= 1;
+                text.push_str(&format!("{}}}\n", "    ".repeat(depth)));
+                text.push_str(&format!("{}    .subtitle(\"closed\")\n", "    ".repeat(depth)));
+            }
+        }
+        while depth > 1 {
+            depth -= 1;
+            text.push_str(&format!("{}}}\n", "    ".repeat(depth)));
+        }
+        text.push_str("}\n");
+        written += in_this;
+        definition += 1;
+    }
+    text
+}
@@ -0,0 +1,132 @@
+// How long the work done on every edit takes for documents of a few sizes: lexing the whole
+// text, encoding its semantic tokens and computing its diagnostics, all three of them after
+// a keystroke, and what a range request costs once the full encoding is cached. Run with `cargo bench`,
+// optionally followed by `-- <filter>` to only run the benchmarks whose name contains it.
+// Numbers are only comparable between runs on the same machine.
+
+// Shared with anything else that needs documents of a chosen size
+#[path = "common/synthetic.rs"]
+mod synthetic;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use gtkui_language_server::{database, diagnostics, lexer, position, semantic_tokens, settings};
+use database::WidgetDb;
+use lexer::LexOptions;
+use position::PositionEncoding;
+use semantic_tokens::TokenTypes;
+use ropey::Rope;
+use settings::Settings;
+use tower_lsp::lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+const SIZES: &[(&str, usize)] = &[("small", 20), ("medium", 500), ("large", 5000)];
+const SAMPLES: usize = 20;
+// Each sample repeats the work until it takes at least this long, so the clock's resolution
+// doesn't matter
+const SAMPLE_TIME: Duration = Duration::from_millis(20);
+
+// The median time of one run of `work`
+fn measure(mut work: impl FnMut()) -> Duration {
+    let mut iterations = 1;
+    loop {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            work();
+        }
+        if start.elapsed() >= SAMPLE_TIME {
+            break;
+        }
+        iterations *= 2;
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                work();
+            }
+            start.elapsed() / iterations
+        })
+        .collect();
+    samples.sort();
+    samples[SAMPLES / 2]
+}
+
+fn report(name: &str, bytes: usize, time: Duration) {
+    let throughput = bytes as f64 / time.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{:<32} {:>12.3?} {:>10.1} MiB/s", name, time, throughput);
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` along with whatever follows `--`
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let selected = |name: &str| filter.as_deref().is_none_or(|filter| name.contains(filter));
+    let options = LexOptions { include_trivia: true, allow_recovery: true };
+    let settings = Settings::default();
+    let db = WidgetDb::default();
+
+    for (size, widgets) in SIZES {
+        let text = synthetic::document(*widgets);
+        let rope = Rope::from_str(&text);
+        let (tokens, _) = lexer::lex_with(&text, options).expect("the synthetic document doesn't lex");
+
+        let name = format!("lex/{}", size);
+        if selected(&name) {
+            report(&name, text.len(), measure(|| {
+                black_box(lexer::lex_with(black_box(&text), options).ok());
+            }));
+        }
+        let name = format!("semantic_tokens/{}", size);
+        if selected(&name) {
+            report(&name, text.len(), measure(|| {
+                black_box(semantic_tokens::encode_semantic_tokens(black_box(&tokens), &rope, PositionEncoding::Utf16, &TokenTypes::default(), false));
+            }));
+        }
+        // A screenful from the middle, as scrolling asks for, sliced from the cached encoding
+        let name = format!("semantic_tokens_range/{}", size);
+        if selected(&name) {
+            let encoded = semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false);
+            let middle = (rope.len_lines() / 2) as u32;
+            let screen = Range::new(Position::new(middle, 0), Position::new(middle + 60, 0));
+            report(&name, text.len(), measure(|| {
+                black_box(semantic_tokens::slice_semantic_tokens(black_box(&encoded), screen));
+            }));
+        }
+        let name = format!("diagnostics/{}", size);
+        if selected(&name) {
+            report(&name, text.len(), measure(|| {
+                black_box(diagnostics::compute_diagnostics(black_box(&text), PositionEncoding::Utf16, &settings, &db));
+            }));
+        }
+        // Minified onto one line, where every token after the first shares its line start
+        let name = format!("semantic_tokens_one_line/{}", size);
+        if selected(&name) {
+            // Without its comments, which would otherwise run to the end of it
+            let line: Vec<&str> = text.lines().map(|line| line.split("//").next().unwrap()).collect();
+            let line = line.join(" ");
+            let line_rope = Rope::from_str(&line);
+            let (line_tokens, _) = lexer::lex_with(&line, options).expect("the minified document doesn't lex");
+            report(&name, line.len(), measure(|| {
+                black_box(semantic_tokens::encode_semantic_tokens(black_box(&line_tokens), &line_rope, PositionEncoding::Utf16, &TokenTypes::default(), false));
+            }));
+        }
+        // What a typed character costs before anything is published: applying the change,
+        // relexing and encoding the tokens again
+        let name = format!("edit/{}", size);
+        if selected(&name) {
+            let middle = (rope.len_lines() / 2) as u32;
+            let change = TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(middle, 0), Position::new(middle, 0))),
+                range_length: None,
+                text: String::from(" ")
+            };
+            report(&name, text.len(), measure(|| {
+                let mut edited = rope.clone();
+                position::apply_change(&mut edited, black_box(&change), PositionEncoding::Utf16);
+                let edited_text = edited.to_string();
+                if let Ok((edited_tokens, _)) = lexer::lex_with(&edited_text, options) {
+                    black_box(semantic_tokens::encode_semantic_tokens(&edited_tokens, &edited, PositionEncoding::Utf16, &TokenTypes::default(), false));
+                }
+            }));
+        }
+    }
+}
@@ -50,6 +50,8 @@ export async function activate(context: ExtensionContext) {
   });
 
   context.subscriptions.push(disposable);
+  context.subscriptions.push(commands.registerCommand("gtkui-language-server.renameProperty", renameProperty));
+  context.subscriptions.push(commands.registerCommand("gtkui-language-server.toggleBoolean", toggleBoolean));
 
   const traceOutputChannel = window.createOutputChannel("GtkUI Language Server trace");
   const command = process.env.SERVER_PATH || "gtkui-language-server";
@@ -72,8 +74,13 @@ export async function activate(context: ExtensionContext) {
   // Options to control the language client
   let clientOptions: LanguageClientOptions = {
     // Register the server for plain text documents
-    documentSelector: [{ scheme: "file", language: "gui" }],
+    documentSelector: [
+      { scheme: "file", language: "gui" },
+      { scheme: "vscode-notebook-cell", language: "gui" }
+    ],
+    initializationOptions: workspace.getConfiguration("gtkui-language-server"),
     synchronize: {
+      configurationSection: "gtkui-language-server",
       // Notify the server about file changes to '.clientrc files contained in the workspace
       fileEvents: workspace.createFileSystemWatcher("**/.clientrc"),
     },
@@ -86,6 +93,57 @@ export async function activate(context: ExtensionContext) {
   client.start();
 }
 
+// Asks for the widget and both names, then shows how much would change before changing it
+async function renameProperty() {
+  const widget = await window.showInputBox({ prompt: "Widget whose property was renamed", placeHolder: "GtkLabel" });
+  const from = widget && (await window.showInputBox({ prompt: `Current name of the ${widget} property` }));
+  const to = from && (await window.showInputBox({ prompt: `New name for .${from}`, value: from }));
+  if (!to || to === from) {
+    return;
+  }
+  const params = { widget, from, to, dryRun: true };
+  const preview = (await commands.executeCommand("gtkui.renameProperty", params)) as { edits: number; files: number };
+  if (!preview || preview.edits === 0) {
+    window.showInformationMessage(`No .${from} setters on ${widget} widgets`);
+    return;
+  }
+  const choice = await window.showInformationMessage(
+    `Rename ${preview.edits} .${from} setters in ${preview.files} files to .${to}?`,
+    "Rename"
+  );
+  if (choice === "Rename") {
+    await commands.executeCommand("gtkui.renameProperty", { ...params, dryRun: false });
+  }
+}
+
+// Flips the Bool setter under the cursor, or asks which one to add when there is none
+async function toggleBoolean() {
+  const editor = window.activeTextEditor;
+  if (!editor || editor.document.languageId !== "gui") {
+    return;
+  }
+  const { line, character } = editor.selection.active;
+  const params = { textDocument: { uri: editor.document.uri.toString() }, position: { line, character } };
+  type Edit = { range: { start: { line: number; character: number }; end: { line: number; character: number } }; newText: string };
+  let edit: Edit;
+  try {
+    edit = (await commands.executeCommand("gtkui.toggleBoolean", params)) as Edit;
+  } catch (err) {
+    const property = await window.showInputBox({ prompt: "Bool property to set to true", placeHolder: "visible" });
+    if (!property) {
+      return;
+    }
+    try {
+      edit = (await commands.executeCommand("gtkui.toggleBoolean", { ...params, property })) as Edit;
+    } catch (err) {
+      window.showErrorMessage(`Could not toggle .${property}: ${err.message ?? err}`);
+      return;
+    }
+  }
+  const { start, end } = edit.range;
+  await editor.edit(builder => builder.replace(new Range(start.line, start.character, end.line, end.character), edit.newText));
+}
+
 export function deactivate(): Thenable<void> | undefined {
   if (!client) {
     return undefined;
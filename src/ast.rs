@@ -0,0 +1,806 @@
+use gtk_ui::lexer::{
+    Token,
+    TokenValue,
+    DefinitionType as TokenDefinitionType,
+    DirectiveType as TokenDirectiveType,
+    IdentifierType as TokenIdentifierType,
+    TypeIdentifierType as TokenTypeIdentifierType
+};
+use std::ops::Range;
+use crate::lexer;
+
+// Nodes
+//
+// This mirrors the statements of `gtk_ui::parser`, but every node keeps the byte range
+// it covers and the parser keeps going after an error so editor features still work on
+// half-written files.
+
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub directive_type: TokenDirectiveType,
+    pub argument: Option<(String, Range<usize>)>,
+    pub range: Range<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub definition_type: TokenDefinitionType,
+    pub name: Option<String>,
+    // The string the name is written as, quotes included
+    pub name_range: Option<Range<usize>>,
+    pub internal_type: Option<TokenTypeIdentifierType>,
+    pub range: Range<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub name_range: Range<usize>,
+    pub inherits: Vec<(String, Range<usize>)>,
+    pub children: Vec<Node>,
+    pub block: Option<Range<usize>>,
+    pub range: Range<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct Setter {
+    pub name: String,
+    pub name_range: Range<usize>,
+    pub value: Option<Token>,
+    pub range: Range<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub name: String,
+    pub name_range: Range<usize>,
+    pub arguments: Vec<Token>,
+    pub children: Vec<Node>,
+    pub setters: Vec<Setter>,
+    pub block: Option<Range<usize>>,
+    pub range: Range<usize>,
+    // How many blocks it is inside of, so 1 for the children of a definition
+    pub depth: usize
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Directive(Directive),
+    Definition(Definition),
+    Property(Property),
+    Object(Object)
+}
+
+impl Node {
+    pub fn range(&self) -> &Range<usize> {
+        match self {
+            Node::Directive(directive) => &directive.range,
+            Node::Definition(definition) => &definition.range,
+            Node::Property(property) => &property.range,
+            Node::Object(object) => &object.range
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Definition(definition) => &definition.children,
+            Node::Object(object) => &object.children,
+            _ => &[]
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub range: Range<usize>
+}
+
+// What the parser did to get past an error, so tooling can show where it made assumptions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recovery {
+    // A token that fit nowhere and was dropped
+    Skipped(Range<usize>),
+    // A missing `}` or `)`, taken to be at the offset
+    Inserted(&'static str, usize)
+}
+
+// One turn of the top-level loop: the tokens it took, leaving out comments, and how much of
+// each part of the tree came from it. Only what these tokens and the one after them say
+// goes into it, so it can be kept as it is when an edit is elsewhere
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub tokens: Range<usize>,
+    pub nodes: usize,
+    pub errors: usize,
+    pub recoveries: usize,
+    pub stray_setters: usize
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    pub nodes: Vec<Node>,
+    pub errors: Vec<ParseError>,
+    pub recoveries: Vec<Recovery>,
+    // Setters that belong to no widget, by their range
+    pub stray_setters: Vec<Range<usize>>,
+    // Empty for a tree that didn't come from the parser, which is then never reused
+    pub steps: Vec<Step>
+}
+
+// Parser
+
+struct Parser<'a> {
+    tokens: Vec<&'a Token>,
+    index: usize,
+    errors: Vec<ParseError>,
+    recoveries: Vec<Recovery>,
+    stray_setters: Vec<Range<usize>>,
+    // Blocks around the current token
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+
+    // Helper Functions
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.index).copied()
+    }
+
+    fn advance(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.index += 1;
+        token
+    }
+
+    fn end_of_input(&self) -> usize {
+        self.tokens.last().map(|token| token.range.end).unwrap_or(0)
+    }
+
+    fn error(&mut self, message: String, range: Range<usize>) {
+        self.errors.push(ParseError { message, range });
+    }
+
+    fn skip(&mut self) {
+        if let Some(token) = self.advance() {
+            self.recoveries.push(Recovery::Skipped(token.range.clone()));
+        }
+    }
+
+    fn error_at_current(&mut self, expected: &str) {
+        match self.peek() {
+            Some(token) => self.error(format!("expected {}, found {}", expected, token.to_string()), token.range.clone()),
+            None => {
+                let end = self.end_of_input();
+                self.error(format!("expected {}, found end of input", expected), end..end)
+            }
+        }
+    }
+
+    // Tokens that begin a statement are never swallowed while recovering
+    fn at_statement_boundary(&self) -> bool {
+        matches!(
+            self.peek().map(|token| &token.value),
+            None
+            | Some(TokenValue::StartBlock)
+            | Some(TokenValue::EndBlock)
+            | Some(TokenValue::Setter(_))
+            | Some(TokenValue::Definition(_))
+            | Some(TokenValue::Directive(_))
+        )
+    }
+
+    // Parsing Functions
+
+    fn nodes(&mut self, top_level: bool) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        while let Some(token) = self.peek() {
+            if !top_level && matches!(token.value, TokenValue::EndBlock) {
+                break;
+            }
+            nodes.extend(self.statement(token, top_level));
+        }
+        nodes
+    }
+
+    // A top-level statement, noted down as a step
+    fn step(&mut self, nodes: &mut Vec<Node>, steps: &mut Vec<Step>) {
+        let (start, errors, recoveries, stray_setters) = (self.index, self.errors.len(), self.recoveries.len(), self.stray_setters.len());
+        let node = self.peek().and_then(|token| self.statement(token, true));
+        steps.push(Step {
+            tokens: start..self.index,
+            nodes: node.is_some() as usize,
+            errors: self.errors.len() - errors,
+            recoveries: self.recoveries.len() - recoveries,
+            stray_setters: self.stray_setters.len() - stray_setters
+        });
+        nodes.extend(node);
+    }
+
+    fn statement(&mut self, token: &'a Token, top_level: bool) -> Option<Node> {
+        let node = match &token.value {
+            TokenValue::Definition(definition_type) => self.definition(definition_type.clone(), token),
+            TokenValue::Directive(directive_type) => self.directive(directive_type.clone(), token),
+            TokenValue::Identifier(TokenIdentifierType::Generic(name)) => self.object(name.clone(), token),
+            // Setters right after an object are parsed with it, so this one belongs to no
+            // widget. Its arguments go with it rather than each being unexpected
+            TokenValue::Setter(name) => {
+                let setter = self.setter(name.clone(), token);
+                self.error(String::from("property outside of a widget"), setter.range.clone());
+                self.stray_setters.push(setter.range);
+                return None;
+            },
+            _ => {
+                self.error(format!("unexpected {}", token.to_string()), token.range.clone());
+                self.skip();
+                return None;
+            }
+        };
+
+        match (&node, top_level) {
+            (Node::Property(_), true) | (Node::Object(_), true) => {
+                let message = format!("found {} on top level. Only object definitions and directives are allowed here.", node_kind(&node));
+                self.error(message, node.range().clone());
+            },
+            (Node::Definition(_), false) | (Node::Directive(_), false) => {
+                let message = format!("found {} inside block. Only properties and objects are allowed here.", node_kind(&node));
+                self.error(message, node.range().clone());
+            },
+            _ => ()
+        }
+        Some(node)
+    }
+
+    fn block(&mut self) -> (Vec<Node>, Range<usize>) {
+        let start = match self.advance() {
+            Some(token) => token.range.clone(),
+            None => return (Vec::new(), self.end_of_input()..self.end_of_input())
+        };
+        self.depth += 1;
+        let children = self.nodes(false);
+        self.depth -= 1;
+        match self.peek() {
+            Some(token) if matches!(token.value, TokenValue::EndBlock) => {
+                self.advance();
+                (children, start.start..token.range.end)
+            },
+            _ => {
+                self.error(String::from("expected '}' to close this block"), start.clone());
+                let end = children.last().map(|child| child.range().end).unwrap_or(start.end);
+                self.recoveries.push(Recovery::Inserted("}", end));
+                (children, start.start..end)
+            }
+        }
+    }
+
+    fn arglist(&mut self) -> (Vec<Token>, Range<usize>) {
+        let start = match self.advance() {
+            Some(token) => token.range.clone(),
+            None => return (Vec::new(), self.end_of_input()..self.end_of_input())
+        };
+        let mut arguments = Vec::new();
+        let mut end = start.end;
+
+        loop {
+            match self.peek() {
+                Some(token) if matches!(token.value, TokenValue::EndArgList) && arguments.is_empty() => {
+                    self.advance();
+                    return (arguments, start.start..token.range.end);
+                },
+                Some(token) if matches!(
+                    token.value,
+                    TokenValue::Number(_) | TokenValue::String(_) | TokenValue::Bool(_) | TokenValue::Identifier(_)
+                ) => {
+                    self.advance();
+                    end = token.range.end;
+                    arguments.push(token.clone());
+                },
+                _ => {
+                    self.error_at_current("Number, String, Bool, or type identifier");
+                    if !self.at_statement_boundary() {
+                        self.skip();
+                    }
+                    self.recoveries.push(Recovery::Inserted(")", end));
+                    return (arguments, start.start..end);
+                }
+            }
+
+            match self.peek() {
+                Some(token) if matches!(token.value, TokenValue::ArgListDeliminator) => {
+                    self.advance();
+                },
+                Some(token) if matches!(token.value, TokenValue::EndArgList) => {
+                    self.advance();
+                    return (arguments, start.start..token.range.end);
+                },
+                _ => {
+                    self.error_at_current("',' or ')'");
+                    self.recoveries.push(Recovery::Inserted(")", end));
+                    return (arguments, start.start..end);
+                }
+            }
+        }
+    }
+
+    fn definition(&mut self, definition_type: TokenDefinitionType, token: &'a Token) -> Node {
+        self.advance();
+        if let TokenDefinitionType::Object(name) = definition_type {
+            let mut inherits = Vec::new();
+            let mut end = token.range.end;
+
+            if let Some(TokenValue::Inherits) = self.peek().map(|token| &token.value) {
+                end = self.advance().map(|token| token.range.end).unwrap_or(end);
+                match self.peek() {
+                    Some(parent) if matches!(parent.value, TokenValue::StartArgList) => {
+                        let (arguments, range) = self.arglist();
+                        for argument in arguments {
+                            if let TokenValue::Identifier(TokenIdentifierType::Generic(parent)) = &argument.value {
+                                inherits.push((parent.clone(), argument.range.clone()));
+                            } else {
+                                self.error(String::from("argument list of parents must only contain definitions"), argument.range.clone());
+                            }
+                        }
+                        end = range.end;
+                    },
+                    Some(Token { value: TokenValue::Identifier(TokenIdentifierType::Generic(parent)), range }) => {
+                        self.advance();
+                        inherits.push((parent.clone(), range.clone()));
+                        end = range.end;
+                    },
+                    _ => self.error_at_current("an argument list or definition identifier")
+                }
+            }
+
+            let mut children = Vec::new();
+            let mut block = None;
+            if let Some(TokenValue::StartBlock) = self.peek().map(|token| &token.value) {
+                let (nodes, range) = self.block();
+                end = range.end;
+                children = nodes;
+                block = Some(range);
+            } else {
+                self.error_at_current("a '->' or '{'");
+            }
+
+            Node::Definition(Definition {
+                name,
+                name_range: token.range.clone(),
+                inherits,
+                children,
+                block,
+                range: token.range.start..end
+            })
+        } else {
+            let mut name = None;
+            let mut name_range = None;
+            let mut internal_type = None;
+            let mut end = token.range.end;
+
+            if let Some(TokenValue::StartArgList) = self.peek().map(|token| &token.value) {
+                let (arguments, range) = self.arglist();
+                end = range.end;
+                if arguments.len() != 2 {
+                    self.error(format!("expected only 2 arguments, found {} args", arguments.len()), range);
+                }
+                match arguments.first().map(|argument| &argument.value) {
+                    Some(TokenValue::String(string)) => {
+                        name = Some(string.clone());
+                        name_range = Some(arguments[0].range.clone());
+                    },
+                    Some(_) => self.error(format!("expected String, found {}", arguments[0].to_string()), arguments[0].range.clone()),
+                    None => ()
+                }
+                match arguments.get(1).map(|argument| &argument.value) {
+                    Some(TokenValue::Identifier(TokenIdentifierType::Type(type_identifier))) => internal_type = Some(type_identifier.clone()),
+                    Some(_) => self.error(format!("expected type identifier, found {}", arguments[1].to_string()), arguments[1].range.clone()),
+                    None => ()
+                }
+            } else {
+                self.error_at_current("start of argument list");
+            }
+
+            Node::Property(Property {
+                definition_type,
+                name,
+                name_range,
+                internal_type,
+                range: token.range.start..end
+            })
+        }
+    }
+
+    fn directive(&mut self, directive_type: TokenDirectiveType, token: &'a Token) -> Node {
+        self.advance();
+        let mut argument = None;
+        match self.peek() {
+            Some(Token { value: TokenValue::String(string), range }) => {
+                self.advance();
+                argument = Some((string.clone(), range.clone()));
+            },
+            _ => self.error_at_current("string")
+        }
+        let end = argument.as_ref().map(|(_, range)| range.end).unwrap_or(token.range.end);
+
+        Node::Directive(Directive {
+            directive_type,
+            argument,
+            range: token.range.start..end
+        })
+    }
+
+    fn setter(&mut self, name: String, token: &'a Token) -> Setter {
+        self.advance();
+        let mut value = None;
+        let mut end = token.range.end;
+
+        if let Some(TokenValue::StartArgList) = self.peek().map(|token| &token.value) {
+            let (arguments, range) = self.arglist();
+            end = range.end;
+            if arguments.len() != 1 {
+                self.error(format!("expected 1 argument, got {}", arguments.len()), range);
+            }
+            if let Some(argument) = arguments.into_iter().next() {
+                match argument.value {
+                    TokenValue::Number(_) | TokenValue::String(_) | TokenValue::Bool(_) => value = Some(argument),
+                    _ => self.error(format!("expected Number, String, or Bool, found {}", argument.to_string()), argument.range.clone())
+                }
+            }
+        } else {
+            self.error_at_current("start of argument list");
+        }
+
+        Setter {
+            name,
+            name_range: token.range.clone(),
+            value,
+            range: token.range.start..end
+        }
+    }
+
+    fn object(&mut self, name: String, token: &'a Token) -> Node {
+        let depth = self.depth;
+        self.advance();
+        let mut arguments = Vec::new();
+        let mut children = Vec::new();
+        let mut block = None;
+        let mut end = token.range.end;
+
+        match self.peek().map(|token| &token.value) {
+            Some(TokenValue::StartArgList) => {
+                let (args, range) = self.arglist();
+                arguments = args;
+                end = range.end;
+                if let Some(TokenValue::StartBlock) = self.peek().map(|token| &token.value) {
+                    let (nodes, range) = self.block();
+                    children = nodes;
+                    end = range.end;
+                    block = Some(range);
+                }
+            },
+            Some(TokenValue::StartBlock) => {
+                let (nodes, range) = self.block();
+                children = nodes;
+                end = range.end;
+                block = Some(range);
+            },
+            _ => self.error_at_current("the start of an argument list or block")
+        }
+
+        let mut setters = Vec::new();
+        while let Some(setter) = self.peek() {
+            if let TokenValue::Setter(name) = &setter.value {
+                let setter = self.setter(name.clone(), setter);
+                end = setter.range.end;
+                setters.push(setter);
+            } else {
+                break;
+            }
+        }
+
+        Node::Object(Object {
+            name,
+            name_range: token.range.clone(),
+            arguments,
+            children,
+            setters,
+            block,
+            range: token.range.start..end,
+            depth
+        })
+    }
+}
+
+fn node_kind(node: &Node) -> &str {
+    match node {
+        Node::Directive(_) => "Directive",
+        Node::Definition(_) => "Definition",
+        Node::Property(_) => "Property",
+        Node::Object(_) => "Object"
+    }
+}
+
+fn parser(tokens: &[Token]) -> Parser<'_> {
+    Parser {
+        tokens: tokens.iter().filter(|token| !matches!(token.value, TokenValue::Comment)).collect(),
+        index: 0,
+        errors: Vec::new(),
+        recoveries: Vec::new(),
+        stray_setters: Vec::new(),
+        depth: 0
+    }
+}
+
+pub fn parse(tokens: &[Token]) -> Ast {
+    let mut parser = parser(tokens);
+    let (mut nodes, mut steps) = (Vec::new(), Vec::new());
+    while parser.peek().is_some() {
+        parser.step(&mut nodes, &mut steps);
+    }
+    Ast {
+        nodes,
+        errors: parser.errors,
+        recoveries: parser.recoveries,
+        stray_setters: parser.stray_setters,
+        steps
+    }
+}
+
+// Incremental Parsing
+
+fn shifted(range: &Range<usize>, delta: isize) -> Range<usize> {
+    range.start.wrapping_add_signed(delta)..range.end.wrapping_add_signed(delta)
+}
+
+fn shift_token(token: &mut Token, delta: isize) {
+    token.range = shifted(&token.range, delta);
+}
+
+fn shift_node(node: &mut Node, delta: isize) {
+    match node {
+        Node::Directive(directive) => {
+            if let Some((_, range)) = &mut directive.argument {
+                *range = shifted(range, delta);
+            }
+            directive.range = shifted(&directive.range, delta);
+        },
+        Node::Property(property) => {
+            property.name_range = property.name_range.as_ref().map(|range| shifted(range, delta));
+            property.range = shifted(&property.range, delta);
+        },
+        Node::Definition(definition) => {
+            definition.name_range = shifted(&definition.name_range, delta);
+            for (_, range) in &mut definition.inherits {
+                *range = shifted(range, delta);
+            }
+            definition.children.iter_mut().for_each(|child| shift_node(child, delta));
+            definition.block = definition.block.as_ref().map(|block| shifted(block, delta));
+            definition.range = shifted(&definition.range, delta);
+        },
+        Node::Object(object) => {
+            object.name_range = shifted(&object.name_range, delta);
+            object.arguments.iter_mut().for_each(|argument| shift_token(argument, delta));
+            object.children.iter_mut().for_each(|child| shift_node(child, delta));
+            for setter in &mut object.setters {
+                setter.name_range = shifted(&setter.name_range, delta);
+                setter.value.iter_mut().for_each(|value| shift_token(value, delta));
+                setter.range = shifted(&setter.range, delta);
+            }
+            object.block = object.block.as_ref().map(|block| shifted(block, delta));
+            object.range = shifted(&object.range, delta);
+        }
+    }
+}
+
+// How many nodes, errors, recoveries and stray setters the steps made between them
+fn made_by(steps: &[Step]) -> (usize, usize, usize, usize) {
+    steps.iter().fold((0, 0, 0, 0), |(nodes, errors, recoveries, stray_setters), step| {
+        (nodes + step.nodes, errors + step.errors, recoveries + step.recoveries, stray_setters + step.stray_setters)
+    })
+}
+
+/// The tree for `tokens`, given the one `previous` made from `previous_tokens`. Steps that
+/// only saw tokens before the first change are kept, and once the new parse starts a step
+/// where one started before and the tokens from there on are the same, except for a shift,
+/// the rest is taken over too. Only what the edit touched is parsed again, which is usually a
+/// single top-level block, and the result is always what `parse` would give.
+pub fn reparse(previous: &Ast, previous_tokens: &[Token], tokens: &[Token]) -> Ast {
+    let old: Vec<&Token> = previous_tokens.iter().filter(|token| !matches!(token.value, TokenValue::Comment)).collect();
+    let mut parser = parser(tokens);
+    if previous.steps.last().map_or(0, |step| step.tokens.end) != old.len() {
+        return parse(tokens);
+    }
+
+    let new = &parser.tokens;
+    let same = |a: &Token, b: &Token, delta: isize| shifted(&a.range, delta) == b.range && lexer::same_value(&a.value, &b.value);
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b, 0)).count();
+    if prefix == old.len() && prefix == new.len() {
+        return previous.clone();
+    }
+    let delta = match (old.last(), new.last()) {
+        (Some(a), Some(b)) => b.range.end as isize - a.range.end as isize,
+        _ => 0
+    };
+    let suffix = (1..=old.len().min(new.len()) - prefix)
+        .take_while(|from_end| same(old[old.len() - from_end], new[new.len() - from_end], delta))
+        .count();
+    let unchanged_from = old.len() - suffix;
+    let shift = new.len() as isize - old.len() as isize;
+
+    // A step also looks at the token after its own, which must be unchanged as well
+    let kept = previous.steps.iter().take_while(|step| step.tokens.end < prefix).count();
+    let (nodes, errors, recoveries, stray_setters) = made_by(&previous.steps[..kept]);
+    parser.index = previous.steps[..kept].last().map_or(0, |step| step.tokens.end);
+    parser.errors = previous.errors[..errors].to_vec();
+    parser.recoveries = previous.recoveries[..recoveries].to_vec();
+    parser.stray_setters = previous.stray_setters[..stray_setters].to_vec();
+    let mut nodes = previous.nodes[..nodes].to_vec();
+    let mut steps = previous.steps[..kept].to_vec();
+
+    let mut resumed = None;
+    let mut candidate = kept;
+    while parser.peek().is_some() {
+        let index = parser.index.wrapping_add_signed(-shift);
+        while previous.steps.get(candidate).is_some_and(|step| step.tokens.start < index) {
+            candidate += 1;
+        }
+        if previous.steps.get(candidate).is_some_and(|step| step.tokens.start == index && index >= unchanged_from) {
+            resumed = Some(candidate);
+            break;
+        }
+        parser.step(&mut nodes, &mut steps);
+    }
+
+    if let Some(resumed) = resumed {
+        let (node_count, errors, recoveries, stray_setters) = made_by(&previous.steps[..resumed]);
+        nodes.extend(previous.nodes[node_count..].iter().cloned().map(|mut node| {
+            shift_node(&mut node, delta);
+            node
+        }));
+        parser.errors.extend(previous.errors[errors..].iter().map(|error| ParseError {
+            message: error.message.clone(),
+            range: shifted(&error.range, delta)
+        }));
+        parser.recoveries.extend(previous.recoveries[recoveries..].iter().map(|recovery| match recovery {
+            Recovery::Skipped(range) => Recovery::Skipped(shifted(range, delta)),
+            Recovery::Inserted(text, offset) => Recovery::Inserted(text, offset.wrapping_add_signed(delta))
+        }));
+        parser.stray_setters.extend(previous.stray_setters[stray_setters..].iter().map(|range| shifted(range, delta)));
+        steps.extend(previous.steps[resumed..].iter().map(|step| Step {
+            tokens: shifted(&step.tokens, shift),
+            ..step.clone()
+        }));
+    }
+
+    Ast {
+        nodes,
+        errors: parser.errors,
+        recoveries: parser.recoveries,
+        stray_setters: parser.stray_setters,
+        steps
+    }
+}
+
+// Searching
+
+pub fn setter_at(ast: &Ast, offset: usize) -> Option<(&Object, &Setter)> {
+    fn search(nodes: &[Node], offset: usize) -> Option<(&Object, &Setter)> {
+        let node = nodes.iter().find(|node| node.range().contains(&offset))?;
+        if let Node::Object(object) = node {
+            if let Some(setter) = object.setters.iter().find(|setter| setter.range.contains(&offset)) {
+                return Some((object, setter));
+            }
+        }
+        search(node.children(), offset)
+    }
+    search(&ast.nodes, offset)
+}
+
+pub fn directive_at(ast: &Ast, offset: usize) -> Option<&Directive> {
+    ast.nodes.iter().find_map(|node| match node {
+        Node::Directive(directive) if directive.range.contains(&offset) => Some(directive),
+        _ => None
+    })
+}
+
+// A cursor right after the last character still counts as being on a name
+fn touches(range: &Range<usize>, offset: usize) -> bool {
+    range.start <= offset && offset <= range.end
+}
+
+// The widget name under an offset: an object's, a definition's own, or one it inherits from
+pub fn widget_name_at(ast: &Ast, offset: usize) -> Option<&str> {
+    fn search(nodes: &[Node], offset: usize) -> Option<&str> {
+        for node in nodes.iter().filter(|node| touches(node.range(), offset)) {
+            let name = match node {
+                Node::Object(object) if touches(&object.name_range, offset) => Some(object.name.as_str()),
+                Node::Definition(definition) if touches(&definition.name_range, offset) => Some(definition.name.as_str()),
+                Node::Definition(definition) => definition.inherits
+                    .iter()
+                    .find(|(_, range)| touches(range, offset))
+                    .map(|(name, _)| name.as_str()),
+                _ => None
+            };
+            if let Some(name) = name.or_else(|| search(node.children(), offset)) {
+                return Some(name);
+            }
+        }
+        None
+    }
+    search(&ast.nodes, offset)
+}
+
+pub fn definitions<'a>(ast: &'a Ast, name: &'a str) -> impl Iterator<Item = &'a Definition> {
+    ast.nodes.iter().filter_map(move |node| match node {
+        Node::Definition(definition) if definition.name == name => Some(definition),
+        _ => None
+    })
+}
+
+// The innermost object or definition around an offset. A widget's range runs from its name
+// to its last setter, so its braces and the setters after them are part of it, while the
+// space between two top-level widgets is part of neither
+pub fn enclosing_definition(ast: &Ast, byte: usize) -> Option<&Node> {
+    fn search(nodes: &[Node], byte: usize) -> Option<&Node> {
+        let node = nodes.iter().find(|node| node.range().contains(&byte))?;
+        search(node.children(), byte).or(match node {
+            Node::Object(_) | Node::Definition(_) => Some(node),
+            _ => None
+        })
+    }
+    search(&ast.nodes, byte)
+}
+
+// For when the tree can't be trusted: the name of the widget whose block is the innermost one
+// still open at `byte`, going by the braces alone. Braces belong to the block they open or close
+pub fn enclosing_name(tokens: &[Token], byte: usize) -> Option<&str> {
+    let tokens: Vec<&Token> = tokens.iter().filter(|token| !matches!(token.value, TokenValue::Comment)).collect();
+    let mut open = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let opens_here = token.range.start == byte && matches!(token.value, TokenValue::StartBlock);
+        if token.range.start >= byte && !opens_here {
+            break;
+        }
+        match token.value {
+            TokenValue::StartBlock => open.push(index),
+            TokenValue::EndBlock => {
+                open.pop();
+            },
+            _ => ()
+        }
+    }
+
+    // Back from the brace, past any arguments and the parents of a definition
+    let mut index = open.pop()?.checked_sub(1)?;
+    if let TokenValue::EndArgList = tokens[index].value {
+        index = tokens[..index].iter().rposition(|token| matches!(token.value, TokenValue::StartArgList))?.checked_sub(1)?;
+    }
+    if let TokenValue::Inherits = tokens[index].value {
+        index = index.checked_sub(1)?;
+    } else if index > 0 && matches!(tokens[index - 1].value, TokenValue::Inherits) {
+        index = index.checked_sub(2)?;
+    }
+    match &tokens[index].value {
+        TokenValue::Definition(TokenDefinitionType::Object(name)) => Some(name),
+        TokenValue::Identifier(TokenIdentifierType::Generic(name)) => Some(name),
+        _ => None
+    }
+}
+
+pub fn objects(ast: &Ast) -> Vec<&Object> {
+    objects_in(&ast.nodes)
+}
+
+pub fn objects_in(nodes: &[Node]) -> Vec<&Object> {
+    fn collect<'a>(nodes: &'a [Node], objects: &mut Vec<&'a Object>) {
+        for node in nodes {
+            if let Node::Object(object) = node {
+                objects.push(object);
+            }
+            collect(node.children(), objects);
+        }
+    }
+    let mut objects = Vec::new();
+    collect(nodes, &mut objects);
+    objects
+}
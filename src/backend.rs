@@ -0,0 +1,2294 @@
+use serde_json::Value;
+use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
+use dashmap::{DashMap, DashSet};
+use ropey::Rope;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use gtk_ui::lexer::{DirectiveType, Token, TokenValue, TypeIdentifierType};
+use crate::{
+    ast,
+    code_action,
+    completion,
+    database,
+    diagnostics,
+    extensions,
+    folding,
+    formatter,
+    index_cache,
+    inlay_hints,
+    lexer,
+    notebook,
+    partial,
+    position,
+    references,
+    rename,
+    semantic_tokens,
+    settings,
+    symbols,
+    toggle,
+    trace,
+    trivia,
+    workspace
+};
+
+use ast::{Ast, Node};
+use lexer::LexOptions;
+use position::PositionEncoding;
+use database::WidgetDb;
+use settings::{CustomWidget, CustomWidgets, DiagnosticTrigger, InlayHintSettings, LogLevel, Settings};
+
+const SEMANTIC_TOKENS_REGISTRATION_ID: &str = "semantic-tokens";
+const WATCHED_FILES_REGISTRATION_ID: &str = "watched-files";
+
+// How long a document has to stay unchanged before its tree goes out in `gtkui/astChanged`
+const AST_CHANGED_DELAY: std::time::Duration = std::time::Duration::from_millis(150);
+
+// How long a document has to stay unchanged before it's validated with `onType`
+const DIAGNOSTICS_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+fn semantic_tokens_registration_options() -> SemanticTokensRegistrationOptions {
+    SemanticTokensRegistrationOptions {
+        text_document_registration_options: {
+            TextDocumentRegistrationOptions {
+                document_selector: Some(vec![
+                    DocumentFilter {
+                        language: Some("gui".to_string()),
+                        scheme: Some("file".to_string()),
+                        pattern: None,
+                    },
+                    DocumentFilter {
+                        language: Some("gui".to_string()),
+                        scheme: Some("vscode-notebook-cell".to_string()),
+                        pattern: None,
+                    }
+                ]),
+            }
+        },
+        semantic_tokens_options: SemanticTokensOptions {
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+            legend: SemanticTokensLegend {
+                token_types: semantic_tokens::LEGEND_TYPE.into(),
+                token_modifiers: vec![],
+            },
+            range: Some(true),
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+        },
+        static_registration_options: StaticRegistrationOptions::default(),
+    }
+}
+
+#[derive(Debug)]
+pub struct Backend {
+    client: Client,
+    document_map: DashMap<String, Rope>,
+    token_map: DashMap<String, Vec<Token>>,
+    ast_map: DashMap<String, Ast>,
+    semantic_token_map: DashMap<String, SemanticTokens>,
+    diagnostic_map: DashMap<String, diagnostics::DiagnosticCache>,
+    semantic_encoding_map: DashMap<String, Vec<SemanticToken>>,
+    // Computed on the first request after each change, since editors ask again on all sorts
+    // of events
+    folding_range_map: DashMap<String, Vec<FoldingRange>>,
+    document_symbol_map: DashMap<String, Vec<DocumentSymbol>>,
+    // Documents whose last lex failed outright, so the user is only told once
+    lex_failures: DashSet<String>,
+    // What the last lex of each document couldn't read, made into diagnostics as it's lexed
+    lex_diagnostics: DashMap<String, Vec<Diagnostic>>,
+    // Documents over `maxFileSizeKb`, which are stored as text only
+    oversized: DashSet<String>,
+    // Each document with every change so far applied, and the version that made it. The maps
+    // above catch up once it's lexed, unless a newer version has come in by then
+    edited: DashMap<String, (i32, Rope)>,
+    // Parsed `.gui` files on disk, from `#include`s and workspace indexing. These rarely
+    // change while the server runs
+    include_map: DashMap<PathBuf, Arc<Ast>>,
+    // How much each file uses each widget and property, by path where it has one, and the
+    // sum of them all. Kept up to date as files change, so completion only has to look
+    file_usage: DashMap<String, FileUsage>,
+    usage: RwLock<completion::Usage>,
+    workspace_roots: RwLock<Vec<PathBuf>>,
+    settings: RwLock<Settings>,
+    // What the client has for each workspace folder, when it can scope its settings. Files
+    // outside every folder use `settings`
+    root_settings: RwLock<Vec<(PathBuf, Settings)>>,
+    // From the `customWidgets` setting, read again whenever the settings change
+    custom_widgets: RwLock<BTreeMap<String, CustomWidget>>,
+    work_done_progress: AtomicBool,
+    // Whether semantic tokens are registered at runtime rather than in `initialize`
+    dynamic_semantic_tokens: AtomicBool,
+    semantic_tokens_registered: AtomicBool,
+    hierarchical_document_symbols: AtomicBool,
+    dynamic_watched_files: AtomicBool,
+    inlay_hint_refresh: AtomicBool,
+    semantic_tokens_refresh: AtomicBool,
+    // Escape sequences are only highlighted on top of their strings where that can be shown
+    overlapping_semantic_tokens: AtomicBool,
+    // What the client's columns count, agreed on in `initialize`
+    position_encoding: RwLock<PositionEncoding>,
+    configuration_requests: AtomicBool,
+    ast_notifications: AtomicBool,
+    // The pending `gtkui/astChanged` of each document. A newer parse aborts it
+    ast_changed_tasks: DashMap<String, JoinHandle<()>>,
+    // Cancelled by `shutdown`, after which nothing more is sent to the client
+    shut_down: CancellationToken,
+    // Held for reading by everything running in `background`, so `shutdown` can wait for it
+    background: tokio::sync::RwLock<()>,
+    // Bumped on every change, so only the last of a burst of changes gets validated
+    diagnostic_generations: DashMap<String, u64>,
+    // Shared with the `Traced` wrapped around the service, which does the tracing
+    tracer: Arc<trace::Tracer>
+}
+
+// A file counts through its open document while there is one, or else through its indexed copy
+#[derive(Debug, Default)]
+struct FileUsage {
+    open: Option<completion::Usage>,
+    indexed: Option<completion::Usage>
+}
+
+impl FileUsage {
+    fn counted(&self) -> Option<&completion::Usage> {
+        self.open.as_ref().or(self.indexed.as_ref())
+    }
+}
+
+fn usage_key(uri: &Url) -> String {
+    match uri.to_file_path() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => uri.to_string()
+    }
+}
+
+// The files the includes of `ast` point to, of those that exist
+fn resolved_includes(ast: &Ast, document: Option<&Path>, bases: &[PathBuf]) -> Vec<PathBuf> {
+    ast.nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Directive(ast::Directive { directive_type: DirectiveType::Include, argument: Some((path, _)), .. }) => {
+                database::resolve_include(document, path, bases)
+            },
+            _ => None
+        })
+        .collect()
+}
+
+struct PulledInputs {
+    // The global settings' first, then each workspace folder's
+    inlay_hints: Vec<InlayHintSettings>,
+    semantic_tokens: bool,
+    semantic_token_types: BTreeMap<String, String>,
+    custom_widgets: BTreeMap<String, CustomWidget>
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(options) = params.initialization_options {
+            let settings = self.read_settings(options).await;
+            *self.settings.write().unwrap() = settings;
+        }
+        if let Some(trace) = params.trace {
+            self.tracer.set(trace);
+        }
+        let dynamic_semantic_tokens = params.capabilities.text_document
+            .as_ref()
+            .and_then(|text_document| text_document.semantic_tokens.as_ref())
+            .and_then(|semantic_tokens| semantic_tokens.dynamic_registration)
+            .unwrap_or(false);
+        self.dynamic_semantic_tokens.store(dynamic_semantic_tokens, Ordering::SeqCst);
+        let hierarchical_document_symbols = params.capabilities.text_document
+            .as_ref()
+            .and_then(|text_document| text_document.document_symbol.as_ref())
+            .and_then(|document_symbol| document_symbol.hierarchical_document_symbol_support)
+            .unwrap_or(false);
+        self.hierarchical_document_symbols.store(hierarchical_document_symbols, Ordering::SeqCst);
+        let work_done_progress = params.capabilities.window
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        self.work_done_progress.store(work_done_progress, Ordering::SeqCst);
+        let dynamic_watched_files = params.capabilities.workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files)
+            .and_then(|watched_files| watched_files.dynamic_registration)
+            .unwrap_or(false);
+        self.dynamic_watched_files.store(dynamic_watched_files, Ordering::SeqCst);
+        let inlay_hint_refresh = params.capabilities.workspace
+            .as_ref()
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            .unwrap_or(false);
+        self.inlay_hint_refresh.store(inlay_hint_refresh, Ordering::SeqCst);
+        let semantic_tokens_refresh = params.capabilities.workspace
+            .as_ref()
+            .and_then(|workspace| workspace.semantic_tokens.as_ref())
+            .and_then(|semantic_tokens| semantic_tokens.refresh_support)
+            .unwrap_or(false);
+        self.semantic_tokens_refresh.store(semantic_tokens_refresh, Ordering::SeqCst);
+        let overlapping_semantic_tokens = params.capabilities.text_document
+            .as_ref()
+            .and_then(|text_document| text_document.semantic_tokens.as_ref())
+            .and_then(|semantic_tokens| semantic_tokens.overlapping_token_support)
+            .unwrap_or(false);
+        self.overlapping_semantic_tokens.store(overlapping_semantic_tokens, Ordering::SeqCst);
+        let position_encoding = PositionEncoding::negotiate(params.capabilities.general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref()));
+        *self.position_encoding.write().unwrap() = position_encoding;
+        let configuration_requests = params.capabilities.workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false);
+        self.configuration_requests.store(configuration_requests, Ordering::SeqCst);
+        let ast_notifications = params.capabilities.experimental
+            .as_ref()
+            .and_then(|experimental| experimental.get("gtkuiAstChanged"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        self.ast_notifications.store(ast_notifications, Ordering::SeqCst);
+
+        // Clients that predate workspace folders only send a single root
+        #[allow(deprecated)]
+        let roots: Vec<PathBuf> = match params.workspace_folders {
+            Some(folders) if !folders.is_empty() => folders
+                .into_iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect(),
+            _ => params.root_uri
+                .and_then(|uri| uri.to_file_path().ok())
+                .or_else(|| params.root_path.map(PathBuf::from))
+                .into_iter()
+                .collect()
+        };
+        *self.workspace_roots.write().unwrap() = roots;
+        // Before any document is opened, so its first diagnostics already know them
+        self.load_custom_widgets().await;
+
+        // Capabilities are fixed after this, so if the client can't register them later
+        // the setting only takes effect on restart
+        let semantic_tokens_provider = if !dynamic_semantic_tokens && self.settings.read().unwrap().semantic_tokens {
+            Some(SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
+                semantic_tokens_registration_options()
+            ))
+        } else {
+            None
+        };
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: completion::trigger_characters(self.settings.read().unwrap().completion.auto_trigger),
+                    ..Default::default()
+                }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        code_action::EXTRACT_TO_FILE.to_string(),
+                        code_action::WRAP_IN_WIDGET.to_string(),
+                        rename::RENAME_PROPERTY.to_string(),
+                        toggle::TOGGLE_BOOLEAN.to_string(),
+                        toggle::TOGGLE_COMMENT.to_string()
+                    ],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(InlayHintOptions {
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                    resolve_provider: Some(true)
+                }))),
+                code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                    code_action_kinds: Some(vec![CodeActionKind::QUICKFIX, CodeActionKind::REFACTOR, CodeActionKind::REFACTOR_EXTRACT]),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                    resolve_provider: None,
+                })),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::INCREMENTAL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..Default::default()
+                })),
+                semantic_tokens_provider,
+                position_encoding: Some(position_encoding.kind()),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.log(LogLevel::Info, "server initialized!").await;
+        self.warn_about_token_types().await;
+        self.update_semantic_tokens_registration().await;
+        self.register_file_watcher().await;
+        if self.fetch_root_settings().await {
+            self.republish_diagnostics().await;
+        }
+        let roots = self.workspace_roots.read().unwrap().clone();
+        self.index_workspace(roots).await;
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let removed: Vec<PathBuf> = params.event.removed
+            .into_iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect();
+        let added: Vec<PathBuf> = {
+            let mut roots = self.workspace_roots.write().unwrap();
+            roots.retain(|root| !removed.contains(root));
+            let added: Vec<PathBuf> = params.event.added
+                .into_iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .filter(|root| !roots.contains(root))
+                .collect();
+            roots.extend(added.iter().cloned());
+            added
+        };
+        // Files that are still included somewhere are simply parsed again when needed
+        self.include_map.retain(|path, _| !removed.iter().any(|root| path.starts_with(root)));
+        self.recount_indexed_usage();
+        self.load_open_includes().await;
+        let pulled = self.pulled_inputs();
+        if self.fetch_root_settings().await {
+            self.refresh_pulled(pulled).await;
+            self.republish_diagnostics().await;
+        }
+        self.index_workspace(added).await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let pulled = self.pulled_inputs();
+        // Clients that expect to be asked for the settings only say that they changed
+        if params.settings.is_null() {
+            if self.fetch_root_settings().await {
+                self.refresh_pulled(pulled).await;
+                self.republish_diagnostics().await;
+            }
+            return;
+        }
+        let settings = self.read_settings(params.settings).await;
+        let exclude_changed = settings.exclude != self.settings.read().unwrap().exclude;
+        *self.settings.write().unwrap() = settings;
+        self.fetch_root_settings().await;
+        self.warn_about_token_types().await;
+        self.update_semantic_tokens_registration().await;
+        self.load_custom_widgets().await;
+        self.refresh_pulled(pulled).await;
+
+        if exclude_changed {
+            let exclusions = self.exclusions();
+            self.include_map.retain(|path, _| !exclusions.matches(path));
+            self.recount_indexed_usage();
+            let roots = self.workspace_roots.read().unwrap().clone();
+            self.index_workspace(roots).await;
+        }
+        self.republish_diagnostics().await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let exclusions = self.exclusions();
+        let mut changed = false;
+        for event in params.changes {
+            let path = match event.uri.to_file_path() {
+                Ok(path) if workspace::is_gui_file(&path) => path,
+                _ => continue
+            };
+            // Excluded files are still kept up to date when something includes them
+            if event.typ == FileChangeType::DELETED {
+                changed |= self.include_map.remove(&path).is_some();
+                self.update_indexed_usage(&path, None, &exclusions);
+            } else if !exclusions.matches(&path) || self.include_map.contains_key(&path) {
+                let ast = tokio::task::spawn_blocking({
+                    let path = path.clone();
+                    move || workspace::index_file(&path)
+                }).await.ok().flatten();
+                if let Some(ast) = ast {
+                    self.update_indexed_usage(&path, Some(&ast), &exclusions);
+                    self.load_includes(&ast, Some(&path)).await;
+                    self.include_map.insert(path, Arc::new(ast));
+                    changed = true;
+                }
+            }
+        }
+        // Open documents may include what changed
+        if changed {
+            self.republish_diagnostics().await;
+        }
+    }
+
+    // Background work is stopped and waited for, so nothing reaches the client after this
+    async fn shutdown(&self) -> Result<()> {
+        self.shut_down.cancel();
+        let keys: Vec<String> = self.ast_changed_tasks.iter().map(|entry| entry.key().clone()).collect();
+        for key in keys {
+            if let Some((_, task)) = self.ast_changed_tasks.remove(&key) {
+                task.abort();
+                let _ = task.await;
+            }
+        }
+        // Only free once everything in `background` has been dropped
+        drop(self.background.write().await);
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let (uri, version) = (params.text_document.uri.clone(), params.text_document.version);
+        // Before anything is awaited, so changes that follow right away apply to this text
+        self.edited.insert(uri.to_string(), (version, Rope::from_str(&params.text_document.text)));
+        self.log(LogLevel::Debug, "file opened!").await;
+        self.on_change(TextDocumentItem {
+            uri: params.text_document.uri,
+            text: params.text_document.text,
+            version: params.text_document.version,
+        })
+        .await;
+        // Even with `onSave`, what's on disk is worth checking right away
+        self.publish_diagnostics(uri, Some(version)).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let (uri, version) = (params.text_document.uri.clone(), params.text_document.version);
+        let encoding = self.position_encoding();
+        // Each change is made to the text as the ones before it left it, those of an earlier
+        // notification included. Nothing is awaited while the entry is held, so two that are
+        // handled at once can't both start from the same text
+        let (text, ignored) = {
+            let mut edited = self.edited.entry(uri.to_string()).or_insert_with(|| {
+                (version, self.document_map.get(uri.as_str()).map(|rope| rope.clone()).unwrap_or_default())
+            });
+            let ignored: Vec<_> = params.content_changes
+                .iter()
+                .filter(|change| position::apply_change(&mut edited.1, change, encoding).is_none())
+                .map(|change| change.range)
+                .collect();
+            edited.0 = version;
+            (edited.1.to_string(), ignored)
+        };
+        for range in ignored {
+            self.log(LogLevel::Warning, format!("Ignored a change to {} outside the document: {:?}", uri, range)).await;
+        }
+        self.on_change(TextDocumentItem {
+            uri: params.text_document.uri,
+            text,
+            version: params.text_document.version,
+        })
+        .await;
+        if self.settings_for(&uri).diagnostics.trigger == DiagnosticTrigger::OnType {
+            self.publish_diagnostics_debounced(uri, version).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.close_document(params.text_document.uri).await;
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if self.settings_for(&params.text_document.uri).diagnostics.trigger == DiagnosticTrigger::OnSave {
+            self.publish_diagnostics(params.text_document.uri, None).await;
+        }
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let (auto_trigger, max_items, rank_by_usage) = {
+            let settings = self.settings.read().unwrap();
+            (settings.completion.auto_trigger, settings.completion.max_items, settings.completion.rank_by_usage)
+        };
+        if !completion::accepts(auto_trigger, params.context.map(|context| context.trigger_kind)) {
+            return Ok(None);
+        }
+        let position = params.text_document_position;
+        match self.literal_at(&position.text_document.uri, position.position) {
+            Some(completion::Literal::Comment) => return Ok(None),
+            Some(completion::Literal::String(range)) => {
+                return Ok(self.string_completions(&position.text_document.uri, position.position, range).map(CompletionResponse::Array));
+            },
+            None => ()
+        }
+        let uri = position.text_document.uri;
+        let encoding = self.position_encoding();
+        let prefix = self.document_map
+            .get(&uri.to_string())
+            .and_then(|rope| completion::line_prefix(&rope, position.position, encoding));
+        // Counted before any map is borrowed, and only where widgets or properties are offered
+        let usage = match prefix.as_deref().map(completion::context) {
+            Some(completion::CompletionContext::Setter | completion::CompletionContext::Statement) if rank_by_usage => self.usage(),
+            _ => completion::Usage::default()
+        };
+        let items = || -> Option<CompletionResponse> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let tokens = self.token_map.get(&uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, position.position, encoding)?;
+            match prefix.as_deref().map(completion::context)? {
+                completion::CompletionContext::Directive => {
+                    let name = completion::directive_name_range(&rope, offset)?;
+                    let has_argument = rope.byte_slice(name.end..).chars().find(|c| *c != ' ' && *c != '\t') == Some('"');
+                    Some(CompletionResponse::Array(completion::directive_completions(position::range_to_lsp_in(&rope, &name, encoding)?, has_argument)))
+                },
+                completion::CompletionContext::Setter => {
+                    let db = self.widget_db(&uri);
+                    let ast = self.ast_map.get(&uri.to_string())?;
+                    // The setter being typed ends at the cursor, and belongs to the widget around it
+                    let object = match ast::enclosing_definition(&ast, offset.checked_sub(1)?)? {
+                        Node::Object(object) => object,
+                        _ => return None
+                    };
+                    let name = completion::name_range(&tokens, offset);
+                    let has_arguments = lexer::token_at(&tokens, name.end)
+                        .is_some_and(|token| token.range.start == name.end && matches!(token.value, TokenValue::StartArgList));
+                    let range = position::range_to_lsp_in(&rope, &name, encoding)?;
+                    let set = completion::already_set(object, offset);
+                    Some(CompletionResponse::Array(completion::setter_completions(&db, &object.name, range, has_arguments, &set, &usage)))
+                },
+                completion::CompletionContext::Argument(property) => {
+                    let db = self.widget_db(&uri);
+                    let ast = self.ast_map.get(&uri.to_string())?;
+                    let (object, _) = ast::setter_at(&ast, offset.checked_sub(1)?)?;
+                    let (_, info) = db.property(&object.name, &property)?;
+                    if !matches!(info.internal_type, Some(TypeIdentifierType::Bool)) {
+                        return None;
+                    }
+                    let range = position::range_to_lsp_in(&rope, &completion::name_range(&tokens, offset), encoding)?;
+                    Some(CompletionResponse::Array(completion::bool_completions(db.default_value(&object.name, &property), range)))
+                },
+                completion::CompletionContext::Unit(property) => {
+                    let db = self.widget_db(&uri);
+                    let ast = self.ast_map.get(&uri.to_string())?;
+                    // Looked up at the number, since a unit isn't part of the setter as parsed
+                    let unit = completion::name_range(&tokens, offset);
+                    let (object, _) = ast::setter_at(&ast, unit.start.checked_sub(1)?)?;
+                    let units = db.dimension_units(&object.name, &property)?;
+                    Some(CompletionResponse::Array(completion::unit_completions(units, position::range_to_lsp_in(&rope, &unit, encoding)?)))
+                },
+                completion::CompletionContext::Statement if ast::enclosing_name(&tokens, offset).is_some() => {
+                    let db = self.widget_db(&uri);
+                    let ast = self.ast_map.get(&uri.to_string())?;
+                    let name = completion::name_range(&tokens, offset);
+                    let range = position::range_to_lsp_in(&rope, &name, encoding)?;
+                    let mut items = completion::child_completions(&db, range, &usage);
+                    // A setter here would belong to the last child that ends before it
+                    let previous = name.start
+                        .checked_sub(1)
+                        .and_then(|before| ast::enclosing_definition(&ast, before))
+                        .map(|node| node.children())
+                        .and_then(|children| children.iter().rev().find(|child| child.range().end <= name.start));
+                    if let Some(Node::Object(object)) = previous {
+                        items.extend(completion::trailing_setter_completions(&db, &object.name, range, &completion::already_set(object, offset), &usage));
+                    }
+                    // Every widget the document can see is a lot once libraries are included
+                    let typed = rope.get_byte_slice(name.start..offset.max(name.start))?.to_string();
+                    Some(completion::capped(items, &typed, max_items))
+                },
+                // Outside of any block, or somewhere nothing can be written, there's nothing to offer
+                completion::CompletionContext::Statement | completion::CompletionContext::Other => None
+            }
+        }();
+        Ok(items)
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let db = self.widget_db(&uri);
+        let encoding = self.position_encoding();
+        let hover = || -> Option<Hover> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, params.text_document_position_params.position, encoding)?;
+            let tokens = self.token_map.get(&uri.to_string())?;
+            let token = lexer::token_at(&tokens, offset);
+            if let Some(Token { value: TokenValue::Comment, .. }) = token {
+                return None;
+            }
+            // Underlines exactly the token being described, which matters when tokens touch
+            let range = token.and_then(|token| position::range_to_lsp_in(&rope, &token.range, encoding));
+            if let Some(ast::Directive { directive_type: DirectiveType::Include, argument: Some((path, _)), .. }) = ast::directive_at(&ast, offset) {
+                return Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: self.include_hover(&uri, path),
+                    }),
+                    range,
+                });
+            }
+            if let Some((value, unit, dimension)) = lexer::dimension_at(&tokens, offset) {
+                let written = rope.get_byte_slice(dimension.clone())?.to_string();
+                let contents = match database::unit_conversions(value, unit) {
+                    Some(conversions) => format!(
+                        "**{}**\n\n{}\n\nAt the default scale, with 16px text.",
+                        written,
+                        conversions.iter().map(|conversion| format!("- `{}`", conversion)).collect::<Vec<_>>().join("\n")
+                    ),
+                    None => format!("**{}**\n\n`{}` depends on context this file doesn't give, so it has no fixed size in `px`.", written, unit)
+                };
+                return Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: contents,
+                    }),
+                    range: position::range_to_lsp_in(&rope, &dimension, encoding),
+                });
+            }
+            if let Some(name) = ast::widget_name_at(&ast, offset) {
+                let mut contents = format!("**{}**", name);
+                if let Some(documented) = db.documented_widget(name) {
+                    if documented != name {
+                        contents.push_str(&format!("\n\nA `{}`", documented));
+                    }
+                    let template = self.settings_for(&uri).hover.docs_url_template;
+                    contents.push_str(&format!("\n\n[See documentation]({})", database::docs_url(&template, documented)));
+                }
+                return Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: contents,
+                    }),
+                    range,
+                });
+            }
+            let (object, setter) = ast::setter_at(&ast, offset)?;
+
+            let value = setter.value.as_ref().and_then(database::literal)?;
+            let mut contents = format!("**{}**: `{}`", setter.name, value);
+            let default = db.default_value(&object.name, &setter.name);
+            if let Some(default) = default {
+                contents.push_str(&format!(" (default: `{}`)", default));
+            }
+            if let Some((widget, property)) = db.property(&object.name, &setter.name) {
+                let internal_type = property.internal_type.as_ref().map(database::type_name).unwrap_or("unknown");
+                contents.push_str(&format!("\n\n{} property of `{}`", internal_type, widget.name));
+            }
+            if let Some(availability) = db.availability(&object.name, &setter.name) {
+                contents.push_str(&format!("\n\n{}", availability.describe(self.settings_for(&uri).gtk_version())));
+            }
+            if default == Some(value.as_str()) {
+                contents.push_str("\n\nThis is already the default value, so the setter can be removed.");
+            }
+
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: contents,
+                }),
+                range,
+            })
+        }();
+        Ok(hover)
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        self.load_document_includes(&uri).await;
+        let db = self.widget_db(&uri);
+        let encoding = self.position_encoding();
+        let found = || -> Option<(Vec<Location>, Vec<(PathBuf, Vec<std::ops::Range<usize>>)>)> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, params.text_document_position_params.position, encoding)?;
+            // Either a widget's name, or the value of a property that names one
+            let name = match ast::widget_name_at(&ast, offset) {
+                Some(name) => name,
+                None => match ast::setter_at(&ast, offset)? {
+                    (object, ast::Setter { name, value: Some(Token { value: TokenValue::String(id), range }), .. })
+                        if range.contains(&offset) && db.is_id_reference(&object.name, name) => id.as_str(),
+                    _ => return None
+                }
+            };
+
+            // Every definition is returned while a name may still be defined more than once
+            let locations: Vec<Location> = ast::definitions(&ast, name)
+                .filter_map(|definition| Some(Location::new(uri.clone(), position::range_to_lsp_in(&rope, &definition.name_range, encoding)?)))
+                .collect();
+            let document = uri.to_file_path().ok();
+            let mut files = Vec::new();
+            let bases = self.include_bases(document.as_deref());
+            self.included_files(&ast, document.as_deref(), &bases, &mut HashSet::new(), &mut files);
+            // Where the included files define it, as byte ranges until the files are read
+            let included = files
+                .iter()
+                .map(|(path, included)| (path.clone(), ast::definitions(included, name).map(|definition| definition.name_range.clone()).collect::<Vec<_>>()))
+                .filter(|(_, ranges)| !ranges.is_empty())
+                .collect::<Vec<_>>();
+            Some((locations, included))
+        }();
+        let (mut locations, included) = match found {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        for (path, ranges) in included {
+            let (file_uri, file_rope) = match (Url::from_file_path(&path), tokio::fs::read_to_string(&path).await) {
+                (Ok(file_uri), Ok(text)) => (file_uri, Rope::from_str(&text)),
+                _ => continue
+            };
+            locations.extend(ranges.iter().filter_map(|range| {
+                Some(Location::new(file_uri.clone(), position::range_to_lsp_in(&file_rope, range, encoding)?))
+            }));
+        }
+        Ok(Some(locations)
+            .filter(|locations| !locations.is_empty())
+            .map(GotoDefinitionResponse::Array))
+    }
+
+    // Uses of the widget name under the cursor in every open document and workspace file.
+    // Names aren't resolved through includes, so a name defined twice finds the uses of both
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let position = params.text_document_position;
+        let encoding = self.position_encoding();
+        let name = || -> Option<String> {
+            let rope = self.document_map.get(&position.text_document.uri.to_string())?;
+            let ast = self.ast_map.get(&position.text_document.uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, position.position, encoding)?;
+            ast::widget_name_at(&ast, offset).map(String::from)
+        }();
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None)
+        };
+        let include_declaration = params.context.include_declaration;
+        let mut results = partial::PartialResults::new(&self.client, params.partial_result_params.partial_result_token);
+        self.search_workspace(
+            &mut results,
+            |uri, rope, ast| references::locations(uri, rope, encoding, &references::name_uses(ast, &name), include_declaration),
+            |ast| !references::name_uses(ast, &name).is_empty()
+        ).await;
+        Ok(Some(results.finish().await))
+    }
+
+    async fn document_highlight(&self, params: DocumentHighlightParams) -> Result<Option<Vec<DocumentHighlight>>> {
+        let position = params.text_document_position_params;
+        let encoding = self.position_encoding();
+        let highlights = || -> Option<Vec<DocumentHighlight>> {
+            let rope = self.document_map.get(&position.text_document.uri.to_string())?;
+            let ast = self.ast_map.get(&position.text_document.uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, position.position, encoding)?;
+            let name = ast::widget_name_at(&ast, offset)?;
+            Some(references::highlights(&rope, encoding, &references::name_uses(&ast, name)))
+        }();
+        let highlights = match highlights {
+            Some(highlights) => highlights,
+            None => return Ok(None)
+        };
+        let mut results = partial::PartialResults::new(&self.client, params.partial_result_params.partial_result_token);
+        results.extend(highlights).await;
+        Ok(Some(results.finish().await))
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        let query = params.query;
+        let encoding = self.position_encoding();
+        let mut results = partial::PartialResults::new(&self.client, params.partial_result_params.partial_result_token);
+        self.search_workspace(
+            &mut results,
+            |uri, rope, ast| references::workspace_symbols(uri, rope, encoding, ast, &query),
+            |ast| references::has_matching_definition(ast, &query)
+        ).await;
+        Ok(Some(results.finish().await))
+    }
+
+    // The other way from go to definition: every definition in the workspace that inherits,
+    // directly or not, from the one under the cursor
+    async fn goto_implementation(&self, params: request::GotoImplementationParams) -> Result<Option<request::GotoImplementationResponse>> {
+        let position = params.text_document_position_params;
+        let encoding = self.position_encoding();
+        let base = || -> Option<String> {
+            let rope = self.document_map.get(&position.text_document.uri.to_string())?;
+            let ast = self.ast_map.get(&position.text_document.uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, position.position, encoding)?;
+            ast::widget_name_at(&ast, offset).map(String::from)
+        }();
+        let base = match base {
+            Some(base) => base,
+            None => return Ok(None)
+        };
+
+        // The whole hierarchy is needed before anything can be found, but only the trees are
+        let mut children = HashMap::new();
+        let mut open = HashSet::new();
+        for entry in self.ast_map.iter() {
+            if let Some(path) = Url::parse(entry.key()).ok().and_then(|uri| uri.to_file_path().ok()) {
+                open.insert(path);
+            }
+            references::add_children(entry.value(), &mut children);
+        }
+        for (_, ast) in self.indexed_workspace_files(&open) {
+            references::add_children(&ast, &mut children);
+        }
+        let implementations = references::descendants(&children, &base);
+
+        let mut results = partial::PartialResults::new(&self.client, params.partial_result_params.partial_result_token);
+        self.search_workspace(
+            &mut results,
+            |uri, rope, ast| references::definition_locations(uri, rope, encoding, ast, &implementations),
+            |ast| ast.nodes.iter().any(|node| matches!(node, Node::Definition(definition) if implementations.contains(&definition.name)))
+        ).await;
+        Ok(Some(GotoDefinitionResponse::Array(results.finish().await)))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            code_action::EXTRACT_TO_FILE => self.extract_to_file(params.arguments).await,
+            code_action::WRAP_IN_WIDGET => self.wrap_in_widget(params.arguments).await,
+            rename::RENAME_PROPERTY => self.rename_property(params.arguments).await,
+            toggle::TOGGLE_BOOLEAN => self.toggle_boolean(params.arguments),
+            toggle::TOGGLE_COMMENT => self.toggle_comment(params.arguments),
+            command => Err(tower_lsp::jsonrpc::Error::invalid_params(format!("unknown command {}", command)))
+        }
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let symbols = || -> Option<DocumentSymbolResponse> {
+            let symbols = match self.document_symbol_map.get(&uri.to_string()) {
+                Some(symbols) => symbols.clone(),
+                None => {
+                    let rope = self.document_map.get(&uri.to_string())?;
+                    let ast = self.ast_map.get(&uri.to_string())?;
+                    let symbols = symbols::document_symbols(&rope, self.position_encoding(), &ast);
+                    self.document_symbol_map.insert(uri.to_string(), symbols.clone());
+                    symbols
+                }
+            };
+            if self.hierarchical_document_symbols.load(Ordering::SeqCst) {
+                Some(DocumentSymbolResponse::Nested(symbols))
+            } else {
+                Some(DocumentSymbolResponse::Flat(symbols::flatten(&uri, symbols)))
+            }
+        }();
+        Ok(symbols)
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri.to_string();
+        let ranges = || -> Option<Vec<FoldingRange>> {
+            if let Some(ranges) = self.folding_range_map.get(&uri) {
+                return Some(ranges.clone());
+            }
+            let rope = self.document_map.get(&uri)?;
+            let ast = self.ast_map.get(&uri)?;
+            let tokens = self.token_map.get(&uri)?;
+            // Blocks can't be trusted while the document doesn't parse
+            let mut ranges = if ast.errors.is_empty() {
+                folding::ast_folding(&rope, &ast)
+            } else {
+                folding::indentation_folding(&rope)
+            };
+            ranges.extend(folding::comment_folding(&rope, &tokens));
+            self.folding_range_map.insert(uri.clone(), ranges.clone());
+            Some(ranges)
+        }();
+        Ok(ranges)
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        if !self.settings.read().unwrap().semantic_tokens {
+            return Ok(None);
+        }
+        let uri = params.text_document.uri.to_string();
+        if let Some(semantic_token) = self.guarded("Encoding semantic tokens", || self.encoded_semantic_tokens(&uri)).await {
+            let semantic_tokens = SemanticTokens {
+                result_id: Some(semantic_tokens::semantic_tokens_result_id(&semantic_token)),
+                data: semantic_token,
+            };
+            // Kept so a later delta request can find the token set it is based on
+            self.semantic_token_map.insert(uri, semantic_tokens.clone());
+            return Ok(Some(SemanticTokensResult::Tokens(semantic_tokens)));
+        }
+        Ok(None)
+    }
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        if !self.settings.read().unwrap().semantic_tokens {
+            return Ok(None);
+        }
+        let uri = params.text_document.uri.to_string();
+        let sliced = self.guarded("Encoding semantic tokens", || {
+            Some(semantic_tokens::slice_semantic_tokens(&self.encoded_semantic_tokens(&uri)?, params.range))
+        }).await;
+        if let Some(semantic_token) = sliced {
+            return Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+                result_id: Some(semantic_tokens::semantic_tokens_result_id(&semantic_token)),
+                data: semantic_token,
+            })));
+        }
+        Ok(None)
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        if workspace::is_read_only(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let line_endings = self.settings_for(&params.text_document.uri).format.line_endings;
+        let uri = params.text_document.uri.to_string();
+        let encoding = self.position_encoding();
+        let edits = || -> Option<Vec<TextEdit>> {
+            let rope = self.document_map.get(&uri)?;
+            let text = rope.to_string();
+            // Comments have to be lexed so they can be written back out
+            let (tokens, errors) = lexer::lex_with(&text, LexOptions { include_trivia: true, allow_recovery: false }).ok()?;
+            // Anything the lexer couldn't read has no token and would be dropped
+            if !errors.is_empty() {
+                return None;
+            }
+            let line_ending = formatter::line_ending(&text, line_endings);
+            let formatted = formatter::format(&text, &tokens, &params.options, line_ending);
+            if formatted == text {
+                return Some(vec![]);
+            }
+            let end = position::offset_to_position_in(&rope, rope.len_bytes(), encoding)?;
+            Some(vec![TextEdit {
+                range: Range::new(Position::new(0, 0), end),
+                new_text: formatted,
+            }])
+        }();
+        Ok(edits)
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        if workspace::is_read_only(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let uri = params.text_document.uri.to_string();
+        let encoding = self.position_encoding();
+        let actions = || -> Option<CodeActionResponse> {
+            let rope = self.document_map.get(&uri)?;
+            let tokens = self.token_map.get(&uri)?;
+            let ast = self.ast_map.get(&uri)?;
+            let source = rope.to_string();
+            let selection = position::lsp_to_range_in(&rope, params.range, encoding)?;
+            let mut actions = Vec::new();
+            for diagnostic in &params.context.diagnostics {
+                if let Some(action) = code_action::remove_redundant_setter(&params.text_document.uri, &source, &rope, encoding, &ast, diagnostic) {
+                    actions.push(CodeActionOrCommand::CodeAction(action));
+                }
+                if let Some(action) = code_action::normalize_indentation(&params.text_document.uri, &source, diagnostic) {
+                    actions.push(CodeActionOrCommand::CodeAction(action));
+                }
+            }
+            if let Some(action) = code_action::convert_to_child(&params.text_document.uri, &source, &rope, encoding, &tokens, &ast, selection.clone()) {
+                actions.push(CodeActionOrCommand::CodeAction(action));
+            }
+            if let Some(action) = code_action::wrap_in_widget(&params.text_document.uri, &ast, selection.clone()) {
+                actions.push(CodeActionOrCommand::CodeAction(action));
+            }
+            if let Some(action) = code_action::collapse_to_line(&params.text_document.uri, &source, &rope, encoding, &tokens, &ast, selection.start) {
+                actions.push(CodeActionOrCommand::CodeAction(action));
+            }
+            if let Some(action) = code_action::extract_to_file(&params.text_document.uri, &ast, selection.start) {
+                actions.push(CodeActionOrCommand::CodeAction(action));
+            }
+            Some(actions)
+        }();
+        Ok(actions)
+    }
+}
+
+// What the server has made of an open document
+#[derive(Debug, Clone)]
+pub struct DocumentState {
+    pub text: Rope,
+    pub tokens: Vec<Token>,
+    pub ast: Ast
+}
+
+impl DocumentState {
+    // The tokens keep their comments, so nothing between them is lost
+    pub fn trivia(&self) -> trivia::TriviaMap {
+        trivia::TriviaMap::new(&self.text.to_string(), &self.tokens)
+    }
+}
+
+struct TextDocumentItem {
+    uri: Url,
+    text: String,
+    version: i32,
+}
+
+impl Backend {
+    pub fn new(client: Client) -> Self {
+        Self {
+            document_map: DashMap::new(),
+            token_map: DashMap::new(),
+            ast_map: DashMap::new(),
+            semantic_token_map: DashMap::new(),
+            diagnostic_map: DashMap::new(),
+            semantic_encoding_map: DashMap::new(),
+            folding_range_map: DashMap::new(),
+            document_symbol_map: DashMap::new(),
+            lex_failures: DashSet::new(),
+            lex_diagnostics: DashMap::new(),
+            oversized: DashSet::new(),
+            edited: DashMap::new(),
+            include_map: DashMap::new(),
+            file_usage: DashMap::new(),
+            usage: RwLock::new(completion::Usage::default()),
+            workspace_roots: RwLock::new(Vec::new()),
+            settings: RwLock::new(Settings::default()),
+            root_settings: RwLock::new(Vec::new()),
+            custom_widgets: RwLock::new(BTreeMap::new()),
+            work_done_progress: AtomicBool::new(false),
+            dynamic_semantic_tokens: AtomicBool::new(false),
+            semantic_tokens_registered: AtomicBool::new(false),
+            hierarchical_document_symbols: AtomicBool::new(false),
+            dynamic_watched_files: AtomicBool::new(false),
+            inlay_hint_refresh: AtomicBool::new(false),
+            semantic_tokens_refresh: AtomicBool::new(false),
+            overlapping_semantic_tokens: AtomicBool::new(false),
+            position_encoding: RwLock::new(PositionEncoding::Utf16),
+            configuration_requests: AtomicBool::new(false),
+            ast_notifications: AtomicBool::new(false),
+            ast_changed_tasks: DashMap::new(),
+            shut_down: CancellationToken::new(),
+            background: tokio::sync::RwLock::new(()),
+            diagnostic_generations: DashMap::new(),
+            tracer: Arc::new(trace::Tracer::new(client.clone())),
+            client
+        }
+    }
+
+    /// The open document at `uri` as of its last change, or `None` if it isn't open.
+    pub fn document(&self, uri: &Url) -> Option<DocumentState> {
+        let key = uri.to_string();
+        Some(DocumentState {
+            text: self.document_map.get(&key)?.clone(),
+            tokens: self.token_map.get(&key)?.clone(),
+            ast: self.ast_map.get(&key)?.clone()
+        })
+    }
+
+    fn is_superseded(&self, key: &str, version: i32) -> bool {
+        self.edited.get(key).is_some_and(|edited| edited.0 > version)
+    }
+
+    fn position_encoding(&self) -> PositionEncoding {
+        *self.position_encoding.read().unwrap()
+    }
+
+    // Messages above the configured level are dropped before reaching the client
+    async fn log(&self, level: LogLevel, message: impl std::fmt::Display) {
+        if level > self.settings.read().unwrap().log_level || self.shut_down.is_cancelled() {
+            return;
+        }
+        let message_type = match level {
+            LogLevel::Error => MessageType::ERROR,
+            LogLevel::Warning => MessageType::WARNING,
+            LogLevel::Info => MessageType::INFO,
+            LogLevel::Debug | LogLevel::Trace => MessageType::LOG
+        };
+        self.client.log_message(message_type, message).await;
+    }
+
+    /// What `work` gives, or `None` when the server shuts down first, which drops it wherever it
+    /// was. `shutdown` waits for everything running here to be done one way or the other.
+    async fn background<T>(&self, work: impl Future<Output = T>) -> Option<T> {
+        let _running = self.background.read().await;
+        tokio::select! {
+            biased;
+            _ = self.shut_down.cancelled() => None,
+            result = work => Some(result)
+        }
+    }
+
+    /// What `run` gives, or `None` when it panics, which is logged. A bug that malformed input
+    /// trips must cost that request its answer, not the client its connection.
+    async fn guarded<T>(&self, what: &str, run: impl FnOnce() -> Option<T>) -> Option<T> {
+        match panic::catch_unwind(AssertUnwindSafe(run)) {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| String::from("no message"));
+                self.log(LogLevel::Error, format!("{} crashed: {}", what, message)).await;
+                None
+            }
+        }
+    }
+
+    // Only what `load_includes` has read, since the lookups that need this can't wait on a file
+    fn include_ast(&self, path: &Path) -> Option<Arc<Ast>> {
+        self.include_map.get(path).map(|ast| ast.clone())
+    }
+
+    // Reads and parses whatever the includes of `ast` reach that isn't parsed yet, off the
+    // async threads, so `included_files` finds it all
+    async fn load_includes(&self, ast: &Ast, document: Option<&Path>) {
+        let bases = self.include_bases(document);
+        self.load_resolved_includes(resolved_includes(ast, document, &bases), &bases).await;
+    }
+
+    // Those of an included file are looked for under the same bases, as in `included_files`
+    async fn load_resolved_includes(&self, mut pending: Vec<PathBuf>, bases: &[PathBuf]) {
+        let mut visited = HashSet::new();
+        let exclusions = self.exclusions();
+        while let Some(path) = pending.pop() {
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+            let included = match self.include_ast(&path) {
+                Some(included) => included,
+                None => {
+                    let parsed = tokio::task::spawn_blocking({
+                        let path = path.clone();
+                        move || workspace::index_file(&path)
+                    }).await.ok().flatten();
+                    let Some(parsed) = parsed else { continue };
+                    let parsed = Arc::new(parsed);
+                    self.update_indexed_usage(&path, Some(&parsed), &exclusions);
+                    self.include_map.insert(path.clone(), parsed.clone());
+                    parsed
+                }
+            };
+            pending.extend(resolved_includes(&included, Some(&path), bases));
+        }
+    }
+
+    // In case a request comes in before the change that added an include has loaded it
+    async fn load_document_includes(&self, uri: &Url) {
+        let document = uri.to_file_path().ok();
+        let bases = self.include_bases(document.as_deref());
+        let includes = match self.ast_map.get(uri.as_str()) {
+            Some(ast) => resolved_includes(&ast, document.as_deref(), &bases),
+            None => return
+        };
+        self.load_resolved_includes(includes, &bases).await;
+    }
+
+    // For when the include lookup itself changed, with the settings or the workspace folders
+    async fn load_open_includes(&self) {
+        let open: Vec<(Url, Ast)> = self.ast_map
+            .iter()
+            .filter_map(|entry| Some((Url::parse(entry.key()).ok()?, entry.value().clone())))
+            .collect();
+        for (uri, ast) in open {
+            self.load_includes(&ast, uri.to_file_path().ok().as_deref()).await;
+        }
+    }
+
+    // Files reachable through `#include`s, each after the files it includes itself
+    fn included_files(&self, ast: &Ast, document: Option<&Path>, bases: &[PathBuf], visited: &mut HashSet<PathBuf>, files: &mut Vec<(PathBuf, Arc<Ast>)>) {
+        for node in &ast.nodes {
+            if let Node::Directive(ast::Directive { directive_type: DirectiveType::Include, argument: Some((path, _)), .. }) = node {
+                let path = match database::resolve_include(document, path, bases) {
+                    Some(path) => path,
+                    None => continue
+                };
+                if !visited.insert(path.clone()) {
+                    continue;
+                }
+                if let Some(included) = self.include_ast(&path) {
+                    self.included_files(&included, Some(&path), bases, visited, files);
+                    files.push((path, included));
+                }
+            }
+        }
+    }
+
+    // Where the includes of the document at `document` are looked for after its own directory,
+    // those of an included file too
+    fn include_bases(&self, document: Option<&Path>) -> Vec<PathBuf> {
+        let settings = match document.and_then(|document| Url::from_file_path(document).ok()) {
+            Some(uri) => self.settings_for(&uri),
+            None => self.settings.read().unwrap().clone()
+        };
+        let roots = self.workspace_roots.read().unwrap();
+        let root = document.and_then(|document| roots
+            .iter()
+            .filter(|root| document.starts_with(root))
+            .max_by_key(|root| root.components().count()));
+        settings.import_base_paths
+            .iter()
+            .map(Path::new)
+            .filter_map(|base| if base.is_absolute() { Some(base.to_path_buf()) } else { root.map(|root| root.join(base)) })
+            .collect()
+    }
+
+    fn exclusions(&self) -> workspace::Exclusions {
+        let roots = self.workspace_roots.read().unwrap();
+        workspace::Exclusions::new(&self.settings.read().unwrap().exclude, &roots)
+    }
+
+    // The settings of the innermost workspace folder the document is in
+    fn settings_for(&self, uri: &Url) -> Settings {
+        let path = uri.to_file_path().ok();
+        let root_settings = self.root_settings.read().unwrap();
+        let settings = root_settings
+            .iter()
+            .filter(|(root, _)| path.as_ref().is_some_and(|path| path.starts_with(root)))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, settings)| settings.clone());
+        settings.unwrap_or_else(|| self.settings.read().unwrap().clone())
+    }
+
+    /// Asks the client for each workspace folder's settings. Clients resolve the scopes
+    /// themselves, so what comes back is used as it is, and a folder the client has nothing
+    /// for follows the global settings. Returns whether anything changed.
+    async fn fetch_root_settings(&self) -> bool {
+        if !self.configuration_requests.load(Ordering::SeqCst) {
+            return false;
+        }
+        let roots = self.workspace_roots.read().unwrap().clone();
+        let items = roots
+            .iter()
+            .map(|root| ConfigurationItem {
+                scope_uri: Url::from_directory_path(root).ok(),
+                section: Some(settings::SECTION.to_string())
+            })
+            .collect();
+        let values = match self.client.configuration(items).await {
+            Ok(values) => values,
+            Err(err) => {
+                self.log(LogLevel::Warning, format!("could not get the workspace folders' settings: {}", err)).await;
+                return false;
+            }
+        };
+        let mut root_settings: Vec<(PathBuf, Settings)> = Vec::new();
+        for (root, value) in roots.into_iter().zip(values) {
+            if !value.is_null() {
+                root_settings.push((root, self.read_settings(value).await));
+            }
+        }
+        let mut previous = self.root_settings.write().unwrap();
+        let changed = *previous != root_settings;
+        *previous = root_settings;
+        changed
+    }
+
+    // What inlay hints and semantic tokens depend on besides the documents themselves
+    fn pulled_inputs(&self) -> PulledInputs {
+        let settings = self.settings.read().unwrap();
+        let mut inlay_hints = vec![settings.inlay_hints.clone()];
+        inlay_hints.extend(self.root_settings.read().unwrap().iter().map(|(_, settings)| settings.inlay_hints.clone()));
+        PulledInputs {
+            inlay_hints,
+            semantic_tokens: settings.semantic_tokens,
+            semantic_token_types: settings.semantic_token_types.clone(),
+            custom_widgets: self.custom_widgets.read().unwrap().clone()
+        }
+    }
+
+    // Clients only pull these again on their own after an edit, so a settings change would
+    // otherwise not show until then
+    async fn refresh_pulled(&self, before: PulledInputs) {
+        let after = self.pulled_inputs();
+        let inlay_hints_changed = before.inlay_hints != after.inlay_hints || before.custom_widgets != after.custom_widgets;
+        if inlay_hints_changed && self.inlay_hint_refresh.load(Ordering::SeqCst) {
+            let _ = self.client.send_request::<request::InlayHintRefreshRequest>(()).await;
+        }
+        let types_changed = before.semantic_token_types != after.semantic_token_types;
+        if types_changed {
+            self.semantic_encoding_map.clear();
+        }
+        if (before.semantic_tokens != after.semantic_tokens || types_changed) && self.semantic_tokens_refresh.load(Ordering::SeqCst) {
+            let _ = self.client.send_request::<request::SemanticTokensRefresh>(()).await;
+        }
+    }
+
+    async fn republish_diagnostics(&self) {
+        self.load_open_includes().await;
+        let uris: Vec<String> = self.document_map.iter().map(|entry| entry.key().clone()).collect();
+        for uri in uris {
+            if let Ok(uri) = Url::parse(&uri) {
+                self.publish_diagnostics(uri, None).await;
+            }
+        }
+    }
+
+    // Validates once the document has gone unchanged for a moment, so typing a word doesn't
+    // validate every prefix of it. The tokens are already up to date by then
+    async fn publish_diagnostics_debounced(&self, uri: Url, version: i32) {
+        let generation = {
+            let mut generation = self.diagnostic_generations.entry(uri.to_string()).or_insert(0);
+            *generation += 1;
+            *generation
+        };
+        self.background(async {
+            tokio::time::sleep(DIAGNOSTICS_DELAY).await;
+            if self.diagnostic_generations.get(uri.as_str()).is_some_and(|latest| *latest == generation) {
+                self.publish_diagnostics(uri, Some(version)).await;
+            }
+        }).await;
+    }
+
+    async fn publish_diagnostics(&self, uri: Url, version: Option<i32>) {
+        if self.shut_down.is_cancelled() {
+            return;
+        }
+        // Generated files aren't the user's to fix
+        if uri.to_file_path().is_ok_and(|path| self.exclusions().matches(&path)) {
+            self.client.publish_diagnostics(uri, Vec::new(), version).await;
+            return;
+        }
+        let settings = self.settings_for(&uri);
+        if self.oversized.contains(uri.as_str()) {
+            let size = self.document_map.get(uri.as_str()).map(|rope| rope.len_bytes()).unwrap_or(0);
+            self.client.publish_diagnostics(uri, vec![diagnostics::file_too_large(size, &settings)], version).await;
+            return;
+        }
+        let db = self.widget_db(&uri);
+        let document = uri.to_file_path().ok();
+        let bases = self.include_bases(document.as_deref());
+        let encoding = self.position_encoding();
+        let diagnostics = || -> Option<Vec<Diagnostic>> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            let mut cache = self.diagnostic_map.entry(uri.to_string()).or_default();
+            let mut diagnostics = self.lex_diagnostics.get(uri.as_str()).map(|found| found.clone()).unwrap_or_default();
+            diagnostics.extend(diagnostics::validate_incremental(&rope, encoding, &ast, &db, &settings, &mut cache));
+            if settings.diagnostics.unresolved_includes {
+                diagnostics.extend(diagnostics::unresolved_includes(&rope, encoding, &ast, |path| {
+                    database::resolve_include(document.as_deref(), path, &bases).is_some()
+                }));
+            }
+            Some(diagnostics)
+        }();
+        if let Some(diagnostics) = diagnostics {
+            self.client.publish_diagnostics(uri, diagnostics, version).await;
+        }
+    }
+
+    // The whole document's tokens, encoded once per version and shared by the full and
+    // range requests
+    fn encoded_semantic_tokens(&self, uri: &str) -> Option<Vec<SemanticToken>> {
+        if let Some(encoded) = self.semantic_encoding_map.get(uri) {
+            return Some(encoded.clone());
+        }
+        let mut im_complete_tokens = self.token_map.get_mut(uri)?;
+        let rope = self.document_map.get(uri)?;
+        im_complete_tokens.sort_by_key(|token| token.range.start);
+        let (types, _) = semantic_tokens::TokenTypes::new(&self.settings.read().unwrap().semantic_token_types);
+        let semantic_tokens = semantic_tokens::encode_semantic_tokens(
+            &im_complete_tokens,
+            &rope,
+            self.position_encoding(),
+            &types,
+            self.overlapping_semantic_tokens.load(Ordering::SeqCst)
+        );
+        self.semantic_encoding_map.insert(uri.to_string(), semantic_tokens.clone());
+        Some(semantic_tokens)
+    }
+
+    fn literal_at(&self, uri: &Url, position: Position) -> Option<completion::Literal> {
+        let rope = self.document_map.get(&uri.to_string())?;
+        let tokens = self.token_map.get(&uri.to_string())?;
+        let offset = position::position_to_offset_in(&rope, position, self.position_encoding())?;
+        completion::literal_at(&rope, &tokens, offset)
+    }
+
+    // Inside a string only markup is worth completing, and only where it's parsed as such
+    fn string_completions(&self, uri: &Url, position: Position, string: std::ops::Range<usize>) -> Option<Vec<CompletionItem>> {
+        let rope = self.document_map.get(&uri.to_string())?;
+        let ast = self.ast_map.get(&uri.to_string())?;
+        let (object, setter) = ast::setter_at(&ast, string.start)?;
+        if !completion::takes_markup(object, &setter.name) {
+            return None;
+        }
+        let offset = position::position_to_offset_in(&rope, position, self.position_encoding())?;
+        let before = rope.get_byte_slice(string.start..offset)?.to_string();
+        // Nothing to offer in the middle of a tag's name or attributes
+        let open_tag = before.rfind('<').is_some_and(|open| !before[open..].contains('>'));
+        if open_tag && !before.ends_with('<') {
+            return None;
+        }
+        Some(completion::markup_completions(before.ends_with('<')))
+    }
+
+    fn include_hover(&self, uri: &Url, path: &str) -> String {
+        const PREVIEW: usize = 10;
+        let document = uri.to_file_path().ok();
+        let bases = self.include_bases(document.as_deref());
+        let resolved = match database::resolve_include(document.as_deref(), path, &bases) {
+            Some(resolved) => resolved,
+            None => {
+                let candidates: Vec<String> = database::include_candidates(document.as_deref(), path, &bases)
+                    .iter()
+                    .map(|candidate| format!("- `{}`", candidate.display()))
+                    .collect();
+                return format!("`{}` does not exist. Looked for:\n{}", path, candidates.join("\n"));
+            }
+        };
+
+        let mut contents = format!("`{}`", resolved.display());
+        if resolved.extension().is_some_and(|extension| extension == "gui") {
+            if let Some(included) = self.include_ast(&resolved) {
+                let names: Vec<String> = included.nodes
+                    .iter()
+                    .filter_map(|node| match node {
+                        Node::Definition(definition) => Some(format!("`{}`", definition.name)),
+                        _ => None
+                    })
+                    .collect();
+                if names.is_empty() {
+                    contents.push_str("\n\nDefines no widgets");
+                } else {
+                    contents.push_str(&format!("\n\nDefines {}", names[..names.len().min(PREVIEW)].join(", ")));
+                    if names.len() > PREVIEW {
+                        contents.push_str(&format!(" and {} more", names.len() - PREVIEW));
+                    }
+                }
+            }
+        }
+        contents
+    }
+
+    // Everything the document can see: its includes, then its own definitions
+    fn widget_db(&self, uri: &Url) -> WidgetDb {
+        match self.ast_map.get(&uri.to_string()) {
+            Some(ast) => self.widget_db_for(&ast, uri.to_file_path().ok().as_deref()),
+            None => WidgetDb::default()
+        }
+    }
+
+    // Custom widgets come first, so a library or document defining the same name wins
+    fn widget_db_for(&self, ast: &Ast, document: Option<&Path>) -> WidgetDb {
+        let mut db = WidgetDb::default();
+        db.add_custom_widgets(&self.custom_widgets.read().unwrap());
+        let mut files = Vec::new();
+        self.included_files(ast, document, &self.include_bases(document), &mut HashSet::new(), &mut files);
+        for (_, included) in &files {
+            db.add_definitions(included);
+        }
+        db.add_definitions(ast);
+        db
+    }
+
+    // Commands
+
+    async fn extract_to_file(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let (uri, offset) = match arguments.as_slice() {
+            [uri, offset] => match (serde_json::from_value::<Url>(uri.clone()), offset.as_u64()) {
+                (Ok(uri), Some(offset)) => (uri, offset as usize),
+                _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document uri and an offset"))
+            },
+            _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document uri and an offset"))
+        };
+
+        let edit = || -> std::result::Result<WorkspaceEdit, String> {
+            let rope = self.document_map.get(&uri.to_string()).ok_or_else(|| String::from("the document is not open"))?;
+            let ast = self.ast_map.get(&uri.to_string()).ok_or_else(|| String::from("the document is not open"))?;
+            code_action::extract_to_file_edit(&uri, &rope.to_string(), &rope, self.position_encoding(), &ast, offset)
+        }();
+        let result = match edit {
+            Ok(edit) => match self.client.apply_edit(edit).await {
+                Ok(response) if response.applied => Ok(()),
+                Ok(response) => Err(response.failure_reason.unwrap_or_else(|| String::from("the editor refused the edit"))),
+                Err(err) => Err(err.to_string())
+            },
+            Err(reason) => Err(reason)
+        };
+        if let Err(reason) = result {
+            self.client
+                .show_message(MessageType::ERROR, format!("Could not extract to a new file: {}", reason))
+                .await;
+        }
+        Ok(None)
+    }
+
+    // Asks which container to use, and does nothing if the question is dismissed
+    async fn wrap_in_widget(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let (uri, selection) = match arguments.as_slice() {
+            [uri, start, end] => match (serde_json::from_value::<Url>(uri.clone()), start.as_u64(), end.as_u64()) {
+                (Ok(uri), Some(start), Some(end)) => (uri, start as usize..end as usize),
+                _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document uri and the selection's offsets"))
+            },
+            _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document uri and the selection's offsets"))
+        };
+        if workspace::is_read_only(&uri) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("the document is read-only"));
+        }
+        let containers = code_action::wrap_containers(&self.settings_for(&uri).code_actions.wrap_container);
+        let choices = containers.into_iter().map(|title| MessageActionItem { title, properties: HashMap::new() }).collect();
+        let container = match self.client.show_message_request(MessageType::INFO, "Wrap the selected widgets in", Some(choices)).await {
+            Ok(Some(choice)) => choice.title,
+            _ => return Ok(None)
+        };
+
+        // The document may have changed while the question was up
+        let edit = || -> Option<WorkspaceEdit> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            code_action::wrap_in_widget_edit(&uri, &rope.to_string(), &rope, self.position_encoding(), &ast, selection, &container)
+        }();
+        let result = match edit {
+            Some(edit) => match self.client.apply_edit(edit).await {
+                Ok(response) if response.applied => Ok(()),
+                Ok(response) => Err(response.failure_reason.unwrap_or_else(|| String::from("the editor refused the edit"))),
+                Err(err) => Err(err.to_string())
+            },
+            None => Err(String::from("the selected widgets are no longer there"))
+        };
+        if let Err(reason) = result {
+            self.client
+                .show_message(MessageType::ERROR, format!("Could not wrap in `{}`: {}", container, reason))
+                .await;
+        }
+        Ok(None)
+    }
+
+    async fn rename_property(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let params = match arguments.as_slice() {
+            [params] => serde_json::from_value::<rename::RenamePropertyParams>(params.clone())
+                .map_err(|err| tower_lsp::jsonrpc::Error::invalid_params(err.to_string()))?,
+            _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected the widget and the old and new property names"))
+        };
+        if !rename::is_property_name(&params.from) || !rename::is_property_name(&params.to) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("property names may only contain letters, `-` and `_`"));
+        }
+
+        // Open documents as the editor has them, then indexed files in the workspace. Files
+        // outside it, like the libraries in /usr/share/gtk-ui, are never touched
+        let encoding = self.position_encoding();
+        let mut changes = HashMap::new();
+        let mut open = HashSet::new();
+        for entry in self.ast_map.iter() {
+            let (uri, rope) = match (Url::parse(entry.key()), self.document_map.get(entry.key())) {
+                (Ok(uri), Some(rope)) => (uri, rope.clone()),
+                _ => continue
+            };
+            if let Ok(path) = uri.to_file_path() {
+                open.insert(path);
+            }
+            let db = self.widget_db_for(entry.value(), uri.to_file_path().ok().as_deref());
+            let edits = rename::setter_edits(&rope, encoding, entry.value(), &db, &params);
+            if !edits.is_empty() {
+                changes.insert(uri, edits);
+            }
+        }
+        for (path, ast) in self.indexed_workspace_files(&open) {
+            let (uri, text) = match (Url::from_file_path(&path), tokio::fs::read_to_string(&path).await) {
+                (Ok(uri), Ok(text)) => (uri, text),
+                _ => continue
+            };
+            self.load_includes(&ast, Some(&path)).await;
+            let db = self.widget_db_for(&ast, Some(&path));
+            let edits = rename::setter_edits(&Rope::from_str(&text), encoding, &ast, &db, &params);
+            if !edits.is_empty() {
+                changes.insert(uri, edits);
+            }
+        }
+
+        let mut result = rename::RenamePropertyResult {
+            edits: changes.values().map(Vec::len).sum(),
+            files: changes.len(),
+            applied: false
+        };
+        if !params.dry_run && !changes.is_empty() {
+            let edit = WorkspaceEdit {
+                changes: Some(changes),
+                ..Default::default()
+            };
+            match self.client.apply_edit(edit).await {
+                Ok(response) => result.applied = response.applied,
+                Err(err) => self.log(LogLevel::Error, format!("renaming `{}` failed: {}", params.from, err)).await
+            }
+        }
+        Ok(serde_json::to_value(result).ok())
+    }
+
+    // The edit goes back to the client to apply, since it's meant for the editor the
+    // keybinding was pressed in
+    fn toggle_boolean(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let params = match arguments.as_slice() {
+            [params] => serde_json::from_value::<toggle::ToggleBooleanParams>(params.clone())
+                .map_err(|err| tower_lsp::jsonrpc::Error::invalid_params(err.to_string()))?,
+            _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document and a position"))
+        };
+        let uri = params.text_document.uri;
+        if workspace::is_read_only(&uri) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("the document is read-only"));
+        }
+        let db = self.widget_db(&uri);
+        let encoding = self.position_encoding();
+        let edit = || -> std::result::Result<TextEdit, String> {
+            let rope = self.document_map.get(&uri.to_string()).ok_or_else(|| String::from("the document is not open"))?;
+            let ast = self.ast_map.get(&uri.to_string()).ok_or_else(|| String::from("the document is not open"))?;
+            let offset = position::position_to_offset_in(&rope, params.position, encoding).ok_or_else(|| String::from("the position is outside the document"))?;
+            toggle::toggle_edit(&rope.to_string(), &rope, encoding, &ast, &db, offset, params.property.as_deref())
+        }();
+        match edit {
+            Ok(edit) => Ok(serde_json::to_value(edit).ok()),
+            Err(reason) => Err(tower_lsp::jsonrpc::Error::invalid_params(reason))
+        }
+    }
+
+    fn toggle_comment(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let params = match arguments.as_slice() {
+            [params] => serde_json::from_value::<toggle::ToggleCommentParams>(params.clone())
+                .map_err(|err| tower_lsp::jsonrpc::Error::invalid_params(err.to_string()))?,
+            _ => return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document and a range"))
+        };
+        let uri = params.text_document.uri;
+        if workspace::is_read_only(&uri) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("the document is read-only"));
+        }
+        let rope = self.document_map
+            .get(&uri.to_string())
+            .ok_or_else(|| tower_lsp::jsonrpc::Error::invalid_params("the document is not open"))?;
+        Ok(serde_json::to_value(toggle::comment_edits(&rope, params.range)).ok())
+    }
+
+    // Files in the workspace that were indexed, leaving out the `open` ones and excluded ones
+    fn indexed_workspace_files(&self, open: &HashSet<PathBuf>) -> Vec<(PathBuf, Arc<Ast>)> {
+        let roots = self.workspace_roots.read().unwrap().clone();
+        let exclusions = self.exclusions();
+        self.include_map
+            .iter()
+            .filter(|entry| !open.contains(entry.key()) && !exclusions.matches(entry.key()))
+            .filter(|entry| roots.iter().any(|root| entry.key().starts_with(root)))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    // How much each widget and property is used in open documents and the indexed workspace
+    fn usage(&self) -> completion::Usage {
+        self.usage.read().unwrap().clone()
+    }
+
+    fn update_usage(&self, key: String, update: impl FnOnce(&mut FileUsage)) {
+        let mut file = self.file_usage.entry(key).or_default();
+        let mut usage = self.usage.write().unwrap();
+        if let Some(counted) = file.counted() {
+            usage.subtract(counted);
+        }
+        update(&mut file);
+        if let Some(counted) = file.counted() {
+            usage.add(counted);
+        }
+    }
+
+    // Only what is in a workspace folder and not excluded counts, like for workspace symbols
+    fn update_indexed_usage(&self, path: &Path, ast: Option<&Ast>, exclusions: &workspace::Exclusions) {
+        let counts = !exclusions.matches(path) && self.workspace_roots.read().unwrap().iter().any(|root| path.starts_with(root));
+        let indexed = ast.filter(|_| counts).map(completion::Usage::of);
+        self.update_usage(path.to_string_lossy().into_owned(), |file| file.indexed = indexed);
+    }
+
+    // After the folders or the exclusions change, which can take any number of files in or out
+    fn recount_indexed_usage(&self) {
+        let exclusions = self.exclusions();
+        let indexed: Vec<(PathBuf, Arc<Ast>)> = self.include_map.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect();
+        let stale: Vec<String> = self.file_usage
+            .iter()
+            .filter(|entry| entry.indexed.is_some())
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in stale {
+            self.update_usage(key, |file| file.indexed = None);
+        }
+        for (path, ast) in indexed {
+            self.update_indexed_usage(&path, Some(&ast), &exclusions);
+        }
+    }
+
+    // Open documents first and then indexed workspace files, one file between each `extend`
+    // so a cancelled request stops soon. The maps are never borrowed across an await
+    async fn search_workspace<T: serde::Serialize>(
+        &self,
+        results: &mut partial::PartialResults<'_, T>,
+        found: impl Fn(&Url, &Rope, &Ast) -> Vec<T>,
+        worth_reading: impl Fn(&Ast) -> bool
+    ) {
+        let uris: Vec<String> = self.ast_map.iter().map(|entry| entry.key().clone()).collect();
+        let mut open = HashSet::new();
+        for key in uris {
+            let items = || -> Option<Vec<T>> {
+                let uri = Url::parse(&key).ok()?;
+                if let Ok(path) = uri.to_file_path() {
+                    open.insert(path);
+                }
+                let rope = self.document_map.get(&key)?.clone();
+                let ast = self.ast_map.get(&key)?;
+                Some(found(&uri, &rope, &ast))
+            }();
+            results.extend(items.unwrap_or_default()).await;
+        }
+        for (path, ast) in self.indexed_workspace_files(&open) {
+            if !worth_reading(&ast) {
+                continue;
+            }
+            let items = match (Url::from_file_path(&path), tokio::fs::read_to_string(&path).await) {
+                (Ok(uri), Ok(text)) => found(&uri, &Rope::from_str(&text), &ast),
+                _ => continue
+            };
+            results.extend(items).await;
+        }
+    }
+
+    async fn load_custom_widgets(&self) {
+        let setting = self.settings.read().unwrap().custom_widgets.clone();
+        let widgets = match setting {
+            None => Ok(BTreeMap::new()),
+            Some(CustomWidgets::Inline(widgets)) => Ok(widgets),
+            Some(CustomWidgets::Path(path)) => {
+                let path = match self.workspace_roots.read().unwrap().first() {
+                    Some(root) => root.join(&path),
+                    None => PathBuf::from(&path)
+                };
+                tokio::fs::read_to_string(&path)
+                    .await
+                    .map_err(|err| err.to_string())
+                    .and_then(|text| serde_json::from_str(&text).map_err(|err| err.to_string()))
+                    .map_err(|err| format!("Could not load custom widgets from {}: {}", path.display(), err))
+            }
+        };
+        // A file that fails to load leaves the widgets from before it in place
+        match widgets {
+            Ok(widgets) => *self.custom_widgets.write().unwrap() = widgets,
+            Err(message) => self.client.show_message(MessageType::WARNING, message).await
+        }
+    }
+
+    // Indexing
+
+    async fn report_progress(&self, token: &NumberOrString, progress: WorkDoneProgress) {
+        self.client.send_notification::<notification::Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(progress),
+        }).await;
+    }
+
+    // Stops where it is if the server shuts down, dropping the files still being parsed
+    async fn index_workspace(&self, roots: Vec<PathBuf>) {
+        self.background(self.index_roots(roots)).await;
+    }
+
+    // Parses every `.gui` file under the given roots, a CPU's worth at a time
+    async fn index_roots(&self, roots: Vec<PathBuf>) {
+        if roots.is_empty() {
+            return;
+        }
+        let exclusions = self.exclusions();
+        let files = tokio::task::spawn_blocking({
+            let (roots, exclusions) = (roots.clone(), exclusions.clone());
+            move || workspace::gui_files(&roots, &exclusions)
+        }).await.unwrap_or_default();
+        if files.is_empty() {
+            return;
+        }
+
+        // Files that haven't changed since the last run come straight from the cache
+        let lookup = tokio::task::spawn_blocking({
+            let (roots, files) = (roots.clone(), files.clone());
+            move || index_cache::lookup(&roots, &files)
+        }).await;
+        let (cached, stale) = match lookup {
+            Ok(lookup) => {
+                for problem in lookup.problems {
+                    self.log(LogLevel::Warning, format!("re-indexing everything, {}", problem)).await;
+                }
+                (lookup.cached, lookup.stale)
+            },
+            Err(_) => (Vec::new(), files.clone())
+        };
+        let from_cache = cached.len();
+        let mut stamps = Vec::new();
+        for (path, stamp, ast) in cached {
+            stamps.push((path.clone(), stamp));
+            self.update_indexed_usage(&path, Some(&ast), &exclusions);
+            self.include_map.insert(path, Arc::new(ast));
+        }
+
+        let token = NumberOrString::String("gtkui-indexing".to_string());
+        let progress = self.work_done_progress.load(Ordering::SeqCst) && self.client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams { token: token.clone() })
+            .await
+            .is_ok();
+        if progress {
+            self.report_progress(&token, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Indexing".to_string(),
+                cancellable: Some(false),
+                message: Some(format!("0/{} files", files.len())),
+                percentage: Some(0),
+            })).await;
+        }
+
+        let workers = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+        let semaphore = Arc::new(Semaphore::new(workers));
+        let mut tasks = JoinSet::new();
+        for path in stale {
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await.ok()?;
+                // Taken before reading, so a change while it is parsed isn't cached as seen
+                let (stamp, ast) = tokio::task::spawn_blocking({
+                    let path = path.clone();
+                    move || (index_cache::stamp(&path), workspace::index_file(&path))
+                }).await.ok()?;
+                Some((path, stamp, ast?))
+            });
+        }
+
+        // Each file is available as soon as it is done, not only once all of them are
+        let mut done = from_cache;
+        let mut reported = 0;
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(Some((path, stamp, ast))) = result {
+                stamps.extend(stamp.map(|stamp| (path.clone(), stamp)));
+                self.update_indexed_usage(&path, Some(&ast), &exclusions);
+                self.include_map.insert(path, Arc::new(ast));
+            }
+            done += 1;
+            let percentage = (done * 100 / files.len()) as u32;
+            if progress && percentage != reported {
+                reported = percentage;
+                self.report_progress(&token, WorkDoneProgress::Report(WorkDoneProgressReport {
+                    cancellable: Some(false),
+                    message: Some(format!("{}/{} files", done, files.len())),
+                    percentage: Some(percentage),
+                })).await;
+            }
+        }
+
+        if progress {
+            self.report_progress(&token, WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(format!("Indexed {} files", files.len())),
+            })).await;
+        }
+        // Only worth writing when something had to be parsed again
+        if from_cache < files.len() {
+            let entries: Vec<(PathBuf, index_cache::Stamp, Arc<Ast>)> = stamps
+                .into_iter()
+                .filter_map(|(path, stamp)| Some((path.clone(), stamp, self.include_map.get(&path)?.clone())))
+                .collect();
+            let problems = tokio::task::spawn_blocking(move || index_cache::save(&roots, &entries)).await.unwrap_or_default();
+            for problem in problems {
+                self.log(LogLevel::Warning, format!("the index cache wasn't saved: {}", problem)).await;
+            }
+        }
+        self.log(LogLevel::Info, format!("indexed {} files, {} of them from the cache", files.len(), from_cache)).await;
+    }
+
+    // Fields that are left at their defaults are said so, or a typo would look like a setting
+    // that does nothing
+    async fn read_settings(&self, value: Value) -> Settings {
+        let (settings, errors) = Settings::parse(value);
+        for error in errors {
+            self.log(LogLevel::Warning, format!("ignoring a setting, {}", error)).await;
+        }
+        settings
+    }
+
+    // Types that are set but can't be used are only left out, so say why they don't show
+    async fn warn_about_token_types(&self) {
+        let (_, ignored) = semantic_tokens::TokenTypes::new(&self.settings.read().unwrap().semantic_token_types);
+        for reason in ignored {
+            self.log(LogLevel::Warning, format!("ignoring a semantic token type setting: {}", reason)).await;
+        }
+    }
+
+    async fn update_semantic_tokens_registration(&self) {
+        if !self.dynamic_semantic_tokens.load(Ordering::SeqCst) {
+            return;
+        }
+        let enabled = self.settings.read().unwrap().semantic_tokens;
+        if enabled == self.semantic_tokens_registered.swap(enabled, Ordering::SeqCst) {
+            return;
+        }
+
+        let result = if enabled {
+            self.client.register_capability(vec![Registration {
+                id: SEMANTIC_TOKENS_REGISTRATION_ID.to_string(),
+                method: "textDocument/semanticTokens".to_string(),
+                register_options: serde_json::to_value(semantic_tokens_registration_options()).ok(),
+            }]).await
+        } else {
+            self.client.unregister_capability(vec![Unregistration {
+                id: SEMANTIC_TOKENS_REGISTRATION_ID.to_string(),
+                method: "textDocument/semanticTokens".to_string(),
+            }]).await
+        };
+        if let Err(err) = result {
+            self.log(LogLevel::Error, format!("failed to update semantic tokens registration: {}", err)).await;
+        }
+    }
+
+    // Lets included and indexed files be parsed again when they change on disk
+    async fn register_file_watcher(&self) {
+        if !self.dynamic_watched_files.load(Ordering::SeqCst) {
+            return;
+        }
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: "**/*.gui".to_string(),
+                kind: None,
+            }],
+        };
+        let result = self.client.register_capability(vec![Registration {
+            id: WATCHED_FILES_REGISTRATION_ID.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        }]).await;
+        if let Err(err) = result {
+            self.log(LogLevel::Error, format!("failed to watch .gui files: {}", err)).await;
+        }
+    }
+
+    // Custom requests
+
+    async fn effective_properties(&self, params: extensions::EffectivePropertiesParams) -> Result<Option<extensions::EffectivePropertiesResult>> {
+        let uri = params.text_document.uri;
+        let db = self.widget_db(&uri);
+        let encoding = self.position_encoding();
+        let result = || -> Option<extensions::EffectivePropertiesResult> {
+            if let Some(name) = &params.name {
+                db.widgets.get(name)?;
+                return Some(extensions::effective_properties(&db, name, None));
+            }
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            let offset = position::position_to_offset_in(&rope, params.position?, encoding)?;
+            if !ast.errors.is_empty() {
+                let tokens = self.token_map.get(&uri.to_string())?;
+                let name = ast::enclosing_name(&tokens, offset)?;
+                db.widgets.get(name)?;
+                return Some(extensions::effective_properties(&db, name, None));
+            }
+            match ast::enclosing_definition(&ast, offset)? {
+                Node::Object(object) => Some(extensions::effective_properties(&db, &object.name, Some(object))),
+                Node::Definition(definition) => Some(extensions::effective_properties(&db, &definition.name, None)),
+                _ => None
+            }
+        }();
+        Ok(result)
+    }
+
+    async fn debug_tokens_pretty(&self, params: extensions::DebugTokensParams) -> Result<Option<Vec<String>>> {
+        let uri = params.text_document.uri.to_string();
+        let encoding = self.position_encoding();
+        let tokens = || -> Option<Vec<String>> {
+            let rope = self.document_map.get(&uri)?;
+            let tokens = self.token_map.get(&uri)?;
+            Some(extensions::pretty_tokens(&rope, encoding, &tokens))
+        }();
+        Ok(tokens)
+    }
+
+    // Stateless, with only the custom widgets to go on since there's no document to find includes from
+    async fn validate_text(&self, params: extensions::ValidateTextParams) -> Result<extensions::ValidateTextResult> {
+        if params.language_id != notebook::LANGUAGE_ID {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("can only validate `{}`, not `{}`", notebook::LANGUAGE_ID, params.language_id)));
+        }
+        let mut db = WidgetDb::default();
+        db.add_custom_widgets(&self.custom_widgets.read().unwrap());
+        let settings = self.settings.read().unwrap().clone();
+        Ok(extensions::validate_text(&params.text, self.position_encoding(), &settings, &db))
+    }
+
+    // Not part of `LanguageServer` in this version of tower-lsp, so routed as custom methods
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        let db = self.widget_db(&uri);
+        let settings = self.settings_for(&uri).inlay_hints;
+        let encoding = self.position_encoding();
+        let hints = || -> Option<Vec<InlayHint>> {
+            let rope = self.document_map.get(&uri.to_string())?;
+            let ast = self.ast_map.get(&uri.to_string())?;
+            let range = position::lsp_to_range_in(&rope, params.range, encoding)?;
+            Some(inlay_hints::inlay_hints(&uri, &rope, encoding, &ast, &db, &settings, range))
+        }();
+        Ok(hints)
+    }
+
+    async fn inlay_hint_resolve(&self, hint: InlayHint) -> Result<InlayHint> {
+        let uri = match hint.data.clone().and_then(|data| serde_json::from_value::<inlay_hints::HintData>(data).ok()) {
+            Some(data) => data.uri,
+            None => return Ok(hint)
+        };
+        let db = self.widget_db(&uri);
+        Ok(match self.ast_map.get(&uri.to_string()) {
+            Some(ast) => inlay_hints::resolve(hint, &ast, &db),
+            None => hint
+        })
+    }
+
+    async fn set_trace(&self, params: SetTraceParams) {
+        self.tracer.set(params.value);
+    }
+
+    // Takes no parameters, like gtkui/features
+    async fn diagnostic_catalog(&self, _: Option<Value>) -> Result<Vec<extensions::DiagnosticCatalogEntry>> {
+        Ok(extensions::diagnostic_catalog())
+    }
+
+    // Takes no parameters, but clients commonly send `null` for them
+    async fn features(&self, _: Option<Value>) -> Result<extensions::Features> {
+        let settings = self.settings.read().unwrap();
+        let rules = &settings.diagnostics;
+        Ok(extensions::Features {
+            diagnostics: rules.redundant_defaults
+                || rules.mixed_indentation
+                || rules.unknown_properties
+                || rules.missing_arguments
+                || rules.properties_outside_widgets
+                || rules.parser_recovery
+                || rules.max_nesting_depth.is_some()
+                || rules.unresolved_includes
+                || settings.gtk_version().is_some(),
+            redundant_defaults: rules.redundant_defaults,
+            mixed_indentation: rules.mixed_indentation,
+            unknown_properties: rules.unknown_properties,
+            missing_arguments: rules.missing_arguments,
+            properties_outside_widgets: rules.properties_outside_widgets,
+            parser_recovery: rules.parser_recovery,
+            deep_nesting: rules.max_nesting_depth.is_some(),
+            property_versions: settings.gtk_version().is_some(),
+            unresolved_includes: rules.unresolved_includes,
+            semantic_tokens: settings.semantic_tokens,
+            completion: true,
+            hover: true,
+            definition: true,
+            implementation: true,
+            references: true,
+            document_highlights: true,
+            workspace_symbols: true,
+            formatting: true,
+            inlay_type_hints: settings.inlay_hints.types,
+            inlay_default_hints: settings.inlay_hints.defaults,
+            code_actions: true,
+            document_symbols: true,
+            folding_ranges: true,
+            rename: false,
+            rename_property: true,
+            extract_to_file: true,
+            validate_text: true,
+            notebooks: true,
+            watched_files: self.dynamic_watched_files.load(Ordering::SeqCst),
+            ast_changed: self.ast_notifications.load(Ordering::SeqCst)
+        })
+    }
+
+    // Notebooks
+
+    // Each `gui` cell is handled as a document of its own, keyed by the cell's uri
+    async fn open_cell(&self, cell: tower_lsp::lsp_types::TextDocumentItem) {
+        if cell.language_id != notebook::LANGUAGE_ID {
+            return;
+        }
+        let (uri, version) = (cell.uri.clone(), cell.version);
+        self.edited.insert(uri.to_string(), (version, Rope::from_str(&cell.text)));
+        self.on_change(TextDocumentItem {
+            uri: cell.uri,
+            text: cell.text,
+            version: cell.version,
+        })
+        .await;
+        self.publish_diagnostics(uri, Some(version)).await;
+    }
+
+    // Forgets an open document or cell along with everything made from it, and clears its
+    // diagnostics. A workspace file is seen through the index again from then on
+    async fn close_document(&self, uri: Url) {
+        let key = uri.to_string();
+        if self.document_map.remove(&key).is_none() {
+            return;
+        }
+        self.token_map.remove(&key);
+        self.ast_map.remove(&key);
+        self.update_usage(usage_key(&uri), |file| file.open = None);
+        self.cancel_ast_changed(&key);
+        self.semantic_token_map.remove(&key);
+        self.diagnostic_map.remove(&key);
+        self.semantic_encoding_map.remove(&key);
+        self.folding_range_map.remove(&key);
+        self.document_symbol_map.remove(&key);
+        self.lex_failures.remove(&key);
+        self.lex_diagnostics.remove(&key);
+        self.oversized.remove(&key);
+        self.edited.remove(&key);
+        self.diagnostic_generations.remove(&key);
+        self.client.publish_diagnostics(uri, vec![], None).await;
+    }
+
+    async fn notebook_did_open(&self, params: notebook::DidOpenNotebookDocumentParams) {
+        for cell in params.cell_text_documents {
+            self.open_cell(cell).await;
+        }
+    }
+
+    async fn notebook_did_change(&self, params: notebook::DidChangeNotebookDocumentParams) {
+        let cells = match params.change.cells {
+            Some(cells) => cells,
+            None => return
+        };
+        if let Some(structure) = cells.structure {
+            for cell in structure.did_close {
+                self.close_document(cell.uri).await;
+            }
+            for cell in structure.did_open {
+                self.open_cell(cell).await;
+            }
+        }
+        for content in cells.text_content {
+            // Cells that were never opened as `gui` are not tracked
+            if !self.document_map.contains_key(&content.document.uri.to_string()) {
+                continue;
+            }
+            self.did_change(DidChangeTextDocumentParams {
+                text_document: content.document,
+                content_changes: content.changes,
+            })
+            .await;
+        }
+    }
+
+    async fn notebook_did_close(&self, params: notebook::DidCloseNotebookDocumentParams) {
+        self.log(LogLevel::Debug, format!("notebook closed: {}", params.notebook_document.uri)).await;
+        for cell in params.cell_text_documents {
+            self.close_document(cell.uri).await;
+        }
+    }
+
+    // Only the last of a quick succession of parses is sent
+    fn schedule_ast_changed(&self, uri: Url, version: i32, ast: &Ast) {
+        let key = uri.to_string();
+        let params = extensions::AstChangedParams {
+            uri,
+            version,
+            nodes: extensions::preview_tree(&ast.nodes)
+        };
+        let client = self.client.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(AST_CHANGED_DELAY).await;
+            client.send_notification::<extensions::AstChanged>(params).await;
+        });
+        if let Some(previous) = self.ast_changed_tasks.insert(key, task) {
+            previous.abort();
+        }
+    }
+
+    // A document that no longer parses, or isn't parsed at all, has no tree to send
+    fn cancel_ast_changed(&self, key: &str) {
+        if let Some((_, task)) = self.ast_changed_tasks.remove(key) {
+            task.abort();
+        }
+    }
+
+    async fn on_change(&self, params: TextDocumentItem) {
+        // Lexing a huge generated file would hold up everything else, so only its text is kept
+        if diagnostics::is_too_large(params.text.len(), &self.settings_for(&params.uri)) {
+            let key = params.uri.to_string();
+            if self.oversized.insert(key.clone()) {
+                self.log(LogLevel::Info, format!("{} is over maxFileSizeKb, so it isn't lexed", params.uri)).await;
+            }
+            if self.is_superseded(&key, params.version) {
+                return;
+            }
+            self.cancel_ast_changed(&key);
+            self.lex_failures.remove(&key);
+            self.lex_diagnostics.remove(&key);
+            self.document_map.insert(key.clone(), Rope::from_str(&params.text));
+            self.ast_map.insert(key.clone(), ast::parse(&[]));
+            self.token_map.insert(key.clone(), Vec::new());
+            self.update_usage(usage_key(&params.uri), |file| file.open = Some(completion::Usage::default()));
+            self.semantic_encoding_map.remove(&key);
+            self.folding_range_map.remove(&key);
+            self.document_symbol_map.remove(&key);
+            return;
+        }
+        self.oversized.remove(&params.uri.to_string());
+        let rope = Rope::from_str(&params.text);
+        let (tokens, errors, lex_diagnostics) = match lexer::lex_with(&params.text, LexOptions { include_trivia: true, allow_recovery: true }) {
+            Ok((tokens, errors)) => {
+                self.lex_failures.remove(&params.uri.to_string());
+                let lex_diagnostics = diagnostics::lex_errors(&rope, self.position_encoding(), &errors);
+                (tokens, errors, lex_diagnostics)
+            },
+            Err(failure) => {
+                // Highlighting disappears with the tokens, so say why, once per breakage
+                if self.lex_failures.insert(params.uri.to_string()) {
+                    self.client
+                        .show_message(MessageType::ERROR, format!("Could not read {}: {}", params.uri, failure.message))
+                        .await;
+                }
+                (Vec::new(), Vec::new(), vec![diagnostics::lex_failure(&failure)])
+            }
+        };
+        if errors.is_empty() {
+            self.log(LogLevel::Debug, "Successfully lexed!").await;
+        } else {
+            for error in &errors {
+                self.log(LogLevel::Debug, format!("Failed to lex {:?}: {}", error.range, error.message)).await;
+            }
+        }
+        // Only the part of the tree the edit touched is parsed again
+        let ast = self.guarded("Parsing", || Some(match (self.ast_map.get(&params.uri.to_string()), self.token_map.get(&params.uri.to_string())) {
+            (Some(previous), Some(previous_tokens)) => ast::reparse(&previous, &previous_tokens, &tokens),
+            _ => ast::parse(&tokens)
+        })).await.unwrap_or_else(|| ast::parse(&[]));
+        // A newer version was applied while this one was lexed, and it stores its own results
+        if self.is_superseded(params.uri.as_str(), params.version) {
+            return;
+        }
+        // Whatever was pending for an older version is out of date either way
+        if ast.errors.is_empty() && self.ast_notifications.load(Ordering::SeqCst) {
+            self.schedule_ast_changed(params.uri.clone(), params.version, &ast);
+        } else {
+            self.cancel_ast_changed(params.uri.as_str());
+        }
+        let document = params.uri.to_file_path().ok();
+        let bases = self.include_bases(document.as_deref());
+        let includes = resolved_includes(&ast, document.as_deref(), &bases);
+        // The text and everything made from it are replaced together, with nothing awaited in
+        // between, so no request ever sees the new text with the old tokens
+        let usage = completion::Usage::of(&ast);
+        self.document_map.insert(params.uri.to_string(), rope);
+        self.ast_map.insert(params.uri.to_string(), ast);
+        self.update_usage(usage_key(&params.uri), |file| file.open = Some(usage));
+        self.token_map.insert(params.uri.to_string(), tokens);
+        self.lex_diagnostics.insert(params.uri.to_string(), lex_diagnostics);
+        self.semantic_encoding_map.remove(&params.uri.to_string());
+        self.folding_range_map.remove(&params.uri.to_string());
+        self.document_symbol_map.remove(&params.uri.to_string());
+        // Before anything is validated, which only happens once this returns
+        self.load_resolved_includes(includes, &bases).await;
+    }
+}
+
+/// The server with every request it handles, including the `gtkui/` extensions, ready to be
+/// served over whatever transport the caller has.
+pub fn service() -> (trace::Traced<LspService<Backend>>, ClientSocket) {
+    let mut tracer = None;
+    let (service, socket) = LspService::build(|client| {
+        let backend = Backend::new(client);
+        tracer = Some(backend.tracer.clone());
+        backend
+    })
+        .custom_method("gtkui/effectiveProperties", Backend::effective_properties)
+        .custom_method("gtkui/debugTokensPretty", Backend::debug_tokens_pretty)
+        .custom_method("gtkui/features", Backend::features)
+        .custom_method("gtkui/validateText", Backend::validate_text)
+        .custom_method("gtkui/diagnosticCatalog", Backend::diagnostic_catalog)
+        .custom_method("textDocument/inlayHint", Backend::inlay_hint)
+        .custom_method("inlayHint/resolve", Backend::inlay_hint_resolve)
+        .custom_method("notebookDocument/didOpen", Backend::notebook_did_open)
+        .custom_method("notebookDocument/didChange", Backend::notebook_did_change)
+        .custom_method("notebookDocument/didClose", Backend::notebook_did_close)
+        .custom_method("$/setTrace", Backend::set_trace)
+        .finish();
+    let tracer = tracer.expect("the backend is built along with the service");
+    (trace::Traced::new(service, tracer), socket)
+}
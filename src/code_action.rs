@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use gtk_ui::lexer::{Token, TokenValue};
+use ropey::Rope;
+use gtk_ui::lexer::DirectiveType;
+use tower_lsp::lsp_types::{
+    CodeAction,
+    CodeActionKind,
+    Command,
+    CreateFile,
+    CreateFileOptions,
+    Diagnostic,
+    DocumentChangeOperation,
+    DocumentChanges,
+    OneOf,
+    OptionalVersionedTextDocumentIdentifier,
+    ResourceOp,
+    TextDocumentEdit,
+    TextEdit,
+    Url,
+    WorkspaceEdit
+};
+use crate::ast::{self, Ast, Definition, Directive, Node, Object};
+use crate::diagnostics::DiagnosticCode;
+use crate::formatter::{detect_indent_unit, line_indent, prevailing_indent, reindent};
+use crate::position::{offset_to_position_in, position_to_offset_in, range_to_lsp_in, PositionEncoding};
+
+fn intersects(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+// Widens a range to the whole lines it is on when nothing else shares them
+fn whole_lines(source: &str, range: Range<usize>) -> Range<usize> {
+    let line_start = source[..range.start].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let line_end = source[range.end..].find('\n').map(|index| range.end + index + 1).unwrap_or(source.len());
+    if source[line_start..range.start].trim().is_empty() && source[range.end..line_end].trim().is_empty() {
+        line_start..line_end
+    } else {
+        let start = source[..range.start].trim_end_matches([' ', '\t']).len();
+        start..range.end
+    }
+}
+
+fn single_edit(uri: &Url, range: tower_lsp::lsp_types::Range, new_text: String) -> WorkspaceEdit {
+    WorkspaceEdit {
+        changes: Some(HashMap::from([(uri.clone(), vec![TextEdit { range, new_text }])])),
+        ..Default::default()
+    }
+}
+
+// Finds the innermost list of siblings in which exactly two adjacent objects are selected
+fn selected_siblings<'a>(nodes: &'a [Node], selection: &Range<usize>) -> Option<(&'a Object, &'a Object)> {
+    let selected: Vec<usize> = nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| intersects(node.range(), selection))
+        .map(|(index, _)| index)
+        .collect();
+
+    match selected.as_slice() {
+        [only] => selected_siblings(nodes[*only].children(), selection),
+        [first, second] if *second == first + 1 => match (&nodes[*first], &nodes[*second]) {
+            (Node::Object(first), Node::Object(second)) => Some((first, second)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// Finds the innermost list of siblings in which a run of whole objects, and nothing else, is selected
+fn selected_objects<'a>(nodes: &'a [Node], selection: &Range<usize>) -> Option<&'a [Node]> {
+    let selected: Vec<usize> = nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| intersects(node.range(), selection))
+        .map(|(index, _)| index)
+        .collect();
+    let covered = |node: &Node| selection.start <= node.range().start && node.range().end <= selection.end;
+
+    match selected.as_slice() {
+        [] => None,
+        [only] if !covered(&nodes[*only]) => selected_objects(nodes[*only].children(), selection),
+        [first, .., last] | [first @ last] => {
+            let run = &nodes[*first..=*last];
+            if run.iter().all(|node| matches!(node, Node::Object(_)) && covered(node)) {
+                Some(run)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+pub const WRAP_IN_WIDGET: &str = "gtkui.wrapInWidget";
+
+// What "wrap in widget" offers to wrap in, after the configured container
+const WRAP_CONTAINERS: &[&str] = &["GtkBox", "GtkGrid", "GtkFrame", "GtkOverlay", "GtkScrolledWindow"];
+
+pub fn wrap_containers(configured: &str) -> Vec<String> {
+    let mut containers = vec![configured.to_string()];
+    containers.extend(WRAP_CONTAINERS.iter().filter(|container| **container != configured).map(|container| container.to_string()));
+    containers
+}
+
+// The container is asked for once the action is picked, so the action itself only carries
+// the selection
+pub fn wrap_in_widget(uri: &Url, ast: &Ast, selection: Range<usize>) -> Option<CodeAction> {
+    selected_objects(&ast.nodes, &selection)?;
+    Some(CodeAction {
+        title: String::from("Wrap in widget..."),
+        kind: Some(CodeActionKind::REFACTOR),
+        command: Some(Command {
+            title: String::from("Wrap in widget"),
+            command: WRAP_IN_WIDGET.to_string(),
+            arguments: Some(vec![
+                serde_json::json!(uri),
+                serde_json::json!(selection.start),
+                serde_json::json!(selection.end)
+            ])
+        }),
+        ..Default::default()
+    })
+}
+
+pub fn wrap_in_widget_edit(uri: &Url, source: &str, rope: &Rope, encoding: PositionEncoding, ast: &Ast, selection: Range<usize>, container: &str) -> Option<WorkspaceEdit> {
+    let run = selected_objects(&ast.nodes, &selection)?;
+    let range = run.first()?.range().start..run.last()?.range().end;
+
+    let indent = line_indent(source, range.start);
+    let inner_indent = format!("{}{}", indent, detect_indent_unit(source));
+    let wrapped = reindent(source.get(range.clone())?, indent, &inner_indent);
+    let new_text = format!("{} {{\n{}\n{}}}", container, wrapped, indent);
+    Some(single_edit(uri, range_to_lsp_in(rope, &range, encoding)?, new_text))
+}
+
+pub fn convert_to_child(uri: &Url, source: &str, rope: &Rope, encoding: PositionEncoding, tokens: &[Token], ast: &Ast, selection: Range<usize>) -> Option<CodeAction> {
+    let (parent, child) = selected_siblings(&ast.nodes, &selection)?;
+    let block = parent.block.as_ref()?;
+
+    // Anything between the two objects would be lost when the child is moved
+    let between = parent.range.end..child.range.start;
+    if tokens.iter().any(|token| matches!(token.value, TokenValue::Comment) && intersects(&token.range, &between)) {
+        return None;
+    }
+
+    let parent_indent = line_indent(source, parent.range.start);
+    let child_indent = format!("{}{}", parent_indent, detect_indent_unit(source));
+    let moved = reindent(source.get(child.range.clone())?, line_indent(source, child.range.start), &child_indent);
+
+    // Insert in front of the parent's closing brace, on its own line
+    let close = block.end - 1;
+    let line_start = source[..close].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let (offset, new_text) = if source[line_start..close].trim().is_empty() {
+        (line_start, format!("{}\n", moved))
+    } else {
+        (close, format!("\n{}\n{}", moved, parent_indent))
+    };
+    let insert_at = offset_to_position_in(rope, offset, encoding)?;
+
+    let edits = vec![
+        TextEdit {
+            range: tower_lsp::lsp_types::Range::new(insert_at, insert_at),
+            new_text
+        },
+        TextEdit {
+            range: range_to_lsp_in(rope, &(parent.range.end..child.range.end), encoding)?,
+            new_text: String::new()
+        }
+    ];
+
+    Some(CodeAction {
+        title: format!("Move `{}` into `{}`", child.name, parent.name),
+        kind: Some(CodeActionKind::REFACTOR),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+// Objects with more setters than this keep a line for each
+const COLLAPSE_MAX_SETTERS: usize = 2;
+
+// The innermost object the offset is in
+fn object_at(ast: &Ast, offset: usize) -> Option<&Object> {
+    ast::objects(ast)
+        .into_iter()
+        .filter(|object| object.range.contains(&offset))
+        .min_by_key(|object| object.range.len())
+}
+
+/// Puts a small object that's spread over several lines onto one, as in
+/// `GtkLabel {} .label("Hi")`. Only objects without children and with a value for each of a
+/// few setters qualify, and nothing is offered if a comment would have to go.
+pub fn collapse_to_line(uri: &Url, source: &str, rope: &Rope, encoding: PositionEncoding, tokens: &[Token], ast: &Ast, offset: usize) -> Option<CodeAction> {
+    let object = object_at(ast, offset)?;
+    let text = source.get(object.range.clone())?;
+    if !text.contains('\n') || !object.children.is_empty() || object.setters.len() > COLLAPSE_MAX_SETTERS {
+        return None;
+    }
+    if tokens.iter().any(|token| matches!(token.value, TokenValue::Comment) && intersects(&token.range, &object.range)) {
+        return None;
+    }
+    if ast.errors.iter().any(|error| intersects(&error.range, &object.range)) {
+        return None;
+    }
+
+    let mut new_text = object.name.clone();
+    if !object.arguments.is_empty() {
+        let arguments: Option<Vec<&str>> = object.arguments.iter().map(|argument| source.get(argument.range.clone())).collect();
+        new_text.push_str(&format!("({})", arguments?.join(", ")));
+    }
+    if object.block.is_some() {
+        new_text.push_str(" {}");
+    }
+    for setter in &object.setters {
+        let value = source.get(setter.value.as_ref()?.range.clone())?;
+        new_text.push_str(&format!(" .{}({})", setter.name, value));
+    }
+
+    Some(CodeAction {
+        title: format!("Collapse `{}` onto one line", object.name),
+        kind: Some(CodeActionKind::REFACTOR),
+        edit: Some(single_edit(uri, range_to_lsp_in(rope, &object.range, encoding)?, new_text)),
+        ..Default::default()
+    })
+}
+
+pub fn normalize_indentation(uri: &Url, source: &str, diagnostic: &Diagnostic) -> Option<CodeAction> {
+    if DiagnosticCode::of(diagnostic) != Some(DiagnosticCode::MixedIndentation) {
+        return None;
+    }
+    let (use_tabs, width) = prevailing_indent(source);
+    let line = source.lines().nth(diagnostic.range.start.line as usize)?;
+    let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+
+    // Tabs count up to the next tab stop, a tab being as wide as the document's indent
+    let column = indent.chars().fold(0, |column, c| if c == '\t' { column + width - column % width } else { column + 1 });
+    let new_text = if use_tabs {
+        format!("{}{}", "\t".repeat(column / width), " ".repeat(column % width))
+    } else {
+        " ".repeat(column)
+    };
+
+    let start = tower_lsp::lsp_types::Position::new(diagnostic.range.start.line, 0);
+    let end = tower_lsp::lsp_types::Position::new(diagnostic.range.start.line, indent.chars().count() as u32);
+    Some(CodeAction {
+        title: format!("Indent with {}", if use_tabs { "tabs" } else { "spaces" }),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(single_edit(uri, tower_lsp::lsp_types::Range::new(start, end), new_text)),
+        is_preferred: Some(true),
+        ..Default::default()
+    })
+}
+
+pub fn remove_redundant_setter(uri: &Url, source: &str, rope: &Rope, encoding: PositionEncoding, ast: &Ast, diagnostic: &Diagnostic) -> Option<CodeAction> {
+    if DiagnosticCode::of(diagnostic) != Some(DiagnosticCode::RedundantDefault) {
+        return None;
+    }
+    let (_, setter) = ast::setter_at(ast, position_to_offset_in(rope, diagnostic.range.start, encoding)?)?;
+    let range = range_to_lsp_in(rope, &whole_lines(source, setter.range.clone()), encoding)?;
+
+    Some(CodeAction {
+        title: format!("Remove `.{}`", setter.name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(single_edit(uri, range, String::new())),
+        is_preferred: Some(true),
+        ..Default::default()
+    })
+}
+
+// Extract to file
+
+pub const EXTRACT_TO_FILE: &str = "gtkui.extractToFile";
+
+fn definition_at(ast: &Ast, offset: usize) -> Option<&Definition> {
+    ast.nodes.iter().find_map(|node| match node {
+        Node::Definition(definition) if definition.range.contains(&offset) => Some(definition),
+        _ => None
+    })
+}
+
+fn includes(ast: &Ast) -> impl Iterator<Item = &Directive> {
+    ast.nodes.iter().filter_map(|node| match node {
+        Node::Directive(directive @ Directive { directive_type: DirectiveType::Include, .. }) => Some(directive),
+        _ => None
+    })
+}
+
+// Only definitions can be referred to from another file, objects can't
+pub fn extract_to_file(uri: &Url, ast: &Ast, offset: usize) -> Option<CodeAction> {
+    let definition = definition_at(ast, offset)?;
+    uri.to_file_path().ok()?;
+    Some(CodeAction {
+        title: format!("Extract `{}` to {}.gui", definition.name, definition.name),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        command: Some(Command {
+            title: format!("Extract `{}` to a new file", definition.name),
+            command: EXTRACT_TO_FILE.to_string(),
+            arguments: Some(vec![
+                serde_json::json!(uri),
+                serde_json::json!(definition.range.start)
+            ])
+        }),
+        ..Default::default()
+    })
+}
+
+/// Moves the definition at `offset` into `<Name>.gui` next to the document, which gets the
+/// document's includes so it still sees the same widgets, and includes the new file in its place.
+pub fn extract_to_file_edit(uri: &Url, source: &str, rope: &Rope, encoding: PositionEncoding, ast: &Ast, offset: usize) -> Result<WorkspaceEdit, String> {
+    let definition = definition_at(ast, offset).ok_or_else(|| String::from("there is no definition here"))?;
+    let path = uri.to_file_path().map_err(|_| String::from("the document is not a file"))?;
+    let new_path = path.with_file_name(format!("{}.gui", definition.name));
+    if new_path.exists() {
+        return Err(format!("{} already exists", new_path.display()));
+    }
+    let new_uri = Url::from_file_path(&new_path).map_err(|_| format!("{} is not a valid path", new_path.display()))?;
+    let invalid = || String::from("the definition could not be located in the document");
+
+    let mut contents: Vec<String> = includes(ast)
+        .filter_map(|directive| source.get(directive.range.clone()).map(String::from))
+        .collect();
+    if !contents.is_empty() {
+        contents.push(String::new());
+    }
+    let text = source.get(definition.range.clone()).ok_or_else(invalid)?;
+    contents.push(reindent(text, line_indent(source, definition.range.start), ""));
+    let contents = format!("{}\n", contents.join("\n"));
+
+    // The include goes after the existing ones, or at the very top if there are none
+    let include = format!("#include \"{}.gui\"", definition.name);
+    let (include_at, include_text) = match includes(ast).last() {
+        Some(last) => (last.range.end, format!("\n{}", include)),
+        None => (0, format!("{}\n", include))
+    };
+    let include_at = offset_to_position_in(rope, include_at, encoding).ok_or_else(invalid)?;
+    // Take a blank line after the block along with it so no gap is left behind
+    let mut removed = whole_lines(source, definition.range.clone());
+    if let Some(blank) = source[removed.end..].find('\n').filter(|end| source[removed.end..removed.end + end].trim().is_empty()) {
+        removed.end += blank + 1;
+    }
+    let removed = range_to_lsp_in(rope, &removed, encoding).ok_or_else(invalid)?;
+    let start = tower_lsp::lsp_types::Position::new(0, 0);
+
+    Ok(WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(vec![
+            DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                uri: new_uri.clone(),
+                options: Some(CreateFileOptions {
+                    overwrite: Some(false),
+                    ignore_if_exists: Some(false)
+                }),
+                annotation_id: None
+            })),
+            DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri: new_uri, version: None },
+                edits: vec![OneOf::Left(TextEdit {
+                    range: tower_lsp::lsp_types::Range::new(start, start),
+                    new_text: contents
+                })]
+            }),
+            DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri: uri.clone(), version: None },
+                edits: vec![
+                    OneOf::Left(TextEdit {
+                        range: tower_lsp::lsp_types::Range::new(include_at, include_at),
+                        new_text: include_text
+                    }),
+                    OneOf::Left(TextEdit {
+                        range: removed,
+                        new_text: String::new()
+                    })
+                ]
+            })
+        ])),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use gtk_ui::lexer::{Token, TokenValue};
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    CompletionItem,
+    CompletionItemKind,
+    CompletionList,
+    CompletionResponse,
+    CompletionTextEdit,
+    CompletionTriggerKind,
+    Documentation,
+    InsertTextFormat,
+    MarkupContent,
+    MarkupKind,
+    Position,
+    Range as LspRange,
+    TextEdit
+};
+use crate::ast::{self, Ast, Object};
+use crate::database::{self, WidgetDb};
+use crate::lexer;
+use crate::position::PositionEncoding;
+use crate::references;
+use crate::settings::AutoTrigger;
+
+pub const DIRECTIVES: &[(&str, &str, &str)] = &[
+    ("include", "include \"${1:gtk-4.0}\"", "Includes the definitions of a library or another `.gui` file."),
+    ("header", "header \"${1:xml}\"", "Inserts raw XML at the top of the generated interface.")
+];
+
+// Pango markup, offered inside strings that are parsed as markup
+const MARKUP_TAGS: &[(&str, &str, &str)] = &[
+    ("b", "<b>$0</b>", "Bold"),
+    ("i", "<i>$0</i>", "Italic"),
+    ("u", "<u>$0</u>", "Underline"),
+    ("s", "<s>$0</s>", "Strikethrough"),
+    ("tt", "<tt>$0</tt>", "Monospace"),
+    ("big", "<big>$0</big>", "Larger text"),
+    ("small", "<small>$0</small>", "Smaller text"),
+    ("sub", "<sub>$0</sub>", "Subscript"),
+    ("sup", "<sup>$0</sup>", "Superscript"),
+    ("span", "<span ${1:foreground}=\"${2:red}\">$0</span>", "Text with any attributes")
+];
+
+pub fn trigger_characters(auto_trigger: AutoTrigger) -> Option<Vec<String>> {
+    let characters: &[&str] = match auto_trigger {
+        AutoTrigger::Always => &["#", ".", "("],
+        AutoTrigger::OnTriggerChar => &["#"],
+        AutoTrigger::Manual => return None
+    };
+    Some(characters.iter().map(|character| character.to_string()).collect())
+}
+
+// Trigger characters are registered once, so a setting changed later is enforced here
+pub fn accepts(auto_trigger: AutoTrigger, trigger_kind: Option<CompletionTriggerKind>) -> bool {
+    match auto_trigger {
+        AutoTrigger::Always => true,
+        AutoTrigger::OnTriggerChar => trigger_kind != Some(CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS),
+        AutoTrigger::Manual => matches!(trigger_kind, None | Some(CompletionTriggerKind::INVOKED))
+    }
+}
+
+pub enum CompletionContext {
+    Directive,
+    // Right after a `.` that starts a setter, such as `.marg`
+    Setter,
+    // The first argument of a setter, such as `.visible(tr`
+    Argument(String),
+    // Right after the number in a setter's first argument, such as `.margin-top(12p`
+    Unit(String),
+    // Nothing but a name so far on the line, which inside a block starts either a child
+    // widget or a setter of the widget before it
+    Statement,
+    Other
+}
+
+// Tokens whose text the cursor is in the middle of, where widget and property completions
+// would only get in the way
+pub enum Literal {
+    Comment,
+    String(Range<usize>)
+}
+
+// Whether a string token's text ends with a closing quote, rather than with the end of the
+// line or an escaped quote
+fn is_terminated(text: &str) -> bool {
+    match text.strip_suffix('"') {
+        Some(rest) if !rest.is_empty() => (rest.len() - rest.trim_end_matches('\\').len()) % 2 == 0,
+        _ => false
+    }
+}
+
+// What the text just before the cursor belongs to. A string counts while the cursor is
+// before its closing quote, or anywhere up to its end if there isn't one yet
+pub fn literal_at(rope: &Rope, tokens: &[Token], offset: usize) -> Option<Literal> {
+    let token = lexer::token_at(tokens, offset.checked_sub(1)?)?;
+    match token.value {
+        TokenValue::Comment => Some(Literal::Comment),
+        TokenValue::String(_) => {
+            let terminated = rope.get_byte_slice(token.range.clone()).is_some_and(|text| is_terminated(&text.to_string()));
+            if offset < token.range.end || !terminated {
+                Some(Literal::String(token.range.clone()))
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+// Labels only parse their text as markup when asked to
+pub fn takes_markup(object: &Object, setter: &str) -> bool {
+    match setter {
+        "tooltip-markup" => true,
+        "label" => object.setters.iter().any(|setter| {
+            setter.name == "use-markup" && matches!(setter.value.as_ref().map(|value| &value.value), Some(TokenValue::Bool(1)))
+        }),
+        _ => false
+    }
+}
+
+// `after_angle` when the `<` is already typed
+pub fn markup_completions(after_angle: bool) -> Vec<CompletionItem> {
+    MARKUP_TAGS
+        .iter()
+        .map(|(name, snippet, description)| CompletionItem {
+            label: format!("<{}>", name),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(description.to_string()),
+            filter_text: Some(name.to_string()),
+            insert_text: Some(if after_angle { snippet[1..].to_string() } else { snippet.to_string() }),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// Everything on the cursor's line before the cursor, whose column counts units of `encoding`
+pub fn line_prefix(rope: &Rope, position: Position, encoding: PositionEncoding) -> Option<String> {
+    let line = rope.get_line(position.line as usize)?;
+    let mut units = 0;
+    let end = line
+        .chars()
+        .take_while(|c| {
+            units += encoding.len(*c);
+            units <= position.character as usize
+        })
+        .count();
+    Some(line.slice(..end).to_string())
+}
+
+pub fn context(line_prefix: &str) -> CompletionContext {
+    let trimmed = line_prefix.trim_start();
+    if let Some(name) = trimmed.strip_prefix('#') {
+        if name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return CompletionContext::Directive;
+        }
+    }
+    let is_name = |c: char| c.is_ascii_alphabetic() || c == '-' || c == '_';
+    // The property of a setter whose parentheses were opened right before `text`
+    let setter_before = |text: &str| -> Option<String> {
+        let setter = text.trim_end().strip_suffix('(')?;
+        let property_start = setter.trim_end_matches(is_name);
+        if property_start.len() < setter.len() && property_start.ends_with('.') {
+            Some(setter[property_start.len()..].to_string())
+        } else {
+            None
+        }
+    };
+    let name_start = trimmed.trim_end_matches(is_name);
+    if let Some(property) = setter_before(name_start) {
+        return CompletionContext::Argument(property);
+    }
+    if name_start.ends_with(|c: char| c.is_ascii_digit()) {
+        let number_start = name_start.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        if let Some(property) = setter_before(number_start.strip_suffix('-').unwrap_or(number_start)) {
+            return CompletionContext::Unit(property);
+        }
+    }
+    match name_start.strip_suffix('.') {
+        // A `.` right after a digit is part of a number
+        Some(before) if !before.ends_with(|c: char| c.is_ascii_digit()) => CompletionContext::Setter,
+        _ if name_start.is_empty() => CompletionContext::Statement,
+        _ => CompletionContext::Other
+    }
+}
+
+// The byte range a completion replaces: the whole name the cursor is in or right after, so
+// accepting one in the middle of a word doesn't leave the rest of the old name behind. The
+// `.` of a setter stays
+pub fn name_range(tokens: &[Token], offset: usize) -> Range<usize> {
+    match offset.checked_sub(1).and_then(|before| lexer::token_at(tokens, before)) {
+        Some(Token { value: TokenValue::Setter(_), range }) => range.start + 1..range.end,
+        Some(Token { value: TokenValue::Identifier(_) | TokenValue::Bool(_), range }) => range.clone(),
+        _ => offset..offset
+    }
+}
+
+// A directive that's still being typed is a lex error rather than a token, so its name is
+// found in the text instead
+pub fn directive_name_range(rope: &Rope, offset: usize) -> Option<Range<usize>> {
+    let cursor = rope.try_byte_to_char(offset).ok()?;
+    let start = cursor - rope.chars_at(cursor).reversed().take_while(char::is_ascii_alphabetic).count();
+    let end = cursor + rope.chars_at(cursor).take_while(char::is_ascii_alphabetic).count();
+    Some(rope.char_to_byte(start)..rope.char_to_byte(end))
+}
+
+// The literals the lexer reads as a Bool, with the property's default first
+pub fn bool_completions(default: Option<&str>, range: LspRange) -> Vec<CompletionItem> {
+    let mut literals: Vec<&str> = lexer::BOOL_LITERALS.iter().map(|(text, _)| *text).collect();
+    literals.sort_by_key(|text| Some(*text) != default);
+    literals
+        .into_iter()
+        .map(|text| CompletionItem {
+            label: text.to_string(),
+            kind: Some(CompletionItemKind::VALUE),
+            detail: Some(if Some(text) == default { "Bool (default)" } else { "Bool" }.to_string()),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, text.to_string()))),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// The units a dimension can be given, in the order the database lists them
+pub fn unit_completions(units: &[&str], range: LspRange) -> Vec<CompletionItem> {
+    units
+        .iter()
+        .enumerate()
+        .map(|(index, unit)| CompletionItem {
+            label: unit.to_string(),
+            kind: Some(CompletionItemKind::ENUM_MEMBER),
+            detail: Some("Unit".to_string()),
+            sort_text: Some(format!("{:02}", index)),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, unit.to_string()))),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// Only the name is written when the directive already has its argument
+pub fn directive_completions(range: LspRange, has_argument: bool) -> Vec<CompletionItem> {
+    DIRECTIVES
+        .iter()
+        .map(|(name, snippet, description)| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            detail: Some(format!("#{} directive", name)),
+            documentation: Some(Documentation::String(description.to_string())),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, if has_argument { name.to_string() } else { snippet.to_string() }))),
+            insert_text_format: Some(if has_argument { InsertTextFormat::PLAIN_TEXT } else { InsertTextFormat::SNIPPET }),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// How often each widget and property is used across the workspace, so completion can offer the
+// common ones first
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    widgets: HashMap<String, usize>,
+    properties: HashMap<String, usize>
+}
+
+impl Usage {
+    pub fn count(&mut self, ast: &Ast) {
+        for object in ast::objects(ast) {
+            *self.widgets.entry(object.name.clone()).or_default() += 1;
+            for setter in &object.setters {
+                *self.properties.entry(setter.name.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    // What a single file has, to add to a total or take from it when the file changes
+    pub fn of(ast: &Ast) -> Self {
+        let mut usage = Self::default();
+        usage.count(ast);
+        usage
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        for (name, count) in &other.widgets {
+            *self.widgets.entry(name.clone()).or_default() += count;
+        }
+        for (name, count) in &other.properties {
+            *self.properties.entry(name.clone()).or_default() += count;
+        }
+    }
+
+    pub fn subtract(&mut self, other: &Usage) {
+        fn take(counts: &mut HashMap<String, usize>, other: &HashMap<String, usize>) {
+            for (name, count) in other {
+                if let Some(total) = counts.get_mut(name) {
+                    *total = total.saturating_sub(*count);
+                    if *total == 0 {
+                        counts.remove(name);
+                    }
+                }
+            }
+        }
+        take(&mut self.widgets, &other.widgets);
+        take(&mut self.properties, &other.properties);
+    }
+
+    pub fn widget(&self, name: &str) -> usize {
+        self.widgets.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn property(&self, name: &str) -> usize {
+        self.properties.get(name).copied().unwrap_or(0)
+    }
+}
+
+// Sorts the more used before the less as text, everything alike when nothing is counted
+fn usage_rank(count: usize) -> String {
+    format!("{:010}", u32::MAX as usize - count.min(u32::MAX as usize))
+}
+
+// Every widget that could go in the block as a child, laid out with its arguments and an empty
+// block to carry on in. The more used sort first, each group by name
+pub fn child_completions(db: &WidgetDb, range: LspRange, usage: &Usage) -> Vec<CompletionItem> {
+    let mut widgets: Vec<_> = db.widgets.values().collect();
+    widgets.sort_by(|a, b| a.name.cmp(&b.name));
+    widgets
+        .into_iter()
+        .map(|widget| {
+            let arguments: Vec<String> = widget.properties
+                .iter()
+                .filter(|property| property.is_required())
+                .enumerate()
+                .map(|(index, property)| format!("${{{}:{}}}", index + 1, property.name))
+                .collect();
+            let snippet = if arguments.is_empty() {
+                format!("{} {{\n\t$0\n}}", widget.name)
+            } else {
+                format!("{}({}) {{\n\t$0\n}}", widget.name, arguments.join(", "))
+            };
+            CompletionItem {
+                label: widget.name.clone(),
+                kind: Some(CompletionItemKind::CLASS),
+                detail: Some("Child widget".to_string()),
+                sort_text: Some(format!("{}{}", usage_rank(usage.widget(&widget.name)), widget.name)),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, snippet))),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// All the items when there are at most `max_items`. Otherwise only those matching what's
+/// typed of the name, those starting with it first, cut short and marked incomplete so the
+/// client asks again as more is typed instead of filtering a list that's missing some.
+pub fn capped(items: Vec<CompletionItem>, typed: &str, max_items: usize) -> CompletionResponse {
+    if items.len() <= max_items {
+        return CompletionResponse::Array(items);
+    }
+    let typed_lower = typed.to_lowercase();
+    let mut items: Vec<CompletionItem> = items
+        .into_iter()
+        .filter(|item| references::matches_query(item.filter_text.as_ref().unwrap_or(&item.label), typed))
+        .collect();
+    items.sort_by_cached_key(|item| {
+        let name = item.filter_text.as_ref().unwrap_or(&item.label).to_lowercase();
+        (!name.starts_with(&typed_lower), name)
+    });
+    items.truncate(max_items);
+    CompletionResponse::List(CompletionList {
+        is_incomplete: true,
+        items
+    })
+}
+
+// What the object's setters already set, other than the one being typed at `offset`
+pub fn already_set(object: &Object, offset: usize) -> Vec<&str> {
+    object.setters
+        .iter()
+        .filter(|setter| !(setter.name_range.start..=setter.name_range.end).contains(&offset))
+        .map(|setter| setter.name.as_str())
+        .collect()
+}
+
+// The setters of the widget just before the cursor, written out with their `.` since none has
+// been typed yet
+pub fn trailing_setter_completions(db: &WidgetDb, widget: &str, range: LspRange, set: &[&str], usage: &Usage) -> Vec<CompletionItem> {
+    setter_completions(db, widget, range, false, set, usage)
+        .into_iter()
+        .map(|mut item| {
+            if let Some(CompletionTextEdit::Edit(edit)) = &mut item.text_edit {
+                edit.new_text.insert(0, '.');
+            }
+            item.filter_text = Some(item.label.clone());
+            item.label.insert(0, '.');
+            item
+        })
+        .collect()
+}
+
+// Every property the widget has that isn't `set` already, with what it takes and a few values
+// it could be set to. Required ones sort first, then the more used, each group by name. Only the
+// name is written when the setter already has its arguments
+pub fn setter_completions(db: &WidgetDb, widget: &str, range: LspRange, has_arguments: bool, set: &[&str], usage: &Usage) -> Vec<CompletionItem> {
+    db.effective_properties(widget)
+        .properties
+        .iter()
+        .filter(|(_, property)| !set.contains(&property.name.as_str()))
+        .map(|(source, property)| {
+            let internal_type = property.internal_type.as_ref().map(database::type_name).unwrap_or("unknown");
+            let examples = db.example_values(widget, property);
+            let mut documentation = format!("{} property of `{}`", internal_type, source.name);
+            if let Some(default) = db.default_value(widget, &property.name) {
+                documentation.push_str(&format!("\n\nDefault: `{}`", default));
+            }
+            if !examples.is_empty() {
+                documentation.push_str(&format!("\n\nExamples: `{}`", examples.join(" | ")));
+            }
+            CompletionItem {
+                label: property.name.clone(),
+                kind: Some(CompletionItemKind::PROPERTY),
+                detail: Some(if property.is_required() { format!("{} (required)", internal_type) } else { internal_type.to_string() }),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: documentation
+                })),
+                sort_text: Some(format!("{}{}{}", if property.is_required() { 0 } else { 1 }, usage_rank(usage.property(&property.name)), property.name)),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, if has_arguments {
+                    property.name.clone()
+                } else {
+                    format!("{}(${{1:{}}})", property.name, examples.first().copied().unwrap_or(""))
+                }))),
+                insert_text_format: Some(if has_arguments { InsertTextFormat::PLAIN_TEXT } else { InsertTextFormat::SNIPPET }),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
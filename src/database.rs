@@ -0,0 +1,543 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use gtk_ui::lexer::{
+    Token,
+    TokenValue,
+    DefinitionType as TokenDefinitionType,
+    TypeIdentifierType
+};
+use crate::ast::{Ast, Node};
+use crate::settings::CustomWidget;
+
+// Same place the gtk-ui compiler looks for libraries like "gtk-4.0"
+const LIB_PATH: &str = "/usr/share/gtk-ui/";
+
+// Defaults aren't part of the library definitions, so the common ones are kept here.
+// A widget of "*" applies to any widget that doesn't have a more specific entry.
+const DEFAULTS: &[(&str, &str, &str)] = &[
+    ("*", "visible", "true"),
+    ("*", "sensitive", "true"),
+    ("*", "can-focus", "true"),
+    ("*", "can-target", "true"),
+    ("*", "focus-on-click", "true"),
+    ("*", "has-tooltip", "false"),
+    ("*", "hexpand", "false"),
+    ("*", "vexpand", "false"),
+    ("*", "halign", "\"fill\""),
+    ("*", "valign", "\"fill\""),
+    ("*", "margin-top", "0"),
+    ("*", "margin-bottom", "0"),
+    ("*", "margin-start", "0"),
+    ("*", "margin-end", "0"),
+    ("*", "width-request", "-1"),
+    ("*", "height-request", "-1"),
+    ("*", "opacity", "1"),
+    ("GtkBox", "spacing", "0"),
+    ("GtkBox", "homogeneous", "false"),
+    ("GtkBox", "orientation", "\"horizontal\""),
+    ("GtkGrid", "row-spacing", "0"),
+    ("GtkGrid", "column-spacing", "0"),
+    ("GtkGrid", "row-homogeneous", "false"),
+    ("GtkGrid", "column-homogeneous", "false"),
+    ("GtkLabel", "use-markup", "false"),
+    ("GtkLabel", "use-underline", "false"),
+    ("GtkLabel", "wrap", "false"),
+    ("GtkLabel", "selectable", "false"),
+    ("GtkButton", "has-frame", "true"),
+    ("GtkButton", "use-underline", "false"),
+    ("GtkEntry", "editable", "true"),
+    ("GtkEntry", "visibility", "true"),
+    ("GtkCheckButton", "active", "false"),
+    ("GtkToggleButton", "active", "false"),
+    ("GtkSwitch", "active", "false"),
+    ("GtkWindow", "resizable", "true"),
+    ("GtkWindow", "modal", "false"),
+    ("GtkWindow", "decorated", "true"),
+    ("GtkWindow", "deletable", "true")
+];
+
+// Values a property accepts, for properties that only take a few. Uses the same widget
+// matching as `DEFAULTS`
+const ALLOWED_VALUES: &[(&str, &str, &[&str])] = &[
+    ("*", "halign", &["\"fill\"", "\"start\"", "\"end\"", "\"center\"", "\"baseline\""]),
+    ("*", "valign", &["\"fill\"", "\"start\"", "\"end\"", "\"center\"", "\"baseline\""]),
+    ("*", "overflow", &["\"visible\"", "\"hidden\""]),
+    ("*", "orientation", &["\"horizontal\"", "\"vertical\""]),
+    ("GtkLabel", "justify", &["\"left\"", "\"right\"", "\"center\"", "\"fill\""]),
+    ("GtkLabel", "ellipsize", &["\"none\"", "\"start\"", "\"middle\"", "\"end\""]),
+    ("GtkLabel", "wrap-mode", &["\"word\"", "\"char\"", "\"word-char\""]),
+    ("GtkImage", "icon-size", &["\"inherit\"", "\"normal\"", "\"large\""]),
+    ("GtkStack", "transition-type", &["\"none\"", "\"crossfade\"", "\"slide-right\"", "\"slide-left\"", "\"slide-up\"", "\"slide-down\""]),
+    ("GtkRevealer", "transition-type", &["\"none\"", "\"crossfade\"", "\"slide-right\"", "\"slide-left\"", "\"slide-up\"", "\"slide-down\""])
+];
+
+// Units a dimension's number can be followed by, as in `.margin-top(12px)`. Only names can
+// be units, since only a name lexes as part of the value. Uses the same widget matching as
+// `DEFAULTS`
+const DIMENSION_UNITS: &[(&str, &str, &[&str])] = &[
+    ("*", "margin-top", &["px", "pt", "em"]),
+    ("*", "margin-bottom", &["px", "pt", "em"]),
+    ("*", "margin-start", &["px", "pt", "em"]),
+    ("*", "margin-end", &["px", "pt", "em"]),
+    ("*", "width-request", &["px", "pt", "em"]),
+    ("*", "height-request", &["px", "pt", "em"]),
+    ("GtkBox", "spacing", &["px", "pt", "em"]),
+    ("GtkGrid", "row-spacing", &["px", "pt", "em"]),
+    ("GtkGrid", "column-spacing", &["px", "pt", "em"])
+];
+
+// How many pixels one of each unit comes to at the default scale, where a point is 1/72 of a
+// 96 dpi inch and text is 16px
+const UNIT_PIXELS: &[(&str, f32)] = &[("px", 1.0), ("pt", 96.0 / 72.0), ("em", 16.0)];
+
+// Properties whose value names another widget, which it can only do by the name of its
+// definition since there are no ids. Uses the same widget matching as `DEFAULTS`
+const ID_REFERENCES: &[(&str, &str)] = &[
+    ("GtkLabel", "mnemonic-widget"),
+    ("GtkMenuButton", "popover"),
+    ("GtkWindow", "default-widget"),
+    ("GtkWindow", "focus-widget"),
+    ("GtkPopover", "default-widget")
+];
+
+// The widgets GTK 4 documents, by the name they have in `.gui` files. Anything else, such as a
+// widget a file defines, is documented by the first of these it inherits from
+const GTK_WIDGETS: &[&str] = &[
+    "GtkAboutDialog", "GtkActionBar", "GtkAppChooserButton", "GtkApplicationWindow",
+    "GtkAspectFrame", "GtkAssistant", "GtkBox", "GtkButton", "GtkCalendar", "GtkCenterBox",
+    "GtkCheckButton", "GtkColorButton", "GtkColumnView", "GtkComboBox", "GtkComboBoxText",
+    "GtkDialog", "GtkDragIcon", "GtkDrawingArea", "GtkDropDown", "GtkEditableLabel",
+    "GtkEntry", "GtkExpander", "GtkFileChooserWidget", "GtkFixed", "GtkFlowBox",
+    "GtkFontButton", "GtkFrame", "GtkGLArea", "GtkGrid", "GtkGridView", "GtkHeaderBar",
+    "GtkImage", "GtkInfoBar", "GtkInscription", "GtkLabel", "GtkLevelBar", "GtkLinkButton",
+    "GtkListBox", "GtkListBoxRow", "GtkListView", "GtkLockButton", "GtkMenuButton",
+    "GtkMessageDialog", "GtkNotebook", "GtkOverlay", "GtkPaned", "GtkPasswordEntry",
+    "GtkPicture", "GtkPopover", "GtkPopoverMenu", "GtkPopoverMenuBar", "GtkProgressBar",
+    "GtkRange", "GtkRevealer", "GtkScale", "GtkScaleButton", "GtkScrollbar",
+    "GtkScrolledWindow", "GtkSearchBar", "GtkSearchEntry", "GtkSeparator", "GtkShortcutLabel",
+    "GtkSpinButton", "GtkSpinner", "GtkStack", "GtkStackSidebar", "GtkStackSwitcher",
+    "GtkStatusbar", "GtkSwitch", "GtkText", "GtkTextView", "GtkToggleButton", "GtkTreeExpander",
+    "GtkTreeView", "GtkVideo", "GtkViewport", "GtkVolumeButton", "GtkWidget", "GtkWindow",
+    "GtkWindowControls", "GtkWindowHandle"
+];
+
+// A widget and property, then the releases it was added, deprecated and removed in
+type PropertyVersions = (&'static str, &'static str, Option<GtkVersion>, Option<GtkVersion>, Option<GtkVersion>);
+
+// When properties that weren't in every release since GTK 3.0 came and went. Uses the same
+// widget matching as `DEFAULTS`
+const PROPERTY_VERSIONS: &[PropertyVersions] = &[
+    ("*", "margin-start", Some(GtkVersion(3, 12)), None, None),
+    ("*", "margin-end", Some(GtkVersion(3, 12)), None, None),
+    ("*", "margin-left", None, Some(GtkVersion(3, 12)), Some(GtkVersion(4, 0))),
+    ("*", "margin-right", None, Some(GtkVersion(3, 12)), Some(GtkVersion(4, 0))),
+    ("*", "focus-on-click", Some(GtkVersion(3, 20)), None, None),
+    ("*", "app-paintable", None, Some(GtkVersion(3, 14)), Some(GtkVersion(4, 0))),
+    ("*", "no-show-all", None, None, Some(GtkVersion(4, 0))),
+    ("*", "can-target", Some(GtkVersion(4, 0)), None, None),
+    ("*", "focusable", Some(GtkVersion(4, 0)), None, None),
+    ("*", "overflow", Some(GtkVersion(4, 0)), None, None),
+    ("*", "css-classes", Some(GtkVersion(4, 0)), None, None),
+    ("*", "cursor", Some(GtkVersion(4, 0)), None, None),
+    ("*", "layout-manager", Some(GtkVersion(4, 0)), None, None),
+    ("GtkBox", "baseline-position", Some(GtkVersion(3, 10)), None, None),
+    ("GtkBox", "baseline-child", Some(GtkVersion(4, 12)), None, None),
+    ("GtkButton", "has-frame", Some(GtkVersion(4, 0)), None, None),
+    ("GtkButton", "relief", None, None, Some(GtkVersion(4, 0))),
+    ("GtkButton", "image", None, None, Some(GtkVersion(4, 0))),
+    ("GtkCheckButton", "group", Some(GtkVersion(4, 0)), None, None),
+    ("GtkLabel", "natural-wrap-mode", Some(GtkVersion(4, 6)), None, None),
+    ("GtkLabel", "tabs", Some(GtkVersion(4, 8)), None, None),
+    ("GtkMenuButton", "always-show-arrow", Some(GtkVersion(4, 4)), None, None),
+    ("GtkMenuButton", "primary", Some(GtkVersion(4, 4)), None, None),
+    ("GtkMenuButton", "child", Some(GtkVersion(4, 6)), None, None),
+    ("GtkMenuButton", "active", Some(GtkVersion(4, 10)), None, None),
+    ("GtkMenuButton", "can-shrink", Some(GtkVersion(4, 12)), None, None),
+    ("GtkPicture", "content-fit", Some(GtkVersion(4, 8)), None, None),
+    ("GtkPicture", "keep-aspect-ratio", None, Some(GtkVersion(4, 8)), None),
+    ("GtkScrolledWindow", "propagate-natural-width", Some(GtkVersion(3, 22)), None, None),
+    ("GtkScrolledWindow", "propagate-natural-height", Some(GtkVersion(3, 22)), None, None),
+    ("GtkSearchEntry", "search-delay", Some(GtkVersion(4, 8)), None, None),
+    ("GtkSearchEntry", "placeholder-text", Some(GtkVersion(4, 10)), None, None),
+    ("GtkSwitch", "state", Some(GtkVersion(3, 14)), None, None),
+    ("GtkWindow", "handle-menubar-accel", Some(GtkVersion(4, 2)), None, None),
+    ("GtkWindow", "titlebar", Some(GtkVersion(4, 6)), None, None),
+    ("GtkWindow", "suspended", Some(GtkVersion(4, 12)), None, None)
+];
+
+// A GTK release by its major and minor version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GtkVersion(pub u32, pub u32);
+
+impl GtkVersion {
+    // As the `gtkVersion` setting has it: "4.10", or just "4" for 4.0
+    pub fn parse(text: &str) -> Option<Self> {
+        let (major, minor) = text.trim().split_once('.').unwrap_or((text.trim(), "0"));
+        Some(Self(major.parse().ok()?, minor.parse().ok()?))
+    }
+}
+
+impl std::fmt::Display for GtkVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.0, self.1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Availability {
+    pub added: Option<GtkVersion>,
+    pub deprecated: Option<GtkVersion>,
+    pub removed: Option<GtkVersion>
+}
+
+// What a property is in one particular release
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Available,
+    NotYet(GtkVersion),
+    Deprecated(GtkVersion),
+    Removed(GtkVersion)
+}
+
+impl Availability {
+    pub fn in_version(&self, target: GtkVersion) -> Support {
+        match (self.added, self.deprecated, self.removed) {
+            (Some(added), _, _) if target < added => Support::NotYet(added),
+            (_, _, Some(removed)) if removed <= target => Support::Removed(removed),
+            (_, Some(deprecated), _) if deprecated <= target => Support::Deprecated(deprecated),
+            _ => Support::Available
+        }
+    }
+
+    // For hover: when the property came and went, and what that means for `target`
+    pub fn describe(&self, target: Option<GtkVersion>) -> String {
+        let mut notes = Vec::new();
+        if let Some(added) = self.added {
+            notes.push(format!("Added in GTK {}.", added));
+        }
+        if let Some(deprecated) = self.deprecated {
+            notes.push(format!("Deprecated in GTK {}.", deprecated));
+        }
+        if let Some(removed) = self.removed {
+            notes.push(format!("Removed in GTK {}.", removed));
+        }
+        match target.map(|target| (target, self.in_version(target))) {
+            Some((target, Support::NotYet(_) | Support::Removed(_))) => notes.push(format!("Not available in GTK {}, which the project targets.", target)),
+            Some((target, Support::Deprecated(_))) => notes.push(format!("Deprecated in GTK {}, which the project targets.", target)),
+            _ => ()
+        }
+        notes.join(" ")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertyInfo {
+    pub name: String,
+    pub definition_type: TokenDefinitionType,
+    pub internal_type: Option<TypeIdentifierType>
+}
+
+impl PropertyInfo {
+    // Arguments go in the parentheses after the widget's name, and gtk-ui won't generate an
+    // object that leaves one out. Properties can always be left to their defaults
+    pub fn is_required(&self) -> bool {
+        matches!(self.definition_type, TokenDefinitionType::InlineArg | TokenDefinitionType::ChildArg)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WidgetInfo {
+    pub name: String,
+    pub inherits: Vec<String>,
+    pub properties: Vec<PropertyInfo>
+}
+
+// Every property a widget has, nearest definition first. A property defined again further
+// up the chain is shadowed by the nearer one
+#[derive(Debug)]
+pub struct EffectiveProperties<'a> {
+    pub properties: Vec<(&'a WidgetInfo, &'a PropertyInfo)>,
+    // The inheritance chain from the widget up to the first widget seen twice on it
+    pub cycle: Option<Vec<String>>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WidgetDb {
+    pub widgets: HashMap<String, WidgetInfo>
+}
+
+impl WidgetDb {
+    pub fn add_definitions(&mut self, ast: &Ast) {
+        for node in &ast.nodes {
+            if let Node::Definition(definition) = node {
+                let properties = definition.children
+                    .iter()
+                    .filter_map(|child| match child {
+                        Node::Property(property) => Some(PropertyInfo {
+                            name: property.name.clone()?,
+                            definition_type: property.definition_type.clone(),
+                            internal_type: property.internal_type.clone()
+                        }),
+                        _ => None
+                    })
+                    .collect();
+                self.widgets.insert(definition.name.clone(), WidgetInfo {
+                    name: definition.name.clone(),
+                    inherits: definition.inherits.iter().map(|(name, _)| name.clone()).collect(),
+                    properties
+                });
+            }
+        }
+    }
+
+    // Properties of custom widgets are set like any inline property
+    pub fn add_custom_widgets(&mut self, widgets: &BTreeMap<String, CustomWidget>) {
+        for (name, widget) in widgets {
+            let properties = widget.properties
+                .iter()
+                .map(|(property, internal_type)| PropertyInfo {
+                    name: property.clone(),
+                    definition_type: TokenDefinitionType::InlineProp,
+                    internal_type: match internal_type.as_str() {
+                        "String" => Some(TypeIdentifierType::String),
+                        "Number" => Some(TypeIdentifierType::Number),
+                        "Bool" => Some(TypeIdentifierType::Bool),
+                        _ => None
+                    }
+                })
+                .collect();
+            self.widgets.insert(name.clone(), WidgetInfo {
+                name: name.clone(),
+                inherits: widget.inherits.clone(),
+                properties
+            });
+        }
+    }
+
+    // Changes whenever a widget, what it inherits or one of its properties does
+    pub fn fingerprint(&self) -> u64 {
+        let mut names: Vec<&String> = self.widgets.keys().collect();
+        names.sort();
+        let mut hasher = DefaultHasher::new();
+        for name in names {
+            let widget = &self.widgets[name];
+            (name, &widget.inherits).hash(&mut hasher);
+            for property in &widget.properties {
+                property.name.hash(&mut hasher);
+                property_kind(property).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    // The widget itself followed by its ancestors, nearest first, each visited once
+    pub fn ancestry<'a>(&'a self, name: &'a str) -> Vec<&'a str> {
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        let mut queue = vec![name];
+        while !queue.is_empty() {
+            let current = queue.remove(0);
+            if !visited.insert(current) {
+                continue;
+            }
+            order.push(current);
+            if let Some(widget) = self.widgets.get(current) {
+                queue.extend(widget.inherits.iter().map(|parent| parent.as_str()));
+            }
+        }
+        order
+    }
+
+    pub fn inheritance_cycle(&self, name: &str) -> Option<Vec<String>> {
+        fn visit(db: &WidgetDb, name: &str, path: &mut Vec<String>, done: &mut HashSet<String>) -> Option<Vec<String>> {
+            if path.iter().any(|seen| seen == name) {
+                let mut cycle = path.clone();
+                cycle.push(name.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(name.to_string()) {
+                return None;
+            }
+            path.push(name.to_string());
+            if let Some(widget) = db.widgets.get(name) {
+                for parent in &widget.inherits {
+                    if let Some(cycle) = visit(db, parent, path, done) {
+                        return Some(cycle);
+                    }
+                }
+            }
+            path.pop();
+            None
+        }
+        visit(self, name, &mut Vec::new(), &mut HashSet::new())
+    }
+
+    pub fn effective_properties<'a>(&'a self, name: &'a str) -> EffectiveProperties<'a> {
+        let mut properties: Vec<(&WidgetInfo, &PropertyInfo)> = Vec::new();
+        for widget in self.ancestry(name).into_iter().filter_map(|name| self.widgets.get(name)) {
+            for property in &widget.properties {
+                if !properties.iter().any(|(_, existing)| existing.name == property.name) {
+                    properties.push((widget, property));
+                }
+            }
+        }
+        EffectiveProperties {
+            properties,
+            cycle: self.inheritance_cycle(name)
+        }
+    }
+
+    pub fn property<'a>(&'a self, widget: &'a str, property: &str) -> Option<(&'a WidgetInfo, &'a PropertyInfo)> {
+        self.effective_properties(widget)
+            .properties
+            .into_iter()
+            .find(|(_, info)| info.name == property)
+    }
+
+    pub fn allowed_values(&self, widget: &str, property: &str) -> Option<&'static [&'static str]> {
+        self.ancestry(widget)
+            .into_iter()
+            .chain(std::iter::once("*"))
+            .find_map(|name| {
+                ALLOWED_VALUES.iter().find(|(widget, key, _)| *widget == name && *key == property).map(|(_, _, values)| *values)
+            })
+    }
+
+    pub fn is_id_reference(&self, widget: &str, property: &str) -> bool {
+        self.ancestry(widget)
+            .into_iter()
+            .chain(std::iter::once("*"))
+            .any(|name| ID_REFERENCES.iter().any(|(widget, key)| *widget == name && *key == property))
+    }
+
+    // None for properties that take plain numbers, or no numbers at all
+    pub fn dimension_units(&self, widget: &str, property: &str) -> Option<&'static [&'static str]> {
+        self.ancestry(widget)
+            .into_iter()
+            .chain(std::iter::once("*"))
+            .find_map(|name| {
+                DIMENSION_UNITS.iter().find(|(widget, key, _)| *widget == name && *key == property).map(|(_, _, units)| *units)
+            })
+    }
+
+    // Values to show as an example: the allowed ones if there's a fixed set, otherwise the
+    // default followed by a representative sample of the type
+    pub fn example_values(&self, widget: &str, property: &PropertyInfo) -> Vec<&'static str> {
+        if let Some(values) = self.allowed_values(widget, &property.name) {
+            return values.to_vec();
+        }
+        let mut examples: Vec<&'static str> = self.default_value(widget, &property.name).into_iter().collect();
+        let samples: &[&'static str] = match property.internal_type {
+            Some(TypeIdentifierType::Bool) => &["true", "false"],
+            Some(TypeIdentifierType::Number) => &["0", "12"],
+            Some(TypeIdentifierType::String) => &["\"text\""],
+            None => &[]
+        };
+        for sample in samples {
+            if !examples.contains(sample) {
+                examples.push(sample);
+            }
+        }
+        examples
+    }
+
+    // The GTK widget whose documentation covers `widget`, itself if it's one
+    pub fn documented_widget(&self, widget: &str) -> Option<&'static str> {
+        self.ancestry(widget)
+            .into_iter()
+            .find_map(|name| GTK_WIDGETS.iter().find(|known| **known == name).copied())
+    }
+
+    // None for properties every release has had
+    pub fn availability(&self, widget: &str, property: &str) -> Option<Availability> {
+        self.ancestry(widget)
+            .into_iter()
+            .chain(std::iter::once("*"))
+            .find_map(|name| {
+                PROPERTY_VERSIONS.iter().find(|(widget, key, ..)| *widget == name && *key == property).map(|(_, _, added, deprecated, removed)| Availability {
+                    added: *added,
+                    deprecated: *deprecated,
+                    removed: *removed
+                })
+            })
+    }
+
+    pub fn default_value(&self, widget: &str, property: &str) -> Option<&'static str> {
+        self.ancestry(widget)
+            .into_iter()
+            .chain(std::iter::once("*"))
+            .find_map(|name| {
+                DEFAULTS.iter().find(|(widget, key, _)| *widget == name && *key == property).map(|(_, _, value)| *value)
+            })
+    }
+}
+
+// Fills in a `docsUrlTemplate` for one of `GTK_WIDGETS`, which the docs name without `Gtk`
+pub fn docs_url(template: &str, widget: &str) -> String {
+    template.replace("{widget}", widget.strip_prefix("Gtk").unwrap_or(widget))
+}
+
+// Writes a value the way it would appear in a `.gui` file
+pub fn literal(token: &Token) -> Option<String> {
+    match &token.value {
+        TokenValue::Bool(value) => Some(String::from(if *value != 0 { "true" } else { "false" })),
+        TokenValue::Number(value) => Some(value.to_string()),
+        TokenValue::String(value) => Some(format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))),
+        _ => None
+    }
+}
+
+// What a dimension comes to in each of the other units, like `16px` for `12pt`. None for a
+// unit that isn't in the table, whose size depends on something the file doesn't say
+pub fn unit_conversions(value: f32, unit: &str) -> Option<Vec<String>> {
+    let (_, pixels) = UNIT_PIXELS.iter().find(|(name, _)| *name == unit)?;
+    Some(UNIT_PIXELS
+        .iter()
+        .filter(|(name, _)| *name != unit)
+        .map(|(name, size)| {
+            let converted = format!("{:.2}", value * pixels / size);
+            format!("{}{}", converted.trim_end_matches('0').trim_end_matches('.'), name)
+        })
+        .collect())
+}
+
+pub fn type_name(internal_type: &TypeIdentifierType) -> &'static str {
+    match internal_type {
+        TypeIdentifierType::String => "String",
+        TypeIdentifierType::Number => "Number",
+        TypeIdentifierType::Bool => "Bool"
+    }
+}
+
+// A property's kind and type as plain strings, since the gtk_ui types can't be hashed
+fn property_kind(property: &PropertyInfo) -> (&'static str, Option<&'static str>) {
+    (definition_kind(&property.definition_type), property.internal_type.as_ref().map(type_name))
+}
+
+pub fn definition_kind(definition_type: &TokenDefinitionType) -> &'static str {
+    match definition_type {
+        TokenDefinitionType::InlineProp => "inlineProp",
+        TokenDefinitionType::InlineArg => "inlineArg",
+        TokenDefinitionType::ChildProp => "childProp",
+        TokenDefinitionType::ChildArg => "childArg",
+        TokenDefinitionType::Object(_) => "object"
+    }
+}
+
+// Where an `#include` may point, in the order they are tried: the installed libraries, next to
+// the document, then under each of the `bases` in turn
+pub fn include_candidates(document: Option<&Path>, path: &str, bases: &[PathBuf]) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(format!("{}{}.gui", LIB_PATH, path))];
+    let directory = document.and_then(|document| document.parent());
+    for base in directory.into_iter().chain(bases.iter().map(PathBuf::as_path)) {
+        candidates.push(base.join(path));
+        candidates.push(base.join(format!("{}.gui", path)));
+    }
+    candidates
+}
+
+pub fn resolve_include(document: Option<&Path>, path: &str, bases: &[PathBuf]) -> Option<PathBuf> {
+    include_candidates(document, path, bases).into_iter().find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,497 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use gtk_ui::lexer::DirectiveType;
+use ropey::Rope;
+use tower_lsp::lsp_types::{CodeDescription, Diagnostic, DiagnosticSeverity, DiagnosticTag, NumberOrString, Position, Range, Url};
+use crate::ast::{self, Ast, Node, Recovery};
+use crate::database::{self, GtkVersion, Support, WidgetDb};
+use crate::formatter::prevailing_indent;
+use crate::lexer::{self, LexError, LexFailure, LexOptions};
+use crate::position::{offset_to_position_in, range_to_lsp_in, PositionEncoding};
+use crate::settings::Settings;
+
+pub const SOURCE: &str = "gtkui";
+
+// Where every code is explained, one heading per code
+const DOCS_URL: &str = "https://github.com/GtkUI/language-server/blob/main/docs/diagnostics.md";
+
+// Every kind of diagnostic the server reports. The string form is what goes out as
+// `Diagnostic.code`, so it must never change once released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    RedundantDefault,
+    MixedIndentation,
+    UnknownProperty,
+    ParserRecovery,
+    DeepNesting,
+    MissingArguments,
+    PropertyOutsideWidget,
+    PropertyVersion,
+    FileTooLarge,
+    UnresolvedInclude,
+    LexError
+}
+
+impl DiagnosticCode {
+    pub const ALL: [DiagnosticCode; 11] = [
+        Self::RedundantDefault,
+        Self::MixedIndentation,
+        Self::UnknownProperty,
+        Self::ParserRecovery,
+        Self::DeepNesting,
+        Self::MissingArguments,
+        Self::PropertyOutsideWidget,
+        Self::PropertyVersion,
+        Self::FileTooLarge,
+        Self::UnresolvedInclude,
+        Self::LexError
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RedundantDefault => "redundant-default",
+            Self::MixedIndentation => "mixed-indentation",
+            Self::UnknownProperty => "unknown-property",
+            Self::ParserRecovery => "parser-recovery",
+            Self::DeepNesting => "deep-nesting",
+            Self::MissingArguments => "missing-arguments",
+            Self::PropertyOutsideWidget => "property-outside-widget",
+            Self::PropertyVersion => "property-version",
+            Self::FileTooLarge => "file-too-large",
+            Self::UnresolvedInclude => "unresolved-include",
+            Self::LexError => "lex-error"
+        }
+    }
+
+    // What gtkui/diagnosticCatalog describes each code as
+    pub fn summary(self) -> &'static str {
+        match self {
+            Self::RedundantDefault => "A setter sets a property to the value it has by default",
+            Self::MixedIndentation => "A line is indented differently from the rest of the file",
+            Self::UnknownProperty => "A setter names a property the widget doesn't have",
+            Self::ParserRecovery => "The parser skipped a token or assumed a missing `}` or `)`",
+            Self::DeepNesting => "A widget is nested deeper than the configured maximum",
+            Self::MissingArguments => "An object leaves out arguments its definition declares",
+            Self::PropertyOutsideWidget => "A setter isn't inside any widget",
+            Self::PropertyVersion => "A setter's property isn't in the targeted GTK version, or is deprecated in it",
+            Self::FileTooLarge => "The file is over the size the server reads in full",
+            Self::UnresolvedInclude => "An `include` names a file that can't be found",
+            Self::LexError => "Part of the text couldn't be read as tokens"
+        }
+    }
+
+    pub fn severity(self) -> DiagnosticSeverity {
+        match self {
+            Self::UnknownProperty | Self::MissingArguments | Self::PropertyOutsideWidget | Self::LexError => DiagnosticSeverity::ERROR,
+            Self::PropertyVersion | Self::UnresolvedInclude => DiagnosticSeverity::WARNING,
+            Self::RedundantDefault | Self::MixedIndentation | Self::ParserRecovery | Self::DeepNesting => DiagnosticSeverity::HINT,
+            Self::FileTooLarge => DiagnosticSeverity::INFORMATION
+        }
+    }
+
+    // The setting that turns the code on or off, if there is one
+    pub fn setting(self) -> Option<&'static str> {
+        match self {
+            Self::RedundantDefault => Some("gtkui-language-server.diagnostics.redundantDefaults"),
+            Self::MixedIndentation => Some("gtkui-language-server.diagnostics.mixedIndentation"),
+            Self::UnknownProperty => Some("gtkui-language-server.diagnostics.unknownProperties"),
+            Self::ParserRecovery => Some("gtkui-language-server.diagnostics.parserRecovery"),
+            Self::DeepNesting => Some("gtkui-language-server.diagnostics.maxNestingDepth"),
+            Self::MissingArguments => Some("gtkui-language-server.diagnostics.missingArguments"),
+            Self::PropertyOutsideWidget => Some("gtkui-language-server.diagnostics.propertiesOutsideWidgets"),
+            Self::PropertyVersion => Some("gtkui-language-server.gtkVersion"),
+            Self::FileTooLarge => Some("gtkui-language-server.maxFileSizeKb"),
+            Self::UnresolvedInclude => Some("gtkui-language-server.diagnostics.unresolvedIncludes"),
+            // Always reported, since whatever the lexer skips is missing from everything else
+            Self::LexError => None
+        }
+    }
+
+    // Only our own diagnostics count, whatever code another server gave its own
+    pub fn of(diagnostic: &Diagnostic) -> Option<Self> {
+        if diagnostic.source.as_deref() != Some(SOURCE) {
+            return None;
+        }
+        match &diagnostic.code {
+            Some(NumberOrString::String(code)) => Self::ALL.into_iter().find(|known| known.as_str() == code),
+            _ => None
+        }
+    }
+
+    pub fn description(self) -> Option<CodeDescription> {
+        Url::parse(&format!("{}#{}", DOCS_URL, self.as_str())).ok().map(|href| CodeDescription { href })
+    }
+}
+
+fn diagnostic(code: DiagnosticCode, range: Range, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(code.severity()),
+        code: Some(NumberOrString::String(code.as_str().to_string())),
+        code_description: code.description(),
+        source: Some(SOURCE.to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Whether `text` is past `settings.max_file_size_kb`, in which case it's neither lexed nor
+/// validated and gets only the diagnostic `file_too_large` makes.
+pub fn is_too_large(text_len: usize, settings: &Settings) -> bool {
+    text_len > settings.max_file_size_kb.saturating_mul(1024)
+}
+
+// Sits at the very start, since nothing past that has been read
+pub fn file_too_large(text_len: usize, settings: &Settings) -> Diagnostic {
+    diagnostic(
+        DiagnosticCode::FileTooLarge,
+        Range::default(),
+        format!(
+            "This file is {} KB, over the {} KB `maxFileSizeKb` allows, so it isn't highlighted, checked or outlined",
+            text_len.div_ceil(1024),
+            settings.max_file_size_kb
+        )
+    )
+}
+
+// What the lexer skipped, each where it was skipped
+pub fn lex_errors(rope: &Rope, encoding: PositionEncoding, errors: &[LexError]) -> Vec<Diagnostic> {
+    errors
+        .iter()
+        .filter_map(|error| Some(diagnostic(DiagnosticCode::LexError, range_to_lsp_in(rope, &error.range, encoding)?, error.message.clone())))
+        .collect()
+}
+
+// A lexer that gave up has no place to point at, so this sits at the start
+pub fn lex_failure(failure: &LexFailure) -> Diagnostic {
+    diagnostic(DiagnosticCode::LexError, Range::default(), format!("This file couldn't be read: {}", failure.message))
+}
+
+// Each `include` that `resolves` can't find a file for, at its argument. Apart from the rest
+// since it needs the file system, which the other rules never touch
+pub fn unresolved_includes(rope: &Rope, encoding: PositionEncoding, ast: &Ast, resolves: impl Fn(&str) -> bool) -> Vec<Diagnostic> {
+    ast.nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Directive(ast::Directive { directive_type: DirectiveType::Include, argument: Some((path, range)), .. }) if !resolves(path) => {
+                Some(diagnostic(
+                    DiagnosticCode::UnresolvedInclude,
+                    range_to_lsp_in(rope, range, encoding)?,
+                    format!("Can't find `{}` next to this file, among the installed libraries or under `importBasePaths`", path)
+                ))
+            },
+            _ => None
+        })
+        .collect()
+}
+
+fn redundant_defaults(rope: &Rope, encoding: PositionEncoding, nodes: &[Node], db: &WidgetDb, diagnostics: &mut Vec<Diagnostic>) {
+    for object in ast::objects_in(nodes) {
+        for setter in &object.setters {
+            let value = match setter.value.as_ref().and_then(database::literal) {
+                Some(value) => value,
+                None => continue
+            };
+            if db.default_value(&object.name, &setter.name) != Some(value.as_str()) {
+                continue;
+            }
+            if let Some(range) = range_to_lsp_in(rope, &setter.range, encoding) {
+                diagnostics.push(Diagnostic {
+                    tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    ..diagnostic(
+                        DiagnosticCode::RedundantDefault,
+                        range,
+                        format!("`{}` is already `{}` by default", setter.name, value)
+                    )
+                });
+            }
+        }
+    }
+}
+
+// A setter may name a property of the widget or of anything it inherits from. Widgets
+// whose chain isn't fully known, say because an include is missing, could have any property
+fn unknown_properties(rope: &Rope, encoding: PositionEncoding, nodes: &[Node], db: &WidgetDb, diagnostics: &mut Vec<Diagnostic>) {
+    for object in ast::objects_in(nodes) {
+        if !db.ancestry(&object.name).iter().all(|name| db.widgets.contains_key(*name)) {
+            continue;
+        }
+        for setter in &object.setters {
+            if db.property(&object.name, &setter.name).is_some() {
+                continue;
+            }
+            if let Some(range) = range_to_lsp_in(rope, &setter.name_range, encoding) {
+                diagnostics.push(diagnostic(
+                    DiagnosticCode::UnknownProperty,
+                    range,
+                    format!("`{}` has no property `{}`", object.name, setter.name)
+                ));
+            }
+        }
+    }
+}
+
+// The arguments an object passes fill its definition's own `@InlineArg`s and `@ChildArg`s in
+// order, and gtk-ui won't generate it with any left out. Inherited ones aren't asked for
+fn missing_arguments(rope: &Rope, encoding: PositionEncoding, nodes: &[Node], db: &WidgetDb, diagnostics: &mut Vec<Diagnostic>) {
+    for object in ast::objects_in(nodes) {
+        let widget = match db.widgets.get(&object.name) {
+            Some(widget) => widget,
+            None => continue
+        };
+        let missing: Vec<String> = widget.properties
+            .iter()
+            .filter(|property| property.is_required())
+            .skip(object.arguments.len())
+            .map(|property| format!("`{}`", property.name))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        if let Some(range) = range_to_lsp_in(rope, &object.name_range, encoding) {
+            diagnostics.push(diagnostic(
+                DiagnosticCode::MissingArguments,
+                range,
+                format!(
+                    "`{}` is missing the required {} {}",
+                    object.name,
+                    if missing.len() == 1 { "argument" } else { "arguments" },
+                    missing.join(", ")
+                )
+            ));
+        }
+    }
+}
+
+fn deep_nesting(rope: &Rope, encoding: PositionEncoding, nodes: &[Node], max_depth: usize, diagnostics: &mut Vec<Diagnostic>) {
+    for object in ast::objects_in(nodes).into_iter().filter(|object| object.depth > max_depth) {
+        if let Some(range) = range_to_lsp_in(rope, &object.name_range, encoding) {
+            diagnostics.push(diagnostic(
+                DiagnosticCode::DeepNesting,
+                range,
+                format!("`{}` is nested {} levels deep. Consider extracting part of this tree into a definition of its own", object.name, object.depth)
+            ));
+        }
+    }
+}
+
+fn property_versions(rope: &Rope, encoding: PositionEncoding, nodes: &[Node], db: &WidgetDb, target: GtkVersion, diagnostics: &mut Vec<Diagnostic>) {
+    for object in ast::objects_in(nodes) {
+        for setter in &object.setters {
+            let support = match db.availability(&object.name, &setter.name) {
+                Some(availability) => availability.in_version(target),
+                None => continue
+            };
+            let (message, tags) = match support {
+                Support::Available => continue,
+                Support::NotYet(added) => (format!("`{}` was added in GTK {}, after the targeted {}", setter.name, added, target), None),
+                Support::Removed(removed) => (format!("`{}` was removed in GTK {}, so the targeted {} doesn't have it", setter.name, removed, target), None),
+                Support::Deprecated(deprecated) => (format!("`{}` is deprecated since GTK {}", setter.name, deprecated), Some(vec![DiagnosticTag::DEPRECATED]))
+            };
+            if let Some(range) = range_to_lsp_in(rope, &setter.name_range, encoding) {
+                diagnostics.push(Diagnostic { tags, ..diagnostic(DiagnosticCode::PropertyVersion, range, message) });
+            }
+        }
+    }
+}
+
+// Works on the raw lines, since whitespace never makes it into the tokens
+fn mixed_indentation(rope: &Rope, diagnostics: &mut Vec<Diagnostic>) {
+    let (use_tabs, _) = prevailing_indent(&rope.to_string());
+    for (line, text) in rope.lines().enumerate() {
+        let indent = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        if indent == text.chars().filter(|c| *c != '\n' && *c != '\r').count() {
+            continue;
+        }
+        let leading = text.slice(..indent);
+        let message = match (leading.chars().any(|c| c == '\t'), leading.chars().any(|c| c == ' ')) {
+            (true, true) => "Indentation mixes tabs and spaces",
+            (true, false) if !use_tabs => "Indented with tabs, but the rest of the file uses spaces",
+            (false, true) if use_tabs => "Indented with spaces, but the rest of the file uses tabs",
+            _ => continue
+        };
+        diagnostics.push(diagnostic(
+            DiagnosticCode::MixedIndentation,
+            Range::new(Position::new(line as u32, 0), Position::new(line as u32, indent as u32)),
+            message.to_string()
+        ));
+    }
+}
+
+fn parser_recoveries(rope: &Rope, encoding: PositionEncoding, ast: &Ast, diagnostics: &mut Vec<Diagnostic>) {
+    for recovery in &ast.recoveries {
+        let (range, message) = match recovery {
+            Recovery::Skipped(range) => {
+                let text = rope.get_byte_slice(range.clone()).map(|text| text.to_string()).unwrap_or_default();
+                (range.clone(), format!("Skipped `{}` while recovering from an error", text))
+            },
+            Recovery::Inserted(text, offset) => (*offset..*offset, format!("Assumed a missing `{}` here", text))
+        };
+        if let Some(range) = range_to_lsp_in(rope, &range, encoding) {
+            diagnostics.push(diagnostic(DiagnosticCode::ParserRecovery, range, message));
+        }
+    }
+}
+
+// A setter the parser found no widget for, such as one at the top level
+fn stray_setters(rope: &Rope, encoding: PositionEncoding, ast: &Ast, diagnostics: &mut Vec<Diagnostic>) {
+    for range in ast.stray_setters.iter().filter_map(|range| range_to_lsp_in(rope, range, encoding)) {
+        diagnostics.push(diagnostic(
+            DiagnosticCode::PropertyOutsideWidget,
+            range,
+            String::from("property outside of a widget")
+        ));
+    }
+}
+
+// Everything that is checked within a single top-level node
+fn validate_block(rope: &Rope, encoding: PositionEncoding, node: &Node, db: &WidgetDb, settings: &Settings) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if settings.diagnostics.redundant_defaults {
+        redundant_defaults(rope, encoding, std::slice::from_ref(node), db, &mut diagnostics);
+    }
+    if settings.diagnostics.unknown_properties {
+        unknown_properties(rope, encoding, std::slice::from_ref(node), db, &mut diagnostics);
+    }
+    if settings.diagnostics.missing_arguments {
+        missing_arguments(rope, encoding, std::slice::from_ref(node), db, &mut diagnostics);
+    }
+    if let Some(max_depth) = settings.diagnostics.max_nesting_depth {
+        deep_nesting(rope, encoding, std::slice::from_ref(node), max_depth, &mut diagnostics);
+    }
+    if let Some(target) = settings.gtk_version() {
+        property_versions(rope, encoding, std::slice::from_ref(node), db, target, &mut diagnostics);
+    }
+    diagnostics
+}
+
+// Incremental validation
+
+// Relative to the start of the block: lines after its first one keep their columns
+fn relative(position: Position, base: Position) -> Position {
+    if position.line == base.line {
+        Position::new(0, position.character - base.character)
+    } else {
+        Position::new(position.line - base.line, position.character)
+    }
+}
+
+fn absolute(position: Position, base: Position) -> Position {
+    if position.line == 0 {
+        Position::new(base.line, base.character + position.character)
+    } else {
+        Position::new(base.line + position.line, position.character)
+    }
+}
+
+fn moved(diagnostics: &[Diagnostic], shift: impl Fn(Position) -> Position) -> Vec<Diagnostic> {
+    diagnostics
+        .iter()
+        .cloned()
+        .map(|mut diagnostic| {
+            diagnostic.range.start = shift(diagnostic.range.start);
+            diagnostic.range.end = shift(diagnostic.range.end);
+            diagnostic
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct BlockDiagnostics {
+    text: String,
+    // Positioned with `relative`, so the block can move without them changing
+    diagnostics: Vec<Diagnostic>
+}
+
+// The diagnostics of the last validation, one entry per top-level node
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticCache {
+    context: u64,
+    blocks: Vec<BlockDiagnostics>
+}
+
+// A block's diagnostics only depend on its own text, the definitions and includes it can
+// see, the settings, and what their columns count. Definitions and directives are blocks too,
+// so a change to any of them invalidates everything, and so does an included file changing on
+// disk
+fn context_hash(rope: &Rope, encoding: PositionEncoding, ast: &Ast, db: &WidgetDb, settings: &Settings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    encoding.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    db.fingerprint().hash(&mut hasher);
+    for node in &ast.nodes {
+        if let Node::Definition(_) | Node::Directive(_) = node {
+            if let Some(text) = rope.get_byte_slice(node.range().clone()) {
+                text.to_string().hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+/// Validates every top-level node, except that blocks whose text is unchanged since the
+/// previous call reuse their diagnostics from it. An empty cache gives a full validation.
+/// Columns count units of `encoding`.
+pub fn validate_incremental(rope: &Rope, encoding: PositionEncoding, ast: &Ast, db: &WidgetDb, settings: &Settings, cache: &mut DiagnosticCache) -> Vec<Diagnostic> {
+    let context = context_hash(rope, encoding, ast, db, settings);
+    let previous: HashMap<&str, &BlockDiagnostics> = if context == cache.context {
+        cache.blocks.iter().map(|block| (block.text.as_str(), block)).collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut diagnostics = Vec::new();
+    let mut blocks = Vec::new();
+    for node in &ast.nodes {
+        let (text, base) = match (rope.get_byte_slice(node.range().clone()), offset_to_position_in(rope, node.range().start, encoding)) {
+            (Some(text), Some(base)) => (text.to_string(), base),
+            _ => continue
+        };
+        let block = match previous.get(text.as_str()) {
+            Some(block) => (*block).clone(),
+            None => BlockDiagnostics {
+                diagnostics: moved(&validate_block(rope, encoding, node, db, settings), |position| relative(position, base)),
+                text
+            }
+        };
+        diagnostics.extend(moved(&block.diagnostics, |position| absolute(position, base)));
+        blocks.push(block);
+    }
+
+    // Line based and cheap enough to not be worth caching
+    if settings.diagnostics.mixed_indentation {
+        mixed_indentation(rope, &mut diagnostics);
+    }
+    // Recoveries are found by the parse itself, so there's nothing to recompute
+    if settings.diagnostics.parser_recovery {
+        parser_recoveries(rope, encoding, ast, &mut diagnostics);
+    }
+    if settings.diagnostics.properties_outside_widgets {
+        stray_setters(rope, encoding, ast, &mut diagnostics);
+    }
+
+    *cache = DiagnosticCache { context, blocks };
+    diagnostics
+}
+
+/// Everything that would be published for `text`, without a server or a cache. `db` holds
+/// what the text can see besides itself, such as its includes; its own definitions are added
+/// here. Text that can't be lexed at all has only the diagnostic saying so, like in the server.
+/// Columns count units of `encoding`.
+pub fn compute_diagnostics(text: &str, encoding: PositionEncoding, settings: &Settings, db: &WidgetDb) -> Vec<Diagnostic> {
+    if is_too_large(text.len(), settings) {
+        return vec![file_too_large(text.len(), settings)];
+    }
+    let (tokens, errors) = match lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }) {
+        Ok(lexed) => lexed,
+        Err(failure) => return vec![lex_failure(&failure)]
+    };
+    let ast = ast::parse(&tokens);
+    let mut db = db.clone();
+    db.add_definitions(&ast);
+    let rope = Rope::from_str(text);
+    let mut diagnostics = lex_errors(&rope, encoding, &errors);
+    diagnostics.extend(validate_incremental(&rope, encoding, &ast, &db, settings, &mut DiagnosticCache::default()));
+    diagnostics
+}
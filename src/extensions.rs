@@ -0,0 +1,288 @@
+// Requests and notifications specific to this server, all under the `gtkui/` prefix
+
+use gtk_ui::lexer::Token;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, TextDocumentIdentifier, Url};
+use crate::ast::{self, Node, Object};
+use crate::database::{self, WidgetDb};
+use crate::diagnostics::{self, DiagnosticCode};
+use crate::lexer::{self, LexOptions};
+use crate::position::{range_to_lsp_in, PositionEncoding};
+use crate::settings::Settings;
+
+// gtkui/effectiveProperties
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectivePropertiesParams {
+    pub text_document: TextDocumentIdentifier,
+    // Either the name of a widget, or a position inside an object or definition
+    pub name: Option<String>,
+    pub position: Option<Position>
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectiveProperty {
+    pub name: String,
+    pub kind: &'static str,
+    #[serde(rename = "type")]
+    pub internal_type: Option<&'static str>,
+    // The widget that defines the property, which is the requested one unless `inherited`
+    pub defined_in: String,
+    pub inherited: bool,
+    // What the object at the requested position sets it to
+    pub value: Option<String>,
+    pub default: Option<&'static str>
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectivePropertiesResult {
+    pub widget: String,
+    pub properties: Vec<EffectiveProperty>,
+    pub cycle: Option<Vec<String>>
+}
+
+pub fn effective_properties(db: &WidgetDb, widget: &str, object: Option<&Object>) -> EffectivePropertiesResult {
+    let effective = db.effective_properties(widget);
+    let properties = effective.properties
+        .iter()
+        .map(|(source, property)| EffectiveProperty {
+            name: property.name.clone(),
+            kind: database::definition_kind(&property.definition_type),
+            internal_type: property.internal_type.as_ref().map(database::type_name),
+            defined_in: source.name.clone(),
+            inherited: source.name != widget,
+            value: object
+                .and_then(|object| object.setters.iter().rev().find(|setter| setter.name == property.name))
+                .and_then(|setter| setter.value.as_ref())
+                .and_then(database::literal),
+            default: db.default_value(widget, &property.name)
+        })
+        .collect();
+
+    EffectivePropertiesResult {
+        widget: widget.to_string(),
+        properties,
+        cycle: effective.cycle
+    }
+}
+
+// gtkui/debugTokensPretty
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugTokensParams {
+    pub text_document: TextDocumentIdentifier
+}
+
+// One line per token, like `L3:C5-L3:C11 Setter("label") ".label"`. Lines and columns
+// count from 1 as editors show them, and the end column is just past the token
+pub fn pretty_tokens(rope: &Rope, encoding: PositionEncoding, tokens: &[Token]) -> Vec<String> {
+    tokens
+        .iter()
+        .map(|token| {
+            let text = rope
+                .get_byte_slice(token.range.clone())
+                .map(|slice| slice.to_string())
+                .unwrap_or_default();
+            let location = match range_to_lsp_in(rope, &token.range, encoding) {
+                Some(range) => format!(
+                    "L{}:C{}-L{}:C{}",
+                    range.start.line + 1,
+                    range.start.character + 1,
+                    range.end.line + 1,
+                    range.end.character + 1
+                ),
+                None => format!("{}..{}", token.range.start, token.range.end)
+            };
+            format!("{} {:?} {:?}", location, token.value, text)
+        })
+        .collect()
+}
+
+// gtkui/validateText, for tools that lint text which isn't open in the editor, or saved at all
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateTextParams {
+    pub text: String,
+    pub language_id: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyntaxError {
+    pub message: String,
+    // `None` when the text couldn't be lexed at all
+    pub range: Option<Range>
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateTextResult {
+    // No syntax errors, and no diagnostics of error severity
+    pub success: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    // What the editor only shows as missing highlighting, so a tool would never see it otherwise
+    pub syntax_errors: Vec<SyntaxError>
+}
+
+pub fn validate_text(text: &str, encoding: PositionEncoding, settings: &Settings, db: &WidgetDb) -> ValidateTextResult {
+    let rope = Rope::from_str(text);
+    let syntax_errors = match lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }) {
+        Ok((tokens, errors)) => {
+            let parse_errors = ast::parse(&tokens).errors.into_iter().map(|error| (error.message, error.range));
+            errors
+                .into_iter()
+                .map(|error| (error.message, error.range))
+                .chain(parse_errors)
+                .map(|(message, range)| SyntaxError { range: range_to_lsp_in(&rope, &range, encoding), message })
+                .collect()
+        },
+        Err(failure) => vec![SyntaxError { message: failure.message, range: None }]
+    };
+    let diagnostics = diagnostics::compute_diagnostics(text, encoding, settings, db);
+    ValidateTextResult {
+        success: syntax_errors.is_empty() && !diagnostics.iter().any(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::ERROR)),
+        diagnostics,
+        syntax_errors
+    }
+}
+
+// gtkui/diagnosticCatalog, every code the server can report, for settings and documentation
+// in clients that shouldn't have to keep their own list
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticCatalogEntry {
+    pub code: &'static str,
+    pub description: &'static str,
+    pub default_severity: DiagnosticSeverity,
+    pub configurable: bool,
+    pub setting: Option<&'static str>,
+    pub documentation: Option<Url>
+}
+
+pub fn diagnostic_catalog() -> Vec<DiagnosticCatalogEntry> {
+    DiagnosticCode::ALL
+        .into_iter()
+        .map(|code| DiagnosticCatalogEntry {
+            code: code.as_str(),
+            description: code.summary(),
+            default_severity: code.severity(),
+            configurable: code.setting().is_some(),
+            setting: code.setting(),
+            documentation: code.description().map(|description| description.href)
+        })
+        .collect()
+}
+
+// gtkui/features, which optional features are on given the current settings and what the
+// client said it supports
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    // Whether any diagnostic rule is enabled, then each rule
+    pub diagnostics: bool,
+    pub redundant_defaults: bool,
+    pub mixed_indentation: bool,
+    pub unknown_properties: bool,
+    pub missing_arguments: bool,
+    pub properties_outside_widgets: bool,
+    pub parser_recovery: bool,
+    pub deep_nesting: bool,
+    pub property_versions: bool,
+    pub unresolved_includes: bool,
+    pub semantic_tokens: bool,
+    pub completion: bool,
+    pub hover: bool,
+    pub definition: bool,
+    pub implementation: bool,
+    pub references: bool,
+    pub document_highlights: bool,
+    pub workspace_symbols: bool,
+    pub formatting: bool,
+    pub inlay_type_hints: bool,
+    pub inlay_default_hints: bool,
+    pub code_actions: bool,
+    pub document_symbols: bool,
+    pub folding_ranges: bool,
+    // `textDocument/rename`, which isn't supported; `gtkui.renameProperty` is
+    pub rename: bool,
+    pub rename_property: bool,
+    pub extract_to_file: bool,
+    pub validate_text: bool,
+    pub notebooks: bool,
+    pub watched_files: bool,
+    pub ast_changed: bool
+}
+
+// gtkui/astChanged, sent after a document parses cleanly to clients that set
+// `experimental.gtkuiAstChanged`, such as live previews
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviewSetter {
+    pub name: String,
+    // As written in the document, `None` if it isn't a literal
+    pub value: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewNode {
+    pub name: String,
+    // "definition" or "object"
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inherits: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setters: Vec<PreviewSetter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<PreviewNode>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AstChangedParams {
+    pub uri: Url,
+    pub version: i32,
+    pub nodes: Vec<PreviewNode>
+}
+
+pub enum AstChanged {}
+
+impl Notification for AstChanged {
+    type Params = AstChangedParams;
+    const METHOD: &'static str = "gtkui/astChanged";
+}
+
+// Definitions and objects only; directives and property definitions don't render
+pub fn preview_tree(nodes: &[Node]) -> Vec<PreviewNode> {
+    nodes.iter().filter_map(|node| match node {
+        Node::Definition(definition) => Some(PreviewNode {
+            name: definition.name.clone(),
+            kind: String::from("definition"),
+            inherits: definition.inherits.iter().map(|(name, _)| name.clone()).collect(),
+            arguments: Vec::new(),
+            setters: Vec::new(),
+            children: preview_tree(&definition.children)
+        }),
+        Node::Object(object) => Some(PreviewNode {
+            name: object.name.clone(),
+            kind: String::from("object"),
+            inherits: Vec::new(),
+            arguments: object.arguments.iter().filter_map(database::literal).collect(),
+            setters: object.setters.iter().map(|setter| PreviewSetter {
+                name: setter.name.clone(),
+                value: setter.value.as_ref().and_then(database::literal)
+            }).collect(),
+            children: preview_tree(&object.children)
+        }),
+        Node::Directive(_) | Node::Property(_) => None
+    }).collect()
+}
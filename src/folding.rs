@@ -0,0 +1,102 @@
+use gtk_ui::lexer::{Token, TokenValue};
+use ropey::Rope;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+use crate::ast::{Ast, Node};
+
+// Tabs are taken as this many columns when comparing indentation
+const TAB_WIDTH: usize = 4;
+
+fn fold(start_line: usize, end_line: usize) -> FoldingRange {
+    FoldingRange {
+        start_line: start_line as u32,
+        start_character: None,
+        end_line: end_line as u32,
+        end_character: None,
+        kind: Some(FoldingRangeKind::Region)
+    }
+}
+
+// Each multi-line block folds up to the line before its closing brace, so the brace stays visible
+pub fn ast_folding(rope: &Rope, ast: &Ast) -> Vec<FoldingRange> {
+    fn collect(rope: &Rope, nodes: &[Node], ranges: &mut Vec<FoldingRange>) {
+        for node in nodes {
+            let block = match node {
+                Node::Object(object) => object.block.as_ref(),
+                Node::Definition(definition) => definition.block.as_ref(),
+                _ => None
+            };
+            if let Some(block) = block {
+                let start = rope.try_byte_to_line(node.range().start).ok();
+                let end = rope.try_byte_to_line(block.end.saturating_sub(1)).ok();
+                if let (Some(start), Some(end)) = (start, end) {
+                    if end > start + 1 {
+                        ranges.push(fold(start, end - 1));
+                    }
+                }
+            }
+            collect(rope, node.children(), ranges);
+        }
+    }
+    let mut ranges = Vec::new();
+    collect(rope, &ast.nodes, &mut ranges);
+    ranges
+}
+
+fn indentation(line: &str) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(line
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum())
+}
+
+// What editors do for plain text: a line folds everything after it that is indented further,
+// blank lines included unless they trail
+pub fn indentation_folding(rope: &Rope) -> Vec<FoldingRange> {
+    let levels: Vec<Option<usize>> = rope.lines().map(|line| indentation(&line.to_string())).collect();
+    let mut ranges = Vec::new();
+    for (start, level) in levels.iter().enumerate() {
+        let level = match level {
+            Some(level) => *level,
+            None => continue
+        };
+        let mut end = start;
+        for (line, other) in levels.iter().enumerate().skip(start + 1) {
+            match other {
+                Some(other) if *other > level => end = line,
+                Some(_) => break,
+                None => ()
+            }
+        }
+        if end > start {
+            ranges.push(fold(start, end));
+        }
+    }
+    ranges
+}
+
+// Comments on two or more lines in a row, each the only thing on its line
+pub fn comment_folding(rope: &Rope, tokens: &[Token]) -> Vec<FoldingRange> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for token in tokens.iter().filter(|token| matches!(token.value, TokenValue::Comment)) {
+        let line = match rope.try_byte_to_line(token.range.start) {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let before = rope.get_byte_slice(rope.line_to_byte(line)..token.range.start);
+        if !before.is_some_and(|before| before.chars().all(char::is_whitespace)) {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => runs.push((line, line))
+        }
+    }
+    runs.into_iter()
+        .filter(|(start, end)| end > start)
+        .map(|(start, end)| FoldingRange { kind: Some(FoldingRangeKind::Comment), ..fold(start, end) })
+        .collect()
+}
@@ -0,0 +1,228 @@
+use gtk_ui::lexer::{Token, TokenValue};
+use tower_lsp::lsp_types::FormattingOptions;
+use crate::settings::LineEndings;
+
+// How a token is placed relative to the one before it
+enum Placement {
+    Line,
+    Space,
+    Attached
+}
+
+pub fn indent_unit(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        String::from("\t")
+    }
+}
+
+// `\r\n`, `\n` and a lone `\r` each end a line
+fn line_breaks(text: &str) -> usize {
+    text.matches('\n').count() + text.matches('\r').count() - text.matches("\r\n").count()
+}
+
+fn newlines_between(source: &str, previous: &Token, token: &Token) -> usize {
+    source
+        .get(previous.range.end..token.range.start)
+        .map(line_breaks)
+        .unwrap_or(0)
+}
+
+// What to end the formatted lines with. A document keeps CRLF if most of its lines use it
+pub fn line_ending(source: &str, setting: LineEndings) -> &'static str {
+    match setting {
+        LineEndings::Lf => "\n",
+        LineEndings::Crlf => "\r\n",
+        LineEndings::Preserve => {
+            let crlf = source.matches("\r\n").count();
+            if crlf > 0 && crlf * 2 >= source.matches('\n').count() { "\r\n" } else { "\n" }
+        }
+    }
+}
+
+fn placement(previous: Option<&Token>, token: &Token, newlines: usize, parens: usize) -> Placement {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Placement::Line
+    };
+
+    // A comment runs to the end of its line, so nothing may follow it there
+    if let TokenValue::Comment = previous.value {
+        return Placement::Line;
+    }
+    if let TokenValue::Comment = token.value {
+        return if newlines == 0 { Placement::Space } else { Placement::Line };
+    }
+
+    if parens > 0 {
+        return match (&previous.value, &token.value) {
+            (_, TokenValue::EndArgList) | (_, TokenValue::ArgListDeliminator) => Placement::Attached,
+            (TokenValue::StartArgList, _) => Placement::Attached,
+            _ => Placement::Space
+        };
+    }
+
+    match (&previous.value, &token.value) {
+        // An empty block stays `{}`
+        (TokenValue::StartBlock, TokenValue::EndBlock) => Placement::Attached,
+        // Setters written on their object's line (as collapsing to a line does) stay there
+        (TokenValue::EndBlock, TokenValue::Setter(_)) | (TokenValue::EndArgList, TokenValue::Setter(_)) if newlines == 0 => Placement::Space,
+        (TokenValue::Inherits, _) => Placement::Space,
+        (TokenValue::Directive(_), _) => Placement::Space,
+        (_, TokenValue::StartArgList) => Placement::Attached,
+        (_, TokenValue::StartBlock) | (_, TokenValue::Inherits) => Placement::Space,
+        (_, TokenValue::EndBlock)
+        | (_, TokenValue::Setter(_))
+        | (_, TokenValue::Definition(_))
+        | (_, TokenValue::Directive(_))
+        | (_, TokenValue::Identifier(_)) => Placement::Line,
+        _ => Placement::Space
+    }
+}
+
+/// Reserializes a document from its tokens (which must include comments), ending every
+/// line with `line_ending`.
+///
+/// Comments stay in front of the token that follows them, trailing comments stay on
+/// their line, and runs of blank lines between statements collapse to a single one.
+pub fn format(source: &str, tokens: &[Token], options: &FormattingOptions, line_ending: &str) -> String {
+    let indent = indent_unit(options);
+    let mut output = String::new();
+    let mut depth: usize = 0;
+    let mut parens: usize = 0;
+    // Setters of an object without a block, or with an empty `{}`, sit one level deeper than
+    // the object. After a block that spans lines they line up with its `}`
+    let mut setter_extra: usize = 0;
+    // For each open block, whether it belongs to an object rather than a definition
+    let mut blocks: Vec<bool> = Vec::new();
+    let mut previous: Option<&Token> = None;
+
+    for (index, token) in tokens.iter().enumerate() {
+        let text = match source.get(token.range.clone()) {
+            Some(text) => text.trim_end(),
+            None => continue
+        };
+        let newlines = previous.map(|previous| newlines_between(source, previous, token)).unwrap_or(0);
+
+        if let TokenValue::EndBlock = token.value {
+            depth = depth.saturating_sub(1);
+        }
+
+        match placement(previous, token, newlines, parens) {
+            Placement::Line => {
+                if !output.is_empty() {
+                    output.push_str(line_ending);
+                    let after_open = matches!(previous.map(|p| &p.value), Some(TokenValue::StartBlock));
+                    let before_close = matches!(token.value, TokenValue::EndBlock);
+                    if newlines >= 2 && !after_open && !before_close {
+                        output.push_str(line_ending);
+                    }
+                }
+                let level = match &token.value {
+                    TokenValue::Setter(_) => depth + setter_extra,
+                    // Comments are indented like the statement they are attached to
+                    TokenValue::Comment => {
+                        let next = tokens[index + 1..].iter().find(|t| !matches!(t.value, TokenValue::Comment));
+                        match next.map(|t| &t.value) {
+                            Some(TokenValue::Setter(_)) => depth + setter_extra,
+                            _ => depth
+                        }
+                    },
+                    _ if parens > 0 => depth + setter_extra + 1,
+                    _ => depth
+                };
+                output.push_str(&indent.repeat(level));
+            },
+            Placement::Space => output.push(' '),
+            Placement::Attached => ()
+        }
+        output.push_str(text);
+
+        match &token.value {
+            TokenValue::StartBlock => {
+                depth += 1;
+                blocks.push(setter_extra == 1);
+                setter_extra = 0;
+            },
+            TokenValue::EndBlock => {
+                let empty = matches!(previous.map(|p| &p.value), Some(TokenValue::StartBlock));
+                setter_extra = (blocks.pop().unwrap_or(false) && empty) as usize;
+            },
+            TokenValue::Definition(_) => setter_extra = 0,
+            TokenValue::Identifier(_) if parens == 0 && !matches!(previous.map(|p| &p.value), Some(TokenValue::Inherits)) => {
+                setter_extra = 1;
+            },
+            TokenValue::StartArgList => parens += 1,
+            TokenValue::EndArgList => parens = parens.saturating_sub(1),
+            _ => ()
+        }
+        previous = Some(token);
+    }
+
+    if !output.is_empty() {
+        output.push_str(line_ending);
+    }
+    output
+}
+
+// Edits made outside of formatting have no FormattingOptions, so follow the document
+pub fn detect_indent_unit(source: &str) -> String {
+    let mut unit: Option<usize> = None;
+    for line in source.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            return String::from("\t");
+        }
+        let spaces = line.len() - line.trim_start_matches(' ').len();
+        if spaces > 0 {
+            unit = Some(unit.map_or(spaces, |unit| unit.min(spaces)));
+        }
+    }
+    " ".repeat(unit.unwrap_or(2))
+}
+
+// The style most indented lines use: a tab, or the smallest run of spaces
+pub fn prevailing_indent(source: &str) -> (bool, usize) {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    for line in source.lines() {
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        if indent.is_empty() || line.trim().is_empty() {
+            continue;
+        }
+        if indent.chars().all(|c| c == '\t') {
+            tabs += 1;
+        } else if indent.chars().all(|c| c == ' ') {
+            spaces += 1;
+        }
+    }
+    let width = detect_indent_unit(&source.lines()
+        .filter(|line| !line.starts_with('\t'))
+        .collect::<Vec<_>>()
+        .join("\n")).len();
+    (tabs > spaces, width)
+}
+
+pub fn line_indent(source: &str, offset: usize) -> &str {
+    let start = source[..offset].rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let line = &source[start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+pub fn reindent(text: &str, from: &str, to: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if line.trim().is_empty() {
+                String::new()
+            } else if index == 0 {
+                format!("{}{}", to, line)
+            } else if let Some(rest) = line.strip_prefix(from) {
+                format!("{}{}", to, rest)
+            } else {
+                format!("{}{}", to, line.trim_start())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
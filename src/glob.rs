@@ -0,0 +1,105 @@
+// Glob patterns as editors write them: `*` and `?` within a path segment, `**` for any
+// number of segments, `[abc]`/`[!a-z]` classes and `{a,b}` alternatives. Paths are matched
+// segment by segment with `/` as the separator.
+
+#[derive(Debug, Clone)]
+pub struct Glob {
+    // One entry per brace alternative, each split into segments
+    alternatives: Vec<Vec<Vec<char>>>
+}
+
+// Expands the first `{...}` group and then whatever groups are left in each result
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![pattern.to_string()]
+    };
+    let mut depth = 0;
+    let mut parts = Vec::new();
+    let mut part_start = open + 1;
+    for (index, c) in pattern[open..].char_indices().map(|(index, c)| (index + open, c)) {
+        match c {
+            '{' => depth += 1,
+            ',' if depth == 1 => {
+                parts.push(&pattern[part_start..index]);
+                part_start = index + 1;
+            },
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    parts.push(&pattern[part_start..index]);
+                    let (prefix, suffix) = (&pattern[..open], &pattern[index + 1..]);
+                    return parts
+                        .into_iter()
+                        .flat_map(|part| expand_braces(&format!("{}{}{}", prefix, part, suffix)))
+                        .collect();
+                }
+            },
+            _ => ()
+        }
+    }
+    // An unclosed brace is taken literally
+    vec![pattern.to_string()]
+}
+
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    let mut index = if negated { 1 } else { 0 };
+    let mut matched = false;
+    let mut first = true;
+    while index < class.len() {
+        if class[index] == ']' && !first {
+            return Some((matched != negated, index + 1));
+        }
+        if index + 2 < class.len() && class[index + 1] == '-' && class[index + 2] != ']' {
+            matched |= class[index] <= c && c <= class[index + 2];
+            index += 3;
+        } else {
+            matched |= class[index] == c;
+            index += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+fn match_segment(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|skip| match_segment(&pattern[1..], &text[skip..])),
+        Some('?') => !text.is_empty() && match_segment(&pattern[1..], &text[1..]),
+        Some('[') if !text.is_empty() => match match_class(&pattern[1..], text[0]) {
+            Some((matched, length)) => matched && match_segment(&pattern[1 + length..], &text[1..]),
+            // No closing bracket, so it's an ordinary character
+            None => text[0] == '[' && match_segment(&pattern[1..], &text[1..])
+        },
+        Some(c) => text.first() == Some(c) && match_segment(&pattern[1..], &text[1..])
+    }
+}
+
+fn match_segments(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(segment) if segment.as_slice() == ['*', '*'] => {
+            (0..=path.len()).any(|skip| match_segments(&pattern[1..], &path[skip..]))
+        },
+        Some(segment) => !path.is_empty() && match_segment(segment, &path[0]) && match_segments(&pattern[1..], &path[1..])
+    }
+}
+
+fn segments(path: &str) -> Vec<Vec<char>> {
+    path.split('/').filter(|segment| !segment.is_empty()).map(|segment| segment.chars().collect()).collect()
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            alternatives: expand_braces(pattern).iter().map(|pattern| segments(pattern)).collect()
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path = segments(path);
+        self.alternatives.iter().any(|pattern| match_segments(pattern, &path))
+    }
+}
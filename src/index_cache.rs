@@ -0,0 +1,368 @@
+// The parsed workspace, saved between runs so a restart only re-parses the files that changed
+// since. One cache per workspace root, under its `.cache` directory, which indexing skips
+// like every other hidden directory.
+
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use gtk_ui::lexer::{DefinitionType, DirectiveType, IdentifierType, Token, TokenValue, TypeIdentifierType};
+use serde::{Deserialize, Serialize};
+use crate::ast::{Ast, Definition, Directive, Node, Object, ParseError, Property, Setter};
+
+// Bumped whenever the shape of the cached tree changes, so an old cache is simply ignored
+const VERSION: u32 = 2;
+
+pub fn cache_path(root: &Path) -> PathBuf {
+    root.join(".cache").join("gtkui-language-server").join("index.json")
+}
+
+// What a file looked like when it was parsed. Checking it is a `stat`, not a read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    modified: (u64, u32),
+    size: u64
+}
+
+pub fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(Stamp {
+        modified: (modified.as_secs(), modified.subsec_nanos()),
+        size: metadata.len()
+    })
+}
+
+// The tree as it is stored. Tokens only ever appear as values and arguments, so those are
+// the only kinds kept. Recoveries aren't kept, since only open documents report them
+#[derive(Serialize, Deserialize)]
+enum CachedValue {
+    String(String),
+    Number(f32),
+    Bool(i32),
+    Name(String),
+    Type(CachedType)
+}
+
+#[derive(Serialize, Deserialize)]
+enum CachedType {
+    String,
+    Number,
+    Bool
+}
+
+#[derive(Serialize, Deserialize)]
+enum CachedProperty {
+    InlineProp,
+    InlineArg,
+    ChildProp,
+    ChildArg,
+    Object(String)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    value: CachedValue,
+    range: Range<usize>
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedSetter {
+    name: String,
+    name_range: Range<usize>,
+    value: Option<CachedToken>,
+    range: Range<usize>
+}
+
+#[derive(Serialize, Deserialize)]
+enum CachedNode {
+    Directive {
+        include: bool,
+        argument: Option<(String, Range<usize>)>,
+        range: Range<usize>
+    },
+    Definition {
+        name: String,
+        name_range: Range<usize>,
+        inherits: Vec<(String, Range<usize>)>,
+        children: Vec<CachedNode>,
+        block: Option<Range<usize>>,
+        range: Range<usize>
+    },
+    Property {
+        definition_type: CachedProperty,
+        name: Option<String>,
+        name_range: Option<Range<usize>>,
+        internal_type: Option<CachedType>,
+        range: Range<usize>
+    },
+    Object {
+        name: String,
+        name_range: Range<usize>,
+        arguments: Vec<CachedToken>,
+        children: Vec<CachedNode>,
+        setters: Vec<CachedSetter>,
+        block: Option<Range<usize>>,
+        range: Range<usize>,
+        depth: usize
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedAst {
+    nodes: Vec<CachedNode>,
+    errors: Vec<(String, Range<usize>)>
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    stamp: Stamp,
+    ast: CachedAst
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexCache {
+    version: u32,
+    files: HashMap<PathBuf, CachedFile>
+}
+
+impl Default for IndexCache {
+    fn default() -> Self {
+        Self { version: VERSION, files: HashMap::new() }
+    }
+}
+
+fn to_cached_type(internal_type: &TypeIdentifierType) -> CachedType {
+    match internal_type {
+        TypeIdentifierType::String => CachedType::String,
+        TypeIdentifierType::Number => CachedType::Number,
+        TypeIdentifierType::Bool => CachedType::Bool
+    }
+}
+
+fn from_cached_type(internal_type: &CachedType) -> TypeIdentifierType {
+    match internal_type {
+        CachedType::String => TypeIdentifierType::String,
+        CachedType::Number => TypeIdentifierType::Number,
+        CachedType::Bool => TypeIdentifierType::Bool
+    }
+}
+
+fn to_cached_token(token: &Token) -> Option<CachedToken> {
+    let value = match &token.value {
+        TokenValue::String(value) => CachedValue::String(value.clone()),
+        TokenValue::Number(value) => CachedValue::Number(*value),
+        TokenValue::Bool(value) => CachedValue::Bool(*value),
+        TokenValue::Identifier(IdentifierType::Generic(name)) => CachedValue::Name(name.clone()),
+        TokenValue::Identifier(IdentifierType::Type(internal_type)) => CachedValue::Type(to_cached_type(internal_type)),
+        _ => return None
+    };
+    Some(CachedToken { value, range: token.range.clone() })
+}
+
+fn from_cached_token(token: &CachedToken) -> Token {
+    let value = match &token.value {
+        CachedValue::String(value) => TokenValue::String(value.clone()),
+        CachedValue::Number(value) => TokenValue::Number(*value),
+        CachedValue::Bool(value) => TokenValue::Bool(*value),
+        CachedValue::Name(name) => TokenValue::Identifier(IdentifierType::Generic(name.clone())),
+        CachedValue::Type(internal_type) => TokenValue::Identifier(IdentifierType::Type(from_cached_type(internal_type)))
+    };
+    Token { value, range: token.range.clone() }
+}
+
+fn to_cached_nodes(nodes: &[Node]) -> Vec<CachedNode> {
+    nodes.iter().map(|node| match node {
+        Node::Directive(directive) => CachedNode::Directive {
+            include: matches!(directive.directive_type, DirectiveType::Include),
+            argument: directive.argument.clone(),
+            range: directive.range.clone()
+        },
+        Node::Definition(definition) => CachedNode::Definition {
+            name: definition.name.clone(),
+            name_range: definition.name_range.clone(),
+            inherits: definition.inherits.clone(),
+            children: to_cached_nodes(&definition.children),
+            block: definition.block.clone(),
+            range: definition.range.clone()
+        },
+        Node::Property(property) => CachedNode::Property {
+            definition_type: match &property.definition_type {
+                DefinitionType::InlineProp => CachedProperty::InlineProp,
+                DefinitionType::InlineArg => CachedProperty::InlineArg,
+                DefinitionType::ChildProp => CachedProperty::ChildProp,
+                DefinitionType::ChildArg => CachedProperty::ChildArg,
+                DefinitionType::Object(name) => CachedProperty::Object(name.clone())
+            },
+            name: property.name.clone(),
+            name_range: property.name_range.clone(),
+            internal_type: property.internal_type.as_ref().map(to_cached_type),
+            range: property.range.clone()
+        },
+        Node::Object(object) => CachedNode::Object {
+            name: object.name.clone(),
+            name_range: object.name_range.clone(),
+            arguments: object.arguments.iter().filter_map(to_cached_token).collect(),
+            children: to_cached_nodes(&object.children),
+            setters: object.setters.iter().map(|setter| CachedSetter {
+                name: setter.name.clone(),
+                name_range: setter.name_range.clone(),
+                value: setter.value.as_ref().and_then(to_cached_token),
+                range: setter.range.clone()
+            }).collect(),
+            block: object.block.clone(),
+            range: object.range.clone(),
+            depth: object.depth
+        }
+    }).collect()
+}
+
+fn from_cached_nodes(nodes: &[CachedNode]) -> Vec<Node> {
+    nodes.iter().map(|node| match node {
+        CachedNode::Directive { include, argument, range } => Node::Directive(Directive {
+            directive_type: if *include { DirectiveType::Include } else { DirectiveType::Header },
+            argument: argument.clone(),
+            range: range.clone()
+        }),
+        CachedNode::Definition { name, name_range, inherits, children, block, range } => Node::Definition(Definition {
+            name: name.clone(),
+            name_range: name_range.clone(),
+            inherits: inherits.clone(),
+            children: from_cached_nodes(children),
+            block: block.clone(),
+            range: range.clone()
+        }),
+        CachedNode::Property { definition_type, name, name_range, internal_type, range } => Node::Property(Property {
+            definition_type: match definition_type {
+                CachedProperty::InlineProp => DefinitionType::InlineProp,
+                CachedProperty::InlineArg => DefinitionType::InlineArg,
+                CachedProperty::ChildProp => DefinitionType::ChildProp,
+                CachedProperty::ChildArg => DefinitionType::ChildArg,
+                CachedProperty::Object(name) => DefinitionType::Object(name.clone())
+            },
+            name: name.clone(),
+            name_range: name_range.clone(),
+            internal_type: internal_type.as_ref().map(from_cached_type),
+            range: range.clone()
+        }),
+        CachedNode::Object { name, name_range, arguments, children, setters, block, range, depth } => Node::Object(Object {
+            name: name.clone(),
+            name_range: name_range.clone(),
+            arguments: arguments.iter().map(from_cached_token).collect(),
+            children: from_cached_nodes(children),
+            setters: setters.iter().map(|setter| Setter {
+                name: setter.name.clone(),
+                name_range: setter.name_range.clone(),
+                value: setter.value.as_ref().map(from_cached_token),
+                range: setter.range.clone()
+            }).collect(),
+            block: block.clone(),
+            range: range.clone(),
+            depth: *depth
+        })
+    }).collect()
+}
+
+impl IndexCache {
+    /// Reads the cache of a workspace root. There being none yet isn't an error, but a cache
+    /// that can't be read or is from another version is, and the caller starts over.
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = cache_path(root);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("could not read {}: {}", path.display(), err))
+        };
+        match serde_json::from_str::<Self>(&text) {
+            Ok(cache) if cache.version == VERSION => Ok(cache),
+            Ok(cache) => Err(format!("{} is from version {} of the cache", path.display(), cache.version)),
+            Err(err) => Err(format!("{} is corrupt: {}", path.display(), err))
+        }
+    }
+
+    // The cached tree, as long as the file is still as it was when it was parsed
+    pub fn get(&self, path: &Path, stamp: Stamp) -> Option<Ast> {
+        let file = self.files.get(path).filter(|file| file.stamp == stamp)?;
+        Some(Ast {
+            nodes: from_cached_nodes(&file.ast.nodes),
+            errors: file.ast.errors
+                .iter()
+                .map(|(message, range)| ParseError { message: message.clone(), range: range.clone() })
+                .collect(),
+            recoveries: Vec::new(),
+            stray_setters: Vec::new(),
+            steps: Vec::new()
+        })
+    }
+
+    pub fn insert(&mut self, path: PathBuf, stamp: Stamp, ast: &Ast) {
+        self.files.insert(path, CachedFile {
+            stamp,
+            ast: CachedAst {
+                nodes: to_cached_nodes(&ast.nodes),
+                errors: ast.errors.iter().map(|error| (error.message.clone(), error.range.clone())).collect()
+            }
+        });
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), String> {
+        let path = cache_path(root);
+        let text = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        // Written next to it and renamed, so a crash halfway leaves the old cache in place
+        let temporary = path.with_extension("json.tmp");
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary, text))
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|err| format!("could not write {}: {}", path.display(), err))
+    }
+}
+
+// A file's entry goes in the cache of the first root it is under
+fn root_index(roots: &[PathBuf], path: &Path) -> Option<usize> {
+    roots.iter().position(|root| path.starts_with(root))
+}
+
+pub struct Lookup {
+    pub cached: Vec<(PathBuf, Stamp, Ast)>,
+    // Changed since they were cached, or never were
+    pub stale: Vec<PathBuf>,
+    // Caches that had to be thrown away
+    pub problems: Vec<String>
+}
+
+pub fn lookup(roots: &[PathBuf], files: &[PathBuf]) -> Lookup {
+    let mut problems = Vec::new();
+    let caches: Vec<IndexCache> = roots
+        .iter()
+        .map(|root| IndexCache::load(root).unwrap_or_else(|err| {
+            problems.push(err);
+            IndexCache::default()
+        }))
+        .collect();
+    let mut lookup = Lookup { cached: Vec::new(), stale: Vec::new(), problems };
+    for path in files {
+        let cached = || -> Option<(Stamp, Ast)> {
+            let stamp = stamp(path)?;
+            Some((stamp, caches[root_index(roots, path)?].get(path, stamp)?))
+        }();
+        match cached {
+            Some((stamp, ast)) => lookup.cached.push((path.clone(), stamp, ast)),
+            None => lookup.stale.push(path.clone())
+        }
+    }
+    lookup
+}
+
+// Replaces the cache of every root with the given files, returning what couldn't be written
+pub fn save(roots: &[PathBuf], files: &[(PathBuf, Stamp, Arc<Ast>)]) -> Vec<String> {
+    let mut caches: Vec<IndexCache> = roots.iter().map(|_| IndexCache::default()).collect();
+    for (path, stamp, ast) in files {
+        if let Some(index) = root_index(roots, path) {
+            caches[index].insert(path.clone(), *stamp, ast);
+        }
+    }
+    roots.iter().zip(caches).filter_map(|(root, cache)| cache.save(root).err()).collect()
+}
@@ -0,0 +1,98 @@
+use std::ops::Range;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, InlayHintTooltip, MarkupContent, MarkupKind, Url};
+use crate::ast::{self, Ast};
+use crate::database::{self, WidgetDb};
+use crate::position::{offset_to_position_in, PositionEncoding};
+use crate::settings::InlayHintSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HintKind {
+    // What the property takes, after the setter's value
+    Type,
+    // What the property would be without the setter, after the setter
+    Default
+}
+
+// Enough to find the setter again when the client resolves the hint
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HintData {
+    pub uri: Url,
+    pub offset: usize,
+    pub kind: HintKind
+}
+
+fn hint(uri: &Url, rope: &Rope, encoding: PositionEncoding, offset: usize, setter: usize, kind: HintKind, label: String) -> Option<InlayHint> {
+    Some(InlayHint {
+        position: offset_to_position_in(rope, offset, encoding)?,
+        label: InlayHintLabel::String(label),
+        kind: match kind {
+            HintKind::Type => Some(InlayHintKind::TYPE),
+            HintKind::Default => None
+        },
+        text_edits: None,
+        // Filled in by `resolve`
+        tooltip: None,
+        padding_left: Some(kind == HintKind::Default),
+        padding_right: None,
+        data: serde_json::to_value(HintData { uri: uri.clone(), offset: setter, kind }).ok()
+    })
+}
+
+// Hints for the setters overlapping `range`, of the kinds the settings enable. Defaults are
+// only shown for setters that change them
+pub fn inlay_hints(uri: &Url, rope: &Rope, encoding: PositionEncoding, ast: &Ast, db: &WidgetDb, settings: &InlayHintSettings, range: Range<usize>) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+    for object in ast::objects(ast) {
+        for setter in object.setters.iter().filter(|setter| setter.range.start < range.end && range.start < setter.range.end) {
+            let value = match &setter.value {
+                Some(value) => value,
+                None => continue
+            };
+            if settings.types {
+                let internal_type = db.property(&object.name, &setter.name).and_then(|(_, property)| property.internal_type.as_ref());
+                if let Some(internal_type) = internal_type {
+                    let label = format!(": {}", database::type_name(internal_type));
+                    hints.extend(hint(uri, rope, encoding, value.range.end, setter.name_range.start, HintKind::Type, label));
+                }
+            }
+            if settings.defaults {
+                let default = db.default_value(&object.name, &setter.name);
+                if let Some(default) = default.filter(|default| database::literal(value).as_deref() != Some(*default)) {
+                    let label = format!("default {}", default);
+                    hints.extend(hint(uri, rope, encoding, setter.range.end, setter.name_range.start, HintKind::Default, label));
+                }
+            }
+        }
+    }
+    hints.sort_by_key(|hint| (hint.position.line, hint.position.character));
+    hints
+}
+
+// Adds the tooltip, which is only worked out for the hints the user points at
+pub fn resolve(mut hint: InlayHint, ast: &Ast, db: &WidgetDb) -> InlayHint {
+    let data = match hint.data.clone().and_then(|data| serde_json::from_value::<HintData>(data).ok()) {
+        Some(data) => data,
+        None => return hint
+    };
+    let (object, setter) = match ast::setter_at(ast, data.offset) {
+        Some(found) => found,
+        None => return hint
+    };
+    let tooltip = match data.kind {
+        HintKind::Type => db.property(&object.name, &setter.name).map(|(widget, property)| {
+            let internal_type = property.internal_type.as_ref().map(database::type_name).unwrap_or("unknown");
+            format!("`{}` is a {} property of `{}`", setter.name, internal_type, widget.name)
+        }),
+        HintKind::Default => db.default_value(&object.name, &setter.name).map(|default| {
+            format!("Without this setter, `{}` on `{}` would be `{}`", setter.name, object.name, default)
+        })
+    };
+    hint.tooltip = tooltip.map(|value| InlayHintTooltip::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value
+    }));
+    hint
+}
@@ -0,0 +1,383 @@
+// A lexer producing the same tokens as `gtk_ui::lexer::Lexer`, but one that is meant for
+// text that is still being typed: it never panics, keeps going after an error, and works
+// on byte offsets so non-ASCII text and CRLF line endings are fine.
+
+use std::any::Any;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use gtk_ui::lexer::{
+    DefinitionType,
+    DirectiveType,
+    IdentifierType,
+    Token,
+    TokenValue,
+    TypeIdentifierType
+};
+use unescape::unescape;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub range: Range<usize>
+}
+
+// The document couldn't be lexed at all, as opposed to `LexError`s which only lose the
+// text they cover. Since every error is recovered from, this only happens when lexing
+// itself crashes
+#[derive(Debug, Clone)]
+pub struct LexFailure {
+    pub message: String
+}
+
+impl LexFailure {
+    /// The failure a lexer panic with `payload` amounts to. `panic!` hands over a `&str` when
+    /// given only a literal and a `String` when it formats; anything else loses its message.
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> LexFailure {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+        LexFailure {
+            message: match message {
+                Some(message) => format!("the lexer crashed: {}", message),
+                None => String::from("the lexer crashed")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LexOptions {
+    // Keep comment tokens. Whitespace never becomes a token either way
+    pub include_trivia: bool,
+    // Keep lexing after an error instead of stopping at the first one
+    pub allow_recovery: bool
+}
+
+impl Default for LexOptions {
+    fn default() -> Self {
+        Self {
+            include_trivia: true,
+            allow_recovery: true
+        }
+    }
+}
+
+// How a comment is written. Like `gtk_ui`, any `/` starts one that runs to the end of the line,
+// so there are no block comments
+pub const LINE_COMMENT: &str = "//";
+
+// Every way a Bool can be written, as `gtk_ui` spells them. `True` or `yes` is just a name
+pub const BOOL_LITERALS: [(&str, i32); 2] = [("true", 1), ("false", 0)];
+
+pub fn bool_value(text: &str) -> Option<i32> {
+    BOOL_LITERALS.iter().find(|(literal, _)| *literal == text).map(|(_, value)| *value)
+}
+
+// The escape sequences in a string token's text, relative to its start: a backslash and the
+// character after it, the way `Cursor::string` reads them
+pub fn escape_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, '\n' | '\r')) | None => (),
+                Some((next, escaped)) => ranges.push(index..next + escaped.len_utf8())
+            }
+        }
+    }
+    ranges
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '-' || c == '_'
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    index: usize,
+    tokens: Vec<Token>,
+    errors: Vec<LexError>
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.index..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.index;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.text[start..self.index]
+    }
+
+    fn push(&mut self, value: TokenValue, start: usize) {
+        self.tokens.push(Token {
+            value,
+            range: start..self.index
+        });
+    }
+
+    fn error(&mut self, message: String, range: Range<usize>) {
+        self.errors.push(LexError { message, range });
+    }
+
+    fn directive(&mut self, start: usize) {
+        let name = self.eat_while(is_name);
+        match name {
+            "include" => self.push(TokenValue::Directive(DirectiveType::Include), start),
+            "header" => self.push(TokenValue::Directive(DirectiveType::Header), start),
+            _ => self.error(format!("unknown directive '#{}'", name), start..self.index)
+        }
+    }
+
+    fn string(&mut self, start: usize) {
+        let mut raw = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    break;
+                },
+                Some('\\') => {
+                    self.bump();
+                    raw.push('\\');
+                    match self.peek() {
+                        Some('\n') | Some('\r') | None => (),
+                        Some(c) => {
+                            self.bump();
+                            raw.push(c);
+                        }
+                    }
+                },
+                // The string still becomes a token, so the rest of the line keeps its meaning
+                Some('\n') | Some('\r') | None => {
+                    self.error(String::from("unexpected end of string input"), start..self.index);
+                    break;
+                },
+                Some(c) => {
+                    self.bump();
+                    raw.push(c);
+                }
+            }
+        }
+        let value = match unescape(&raw) {
+            Some(value) => value,
+            None => {
+                self.error(String::from("unable to escape string"), start..self.index);
+                raw
+            }
+        };
+        self.push(TokenValue::String(value), start);
+    }
+
+    fn number(&mut self, start: usize) {
+        let number = self.eat_while(|c| c.is_ascii_digit() || c == '.');
+        match number.parse::<f32>() {
+            Ok(value) => self.push(TokenValue::Number(value), start),
+            Err(err) => self.error(err.to_string(), start..self.index)
+        }
+    }
+
+    fn identifier(&mut self, start: usize) {
+        let name = self.eat_while(is_name);
+        let value = match name {
+            "String" => TokenValue::Identifier(IdentifierType::Type(TypeIdentifierType::String)),
+            "Number" => TokenValue::Identifier(IdentifierType::Type(TypeIdentifierType::Number)),
+            "Bool" => TokenValue::Identifier(IdentifierType::Type(TypeIdentifierType::Bool)),
+            name => match bool_value(name) {
+                Some(value) => TokenValue::Bool(value),
+                None => TokenValue::Identifier(IdentifierType::Generic(name.to_string()))
+            }
+        };
+        self.push(value, start);
+    }
+
+    fn comment(&mut self, start: usize) {
+        self.eat_while(|c| c != '\n' && c != '\r');
+        self.push(TokenValue::Comment, start);
+    }
+
+    fn next(&mut self) -> bool {
+        let start = self.index;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return false
+        };
+        if is_name_start(c) {
+            self.identifier(start);
+            return true;
+        }
+        if c.is_ascii_digit() {
+            self.number(start);
+            return true;
+        }
+
+        self.bump();
+        match c {
+            ' ' | '\t' | '\n' | '\r' => (),
+            '@' => {
+                let name = self.eat_while(is_name).to_string();
+                self.push(DefinitionType::from(&name), start);
+            },
+            '#' => self.directive(start),
+            '"' => self.string(start),
+            '.' => {
+                let name = self.eat_while(is_name).to_string();
+                self.push(TokenValue::Setter(name), start);
+            },
+            '-' if self.peek() == Some('>') => {
+                self.bump();
+                self.push(TokenValue::Inherits, start);
+            },
+            '{' => self.push(TokenValue::StartBlock, start),
+            '}' => self.push(TokenValue::EndBlock, start),
+            ',' => self.push(TokenValue::ArgListDeliminator, start),
+            '(' => self.push(TokenValue::StartArgList, start),
+            ')' => self.push(TokenValue::EndArgList, start),
+            '/' => self.comment(start),
+            _ => self.error(format!("unrecognized character '{}'", c), start..self.index)
+        }
+        true
+    }
+}
+
+/// The token covering a byte offset. Ranges are half open like everywhere else, so an
+/// offset at the very end of a token belongs to whatever starts there, if anything. Tokens
+/// must be sorted by start, as the lexer produces them.
+pub fn token_at(tokens: &[Token], byte: usize) -> Option<&Token> {
+    let index = tokens.partition_point(|token| token.range.start <= byte).checked_sub(1)?;
+    let token = &tokens[index];
+    if byte < token.range.end {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+/// The dimension, like `12px`, that a byte offset is in: its number, its unit and the range
+/// of both. Such a value lexes as a number directly followed by an identifier.
+pub fn dimension_at(tokens: &[Token], byte: usize) -> Option<(f32, &str, Range<usize>)> {
+    let index = tokens.partition_point(|token| token.range.start <= byte).checked_sub(1)?;
+    let number = match tokens[index].value {
+        _ if byte >= tokens[index].range.end => return None,
+        TokenValue::Number(_) => index,
+        TokenValue::Identifier(_) => index.checked_sub(1)?,
+        _ => return None
+    };
+    match (&tokens[number], tokens.get(number + 1)?) {
+        (Token { value: TokenValue::Number(value), range }, Token { value: TokenValue::Identifier(IdentifierType::Generic(unit)), range: unit_range })
+            if range.end == unit_range.start => Some((*value, unit.as_str(), range.start..unit_range.end)),
+        _ => None
+    }
+}
+
+// `gtk_ui` tokens don't implement `PartialEq`, and being foreign types they can't be given
+// it here, so tests and the incremental parse compare them through this
+pub fn same_value(a: &TokenValue, b: &TokenValue) -> bool {
+    fn same_type(a: &TypeIdentifierType, b: &TypeIdentifierType) -> bool {
+        matches!(
+            (a, b),
+            (TypeIdentifierType::String, TypeIdentifierType::String)
+                | (TypeIdentifierType::Number, TypeIdentifierType::Number)
+                | (TypeIdentifierType::Bool, TypeIdentifierType::Bool)
+        )
+    }
+    match (a, b) {
+        (TokenValue::String(a), TokenValue::String(b)) | (TokenValue::Setter(a), TokenValue::Setter(b)) => a == b,
+        // Bitwise, so a token always equals itself
+        (TokenValue::Number(a), TokenValue::Number(b)) => a.to_bits() == b.to_bits(),
+        (TokenValue::Bool(a), TokenValue::Bool(b)) => a == b,
+        (TokenValue::Definition(a), TokenValue::Definition(b)) => match (a, b) {
+            (DefinitionType::Object(a), DefinitionType::Object(b)) => a == b,
+            (DefinitionType::InlineProp, DefinitionType::InlineProp)
+            | (DefinitionType::InlineArg, DefinitionType::InlineArg)
+            | (DefinitionType::ChildProp, DefinitionType::ChildProp)
+            | (DefinitionType::ChildArg, DefinitionType::ChildArg) => true,
+            _ => false
+        },
+        (TokenValue::Directive(a), TokenValue::Directive(b)) => matches!(
+            (a, b),
+            (DirectiveType::Include, DirectiveType::Include) | (DirectiveType::Header, DirectiveType::Header)
+        ),
+        (TokenValue::Identifier(a), TokenValue::Identifier(b)) => match (a, b) {
+            (IdentifierType::Generic(a), IdentifierType::Generic(b)) => a == b,
+            (IdentifierType::Type(a), IdentifierType::Type(b)) => same_type(a, b),
+            _ => false
+        },
+        (TokenValue::Comment, TokenValue::Comment)
+        | (TokenValue::Inherits, TokenValue::Inherits)
+        | (TokenValue::StartBlock, TokenValue::StartBlock)
+        | (TokenValue::EndBlock, TokenValue::EndBlock)
+        | (TokenValue::StartArgList, TokenValue::StartArgList)
+        | (TokenValue::EndArgList, TokenValue::EndArgList)
+        | (TokenValue::ArgListDeliminator, TokenValue::ArgListDeliminator) => true,
+        _ => false
+    }
+}
+
+pub trait TokenExt {
+    /// The source text the token was lexed from, or an empty string if its range doesn't
+    /// fit `source`.
+    fn text<'a>(&self, source: &'a str) -> &'a str;
+    /// Whether both tokens have the same range and value.
+    fn same_as(&self, other: &Token) -> bool;
+}
+
+impl TokenExt for Token {
+    fn text<'a>(&self, source: &'a str) -> &'a str {
+        source.get(self.range.clone()).unwrap_or("")
+    }
+
+    fn same_as(&self, other: &Token) -> bool {
+        self.range == other.range && same_value(&self.value, &other.value)
+    }
+}
+
+/// Whether both slices hold the same tokens in the same order.
+pub fn same_tokens(a: &[Token], b: &[Token]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_as(b))
+}
+
+pub fn lex_with(text: &str, options: LexOptions) -> Result<(Vec<Token>, Vec<LexError>), LexFailure> {
+    let mut cursor = Cursor {
+        text,
+        index: 0,
+        tokens: Vec::new(),
+        errors: Vec::new()
+    };
+    // A bug in here must cost the document its tokens, not the server its life
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        while cursor.next() {
+            if !options.allow_recovery && !cursor.errors.is_empty() {
+                break;
+            }
+        }
+    })).map_err(LexFailure::from_panic)?;
+
+    if !options.include_trivia {
+        cursor.tokens.retain(|token| !matches!(token.value, TokenValue::Comment));
+    }
+    Ok((cursor.tokens, cursor.errors))
+}
+
+/// Lexes a whole document with the default options: comments included (what
+/// `Lexer::lex(true)` means) and recovering from errors. Returns every token it could make
+/// sense of along with the errors found on the way. Text an error covers produces no
+/// token, except for unterminated strings which still do.
+pub fn tokenize(text: &str) -> Result<(Vec<Token>, Vec<LexError>), LexFailure> {
+    lex_with(text, LexOptions::default())
+}
@@ -0,0 +1,36 @@
+// The language server as a library, so it can be embedded or tested as a crate. `Backend`
+// and `service` are the server itself; the other public modules are the pipeline it runs
+// documents through, usable on their own: `lexer` and `ast` to read a document, with
+// `trivia` for what lies between its tokens, `database` for what its widgets have, and
+// `diagnostics`, `semantic_tokens` and the rest for what editors are sent. Positions go
+// through `position`, settings through `settings`.
+
+pub mod ast;
+pub mod code_action;
+pub mod completion;
+pub mod database;
+pub mod diagnostics;
+pub mod extensions;
+pub mod folding;
+pub mod formatter;
+pub mod glob;
+pub mod inlay_hints;
+pub mod lexer;
+pub mod lifecycle;
+pub mod position;
+pub mod references;
+pub mod rename;
+pub mod semantic_tokens;
+pub mod settings;
+pub mod symbols;
+pub mod toggle;
+pub mod trace;
+pub mod trivia;
+
+mod backend;
+mod index_cache;
+mod notebook;
+mod partial;
+mod workspace;
+
+pub use backend::{service, Backend, DocumentState};
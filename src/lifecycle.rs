@@ -0,0 +1,72 @@
+// tower-lsp refuses requests once `shutdown` has been answered, but after `exit` it only
+// stops serving when the next message arrives or stdin closes. `Lifecycle` wraps the service
+// to notice `exit` itself, so the process can end right away with the code the spec asks for.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+use tower_lsp::jsonrpc::{Request, Response};
+use tower_service::Service;
+
+#[derive(Default)]
+pub struct Exit {
+    notify: Notify,
+    shut_down: AtomicBool
+}
+
+impl Exit {
+    /// Waits for the `exit` notification, then gives the process exit code: 0 after a
+    /// successful `shutdown` and 1 without one.
+    pub async fn wait(&self) -> i32 {
+        self.notify.notified().await;
+        if self.shut_down.load(Ordering::SeqCst) { 0 } else { 1 }
+    }
+}
+
+pub struct Lifecycle<S> {
+    inner: S,
+    exit: Arc<Exit>
+}
+
+impl<S> Lifecycle<S> {
+    pub fn new(inner: S) -> (Self, Arc<Exit>) {
+        let exit = Arc::new(Exit::default());
+        (Self { inner, exit: exit.clone() }, exit)
+    }
+}
+
+impl<S> Service<Request> for Lifecycle<S>
+where
+    S: Service<Request, Response = Option<Response>>,
+    S::Future: Send + 'static
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let method = request.method().to_string();
+        let exit = self.exit.clone();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            match method.as_str() {
+                "shutdown" => {
+                    let succeeded = matches!(&response, Ok(Some(response)) if response.is_ok());
+                    exit.shut_down.store(succeeded, Ordering::SeqCst);
+                },
+                // `notify_one` keeps the wakeup even if nothing is waiting yet
+                "exit" => exit.notify.notify_one(),
+                _ => ()
+            }
+            response
+        })
+    }
+}
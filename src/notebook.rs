@@ -0,0 +1,65 @@
+// `notebookDocument/*` notifications from LSP 3.17, which the lsp-types version we use
+// doesn't have yet. Only the parts needed to follow `gui` cells are modelled.
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{
+    TextDocumentContentChangeEvent,
+    TextDocumentIdentifier,
+    TextDocumentItem,
+    Url,
+    VersionedTextDocumentIdentifier
+};
+
+pub const LANGUAGE_ID: &str = "gui";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentIdentifier {
+    pub uri: Url
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenNotebookDocumentParams {
+    pub cell_text_documents: Vec<TextDocumentItem>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellTextContentChange {
+    pub document: VersionedTextDocumentIdentifier,
+    pub changes: Vec<TextDocumentContentChangeEvent>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotebookCellStructureChange {
+    pub did_open: Vec<TextDocumentItem>,
+    pub did_close: Vec<TextDocumentIdentifier>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotebookCellsChange {
+    pub structure: Option<NotebookCellStructureChange>,
+    pub text_content: Vec<NotebookCellTextContentChange>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotebookDocumentChangeEvent {
+    pub cells: Option<NotebookCellsChange>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeNotebookDocumentParams {
+    pub change: NotebookDocumentChangeEvent
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+    pub cell_text_documents: Vec<TextDocumentIdentifier>
+}
@@ -0,0 +1,68 @@
+// Partial results: when a request comes with a `partialResultToken`, its results go out as
+// `$/progress` notifications while they are still being found, and the response itself is
+// left empty
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::ProgressToken;
+use tower_lsp::Client;
+
+// Large enough that a widget used hundreds of times doesn't mean hundreds of notifications
+const BATCH_SIZE: usize = 100;
+
+// `lsp_types` only knows the work done kind of `$/progress`
+pub enum PartialResultNotification {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartialResultProgress {
+    pub token: ProgressToken,
+    pub value: Value
+}
+
+impl Notification for PartialResultNotification {
+    type Params = PartialResultProgress;
+    const METHOD: &'static str = "$/progress";
+}
+
+pub struct PartialResults<'a, T> {
+    client: &'a Client,
+    token: Option<ProgressToken>,
+    pending: Vec<T>
+}
+
+impl<'a, T: Serialize> PartialResults<'a, T> {
+    pub fn new(client: &'a Client, token: Option<ProgressToken>) -> Self {
+        Self { client, token, pending: Vec::new() }
+    }
+
+    async fn send(&self, batch: &[T]) {
+        if let (Some(token), Ok(value)) = (&self.token, serde_json::to_value(batch)) {
+            self.client.send_notification::<PartialResultNotification>(PartialResultProgress { token: token.clone(), value }).await;
+        }
+    }
+
+    /// Adds results, sending every full batch right away when streaming. It also gives way to
+    /// the runtime, which is where a cancelled request stops, so call it between pieces of work.
+    pub async fn extend(&mut self, items: impl IntoIterator<Item = T>) {
+        self.pending.extend(items);
+        if self.token.is_some() {
+            while self.pending.len() >= BATCH_SIZE {
+                let batch: Vec<T> = self.pending.drain(..BATCH_SIZE).collect();
+                self.send(&batch).await;
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+
+    // What the response should hold: everything, or nothing once it has all been streamed
+    pub async fn finish(self) -> Vec<T> {
+        if self.token.is_none() {
+            return self.pending;
+        }
+        if !self.pending.is_empty() {
+            self.send(&self.pending).await;
+        }
+        Vec::new()
+    }
+}
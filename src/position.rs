@@ -0,0 +1,102 @@
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range, TextDocumentContentChangeEvent};
+
+// What a column counts. Every conversion takes one, since only what the client and server
+// agreed on in `initialize` is right
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PositionEncoding {
+    Utf8,
+    Utf16,
+    Utf32
+}
+
+impl PositionEncoding {
+    /// Chars when the client offers them, since that's what the server counts in, and UTF-16
+    /// otherwise, which every client understands and is what one that offers nothing means.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        match offered {
+            Some(offered) if offered.contains(&PositionEncodingKind::UTF32) => Self::Utf32,
+            _ => Self::Utf16
+        }
+    }
+
+    /// How many units `c` takes up.
+    pub fn len(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32
+        }
+    }
+}
+
+// How far into the document a char is, in units of `encoding`. Subtracting the value for
+// the start of its line gives its column
+pub fn units(rope: &Rope, char_index: usize, encoding: PositionEncoding) -> usize {
+    match encoding {
+        PositionEncoding::Utf8 => rope.char_to_byte(char_index),
+        PositionEncoding::Utf16 => rope.char_to_utf16_cu(char_index),
+        PositionEncoding::Utf32 => char_index
+    }
+}
+
+// The char `units` of `encoding` into the document is, or the one it falls inside
+fn char_at_units(rope: &Rope, units: usize, encoding: PositionEncoding) -> Option<usize> {
+    match encoding {
+        PositionEncoding::Utf8 => rope.try_byte_to_char(units).ok(),
+        PositionEncoding::Utf16 => rope.try_utf16_cu_to_char(units).ok(),
+        PositionEncoding::Utf32 => Some(units).filter(|units| *units <= rope.len_chars())
+    }
+}
+
+pub fn offset_to_position_in(rope: &Rope, offset: usize, encoding: PositionEncoding) -> Option<Position> {
+    let line = rope.try_byte_to_line(offset).ok()?;
+    let first = units(rope, rope.try_line_to_char(line).ok()?, encoding);
+    let character = units(rope, rope.try_byte_to_char(offset).ok()?, encoding) - first;
+    Some(Position::new(line as u32, character as u32))
+}
+
+pub fn position_to_offset_in(rope: &Rope, position: Position, encoding: PositionEncoding) -> Option<usize> {
+    let first = units(rope, rope.try_line_to_char(position.line as usize).ok()?, encoding);
+    rope.try_char_to_byte(char_at_units(rope, first + position.character as usize, encoding)?).ok()
+}
+
+pub fn range_to_lsp_in(rope: &Rope, range: &std::ops::Range<usize>, encoding: PositionEncoding) -> Option<Range> {
+    Some(Range::new(
+        offset_to_position_in(rope, range.start, encoding)?,
+        offset_to_position_in(rope, range.end, encoding)?
+    ))
+}
+
+pub fn lsp_to_range_in(rope: &Rope, range: Range, encoding: PositionEncoding) -> Option<std::ops::Range<usize>> {
+    Some(position_to_offset_in(rope, range.start, encoding)?..position_to_offset_in(rope, range.end, encoding)?)
+}
+
+/// Applies one of the changes a `didChange` carries: its text replaces `range`, counted in
+/// `encoding`, or the whole document when there is none. A range that isn't in the document, or ends before it starts,
+/// leaves it as it was and returns `None`.
+pub fn apply_change(rope: &mut Rope, change: &TextDocumentContentChangeEvent, encoding: PositionEncoding) -> Option<()> {
+    let range = match change.range {
+        Some(range) => range,
+        None => {
+            *rope = Rope::from_str(&change.text);
+            return Some(());
+        }
+    };
+    let bytes = lsp_to_range_in(rope, range, encoding)?;
+    if bytes.start > bytes.end {
+        return None;
+    }
+    let (start, end) = (rope.byte_to_char(bytes.start), rope.byte_to_char(bytes.end));
+    rope.remove(start..end);
+    rope.insert(start, &change.text);
+    Some(())
+}
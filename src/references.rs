@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use ropey::Rope;
+use tower_lsp::lsp_types::{DocumentHighlight, DocumentHighlightKind, Location, SymbolInformation, SymbolKind, Url};
+use crate::ast::{Ast, Node};
+use crate::position::{range_to_lsp_in, PositionEncoding};
+
+// One place a widget name appears
+pub struct NameUse {
+    pub range: Range<usize>,
+    // The name of a definition, rather than an object of it or a definition inheriting from it
+    pub declaration: bool
+}
+
+// Every use of `name` in the file, in document order
+pub fn name_uses(ast: &Ast, name: &str) -> Vec<NameUse> {
+    fn collect(nodes: &[Node], name: &str, uses: &mut Vec<NameUse>) {
+        for node in nodes {
+            match node {
+                Node::Definition(definition) => {
+                    if definition.name == name {
+                        uses.push(NameUse { range: definition.name_range.clone(), declaration: true });
+                    }
+                    for (parent, range) in &definition.inherits {
+                        if parent == name {
+                            uses.push(NameUse { range: range.clone(), declaration: false });
+                        }
+                    }
+                },
+                Node::Object(object) if object.name == name => {
+                    uses.push(NameUse { range: object.name_range.clone(), declaration: false });
+                },
+                _ => ()
+            }
+            collect(node.children(), name, uses);
+        }
+    }
+    let mut uses = Vec::new();
+    collect(&ast.nodes, name, &mut uses);
+    uses
+}
+
+pub fn locations(uri: &Url, rope: &Rope, encoding: PositionEncoding, uses: &[NameUse], include_declaration: bool) -> Vec<Location> {
+    uses.iter()
+        .filter(|name_use| include_declaration || !name_use.declaration)
+        .filter_map(|name_use| Some(Location::new(uri.clone(), range_to_lsp_in(rope, &name_use.range, encoding)?)))
+        .collect()
+}
+
+// Definitions count as writes and everything else as reads, so editors can tell them apart
+pub fn highlights(rope: &Rope, encoding: PositionEncoding, uses: &[NameUse]) -> Vec<DocumentHighlight> {
+    uses.iter()
+        .filter_map(|name_use| Some(DocumentHighlight {
+            range: range_to_lsp_in(rope, &name_use.range, encoding)?,
+            kind: Some(if name_use.declaration { DocumentHighlightKind::WRITE } else { DocumentHighlightKind::READ })
+        }))
+        .collect()
+}
+
+// Each parent's definitions name it in their inheritance, for going down the hierarchy
+pub fn add_children(ast: &Ast, children: &mut HashMap<String, HashSet<String>>) {
+    for node in &ast.nodes {
+        if let Node::Definition(definition) = node {
+            for (parent, _) in &definition.inherits {
+                children.entry(parent.clone()).or_default().insert(definition.name.clone());
+            }
+        }
+    }
+}
+
+// Every definition whose inheritance chain reaches `base`, however indirectly
+pub fn descendants(children: &HashMap<String, HashSet<String>>, base: &str) -> HashSet<String> {
+    let mut found = HashSet::new();
+    let mut pending = vec![base];
+    while let Some(name) = pending.pop() {
+        for child in children.get(name).into_iter().flatten() {
+            // A cycle ends when it gets back to a name already found
+            if child != base && found.insert(child.clone()) {
+                pending.push(child);
+            }
+        }
+    }
+    found
+}
+
+pub fn definition_locations(uri: &Url, rope: &Rope, encoding: PositionEncoding, ast: &Ast, names: &HashSet<String>) -> Vec<Location> {
+    ast.nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Definition(definition) if names.contains(&definition.name) => {
+                Some(Location::new(uri.clone(), range_to_lsp_in(rope, &definition.name_range, encoding)?))
+            },
+            _ => None
+        })
+        .collect()
+}
+
+// The query's characters in order, ignoring case, so `hbar` finds `AdwHeaderBar`
+pub fn matches_query(name: &str, query: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    query.chars().flat_map(char::to_lowercase).all(|c| name.any(|n| n == c))
+}
+
+pub fn has_matching_definition(ast: &Ast, query: &str) -> bool {
+    ast.nodes.iter().any(|node| matches!(node, Node::Definition(definition) if matches_query(&definition.name, query)))
+}
+
+// Construction of the lsp types needs the deprecated field, which is superseded by tags
+#[allow(deprecated)]
+pub fn workspace_symbols(uri: &Url, rope: &Rope, encoding: PositionEncoding, ast: &Ast, query: &str) -> Vec<SymbolInformation> {
+    ast.nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Definition(definition) if matches_query(&definition.name, query) => Some(SymbolInformation {
+                name: definition.name.clone(),
+                kind: SymbolKind::CLASS,
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), range_to_lsp_in(rope, &definition.name_range, encoding)?),
+                container_name: None
+            }),
+            _ => None
+        })
+        .collect()
+}
@@ -0,0 +1,52 @@
+// `gtkui.renameProperty`, for when GTK renames a property between versions and every
+// setter of it has to follow
+
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::TextEdit;
+use crate::ast::{self, Ast};
+use crate::database::WidgetDb;
+use crate::position::{range_to_lsp_in, PositionEncoding};
+
+pub const RENAME_PROPERTY: &str = "gtkui.renameProperty";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamePropertyParams {
+    pub widget: String,
+    pub from: String,
+    pub to: String,
+    // Count the edits without applying them
+    #[serde(default)]
+    pub dry_run: bool
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamePropertyResult {
+    pub edits: usize,
+    pub files: usize,
+    pub applied: bool
+}
+
+// Same characters the lexer accepts after a `.`
+pub fn is_property_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphabetic() || c == '-' || c == '_')
+}
+
+/// Edits renaming every `.from` setter on widgets that are `widget` or inherit from it. A
+/// subtype that declares a property of the same name itself shadows the one being renamed,
+/// so its setters are left alone.
+pub fn setter_edits(rope: &Rope, encoding: PositionEncoding, ast: &Ast, db: &WidgetDb, params: &RenamePropertyParams) -> Vec<TextEdit> {
+    ast::objects(ast)
+        .into_iter()
+        .filter(|object| db.ancestry(&object.name).contains(&params.widget.as_str()))
+        .filter(|object| match db.property(&object.name, &params.from) {
+            Some((owner, _)) => owner.name == params.widget || !db.ancestry(&owner.name).contains(&params.widget.as_str()),
+            None => true
+        })
+        .flat_map(|object| object.setters.iter().filter(|setter| setter.name == params.from))
+        .filter_map(|setter| Some(TextEdit::new(range_to_lsp_in(rope, &setter.name_range, encoding)?, format!(".{}", params.to))))
+        .collect()
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use gtk_ui::lexer::{IdentifierType, Token, TokenValue};
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, Range, SemanticToken, SemanticTokenType};
+use crate::lexer;
+use crate::position::{units, PositionEncoding};
+
+// Every standard type, so that any of them can be configured without registering a new
+// legend. The ones used by default come first
+pub const LEGEND_TYPE: &[SemanticTokenType] = &[
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::OPERATOR,
+    // Not a standard type, so clients fall back to the string around it when they don't know it
+    SemanticTokenType::new("escapeSequence"),
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::ENUM,
+    SemanticTokenType::INTERFACE,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::EVENT,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::MODIFIER,
+    SemanticTokenType::REGEXP
+];
+
+// The kinds of token that are highlighted, as the `semanticTokenTypes` setting names them,
+// and the type each gets unless configured otherwise. `true` and `false` are the members of
+// `Bool` rather than words of the language, so themes show them like other constants, while
+// the built-in type names are the closest the language has to keywords
+pub const TOKEN_KINDS: &[(&str, SemanticTokenType)] = &[
+    ("bool", SemanticTokenType::ENUM_MEMBER),
+    ("type", SemanticTokenType::KEYWORD),
+    ("number", SemanticTokenType::NUMBER),
+    ("setter", SemanticTokenType::METHOD),
+    ("string", SemanticTokenType::STRING),
+    ("directive", SemanticTokenType::MACRO),
+    ("definition", SemanticTokenType::CLASS),
+    ("inherits", SemanticTokenType::OPERATOR),
+    ("unit", SemanticTokenType::TYPE),
+    ("escape", SemanticTokenType::new("escapeSequence"))
+];
+
+fn kind(name: &str) -> usize {
+    TOKEN_KINDS.iter().position(|(kind, _)| *kind == name).unwrap()
+}
+
+fn legend_index(token_type: &str) -> Option<u32> {
+    LEGEND_TYPE.iter().position(|item| item.as_str() == token_type).map(|index| index as u32)
+}
+
+// The legend index each kind in `TOKEN_KINDS` is encoded with
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenTypes(Vec<u32>);
+
+impl Default for TokenTypes {
+    fn default() -> Self {
+        Self(TOKEN_KINDS.iter().map(|(_, token_type)| legend_index(token_type.as_str()).unwrap()).collect())
+    }
+}
+
+impl TokenTypes {
+    /// The defaults with `overrides` from kinds to type names on top. Overrides naming a kind or
+    /// type that doesn't exist are left out, and come back as the reasons why.
+    pub fn new(overrides: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut types = Self::default();
+        let mut ignored = Vec::new();
+        for (name, token_type) in overrides {
+            match (TOKEN_KINDS.iter().position(|(kind, _)| kind == name), legend_index(token_type)) {
+                (Some(kind), Some(index)) => types.0[kind] = index,
+                (None, _) => ignored.push(format!("`{}` is not a kind of token that is highlighted", name)),
+                (_, None) => ignored.push(format!("`{}` is not a semantic token type", token_type))
+            }
+        }
+        (types, ignored)
+    }
+}
+
+// A dimension like `10px` lexes as a number directly followed by an identifier; the
+// identifier is then the unit rather than the start of an object
+fn is_unit_suffix(tokens: &[Token], index: usize) -> bool {
+    match (index.checked_sub(1).and_then(|previous| tokens.get(previous)), tokens.get(index)) {
+        (Some(number), Some(unit)) => {
+            matches!(number.value, TokenValue::Number(_))
+                && matches!(unit.value, TokenValue::Identifier(_))
+                && number.range.end == unit.range.start
+        },
+        _ => false
+    }
+}
+
+fn token_kind(tokens: &[Token], index: usize) -> Option<usize> {
+    if is_unit_suffix(tokens, index) {
+        return Some(kind("unit"));
+    }
+    match &tokens[index].value {
+        TokenValue::Bool(_) => Some(kind("bool")),
+        TokenValue::Identifier(IdentifierType::Type(_)) => Some(kind("type")),
+        TokenValue::Number(_) => Some(kind("number")),
+        TokenValue::Setter(_) => Some(kind("setter")),
+        TokenValue::String(_) => Some(kind("string")),
+        TokenValue::Directive(_) => Some(kind("directive")),
+        TokenValue::Definition(_) => Some(kind("definition")),
+        TokenValue::Inherits => Some(kind("inherits")),
+        _ => None
+    }
+}
+
+/// Delta encodes the tokens that have a legend type. Tokens must be sorted by start, and
+/// columns and lengths are counted in units of `encoding`. Tokens outside the rope are skipped.
+/// Each kind of token gets the type `types` gives it. With `escapes`, escape sequences get a
+/// token of their own on top of their string's, which only clients that support overlapping
+/// tokens can show.
+pub fn encode_semantic_tokens(tokens: &[Token], rope: &Rope, encoding: PositionEncoding, types: &TokenTypes, escapes: bool) -> Vec<SemanticToken> {
+    let escape_type = types.0[kind("escape")];
+    let mut encoded = Vec::new();
+    let mut pre_line = 0;
+    let mut pre_start = 0;
+    let mut push = |line: u32, start: u32, end: u32, token_type: u32| {
+        let delta_line = line - pre_line;
+        let delta_start = if delta_line == 0 { start - pre_start } else { start };
+        pre_line = line;
+        pre_start = start;
+        encoded.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: end - start,
+            token_modifiers_bitset: 0,
+            token_type
+        });
+    };
+    // The current line: its number, the byte it ends at and where it starts in `encoding`.
+    // Tokens are sorted, so it only changes when a token starts past its end, which on a
+    // long minified line is almost never
+    let mut current: Option<(usize, usize, usize)> = None;
+    for (index, token) in tokens.iter().enumerate() {
+        let Some(token_type) = token_kind(tokens, index).map(|kind| types.0[kind]) else {
+            continue;
+        };
+        // Tokens lexed from some other version of the text can reach past its end
+        if token.range.start > token.range.end || token.range.end > rope.len_bytes() {
+            continue;
+        }
+        let (line, base) = match &current {
+            Some((line, end, base)) if token.range.start < *end => (*line, *base),
+            _ => {
+                let Ok(line) = rope.try_byte_to_line(token.range.start) else {
+                    continue;
+                };
+                let end = rope.try_line_to_byte(line + 1).unwrap_or(rope.len_bytes());
+                let Ok(start) = rope.try_line_to_char(line) else {
+                    continue;
+                };
+                let base = units(rope, start, encoding);
+                current = Some((line, end, base));
+                (line, base)
+            }
+        };
+        // Columns on the token's line, in units of `encoding`
+        let column = |byte: usize| rope.try_byte_to_char(byte).ok().map(|char| (units(rope, char, encoding) - base) as u32);
+        let (Some(start), Some(end)) = (column(token.range.start), column(token.range.end)) else {
+            continue;
+        };
+        push(line as u32, start, end, token_type);
+        if escapes && matches!(token.value, TokenValue::String(_)) {
+            // A string never goes past the end of its line, so neither do its escapes
+            let text = rope.byte_slice(token.range.clone()).to_string();
+            for escape in lexer::escape_ranges(&text) {
+                if let (Some(start), Some(end)) = (column(token.range.start + escape.start), column(token.range.start + escape.end)) {
+                    push(line as u32, start, end, escape_type);
+                }
+            }
+        }
+    }
+    encoded
+}
+
+// The encoded tokens starting inside the range. Deltas between them stay the same, only the
+// first one has to be made relative to the start of the document again
+pub fn slice_semantic_tokens(encoded: &[SemanticToken], range: Range) -> Vec<SemanticToken> {
+    let mut sliced: Vec<SemanticToken> = Vec::new();
+    let mut line = 0;
+    let mut start = 0;
+    for token in encoded {
+        line += token.delta_line;
+        start = if token.delta_line == 0 { start + token.delta_start } else { token.delta_start };
+        let position = Position::new(line, start);
+        if position < range.start {
+            continue;
+        }
+        if position >= range.end {
+            break;
+        }
+        sliced.push(if sliced.is_empty() {
+            SemanticToken { delta_line: line, delta_start: start, ..*token }
+        } else {
+            *token
+        });
+    }
+    sliced
+}
+
+// Identical token data always gets the same id, so clients can tell when nothing changed
+pub fn semantic_tokens_result_id(data: &[SemanticToken]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for token in data {
+        (token.delta_line, token.delta_start, token.length, token.token_type, token.token_modifiers_bitset).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
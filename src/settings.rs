@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::database::GtkVersion;
+
+pub const SECTION: &str = "gtkui-language-server";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticTrigger {
+    // Shortly after typing stops
+    #[default]
+    OnType,
+    // When the document is opened or saved
+    OnSave
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticSettings {
+    pub trigger: DiagnosticTrigger,
+    // Off by default since some teams spell out defaults on purpose
+    pub redundant_defaults: bool,
+    pub mixed_indentation: bool,
+    pub unknown_properties: bool,
+    // Objects that leave out arguments their definition declares
+    pub missing_arguments: bool,
+    // Setters that aren't inside any widget
+    pub properties_outside_widgets: bool,
+    // Where the parser skipped tokens or assumed a missing `}` or `)`
+    pub parser_recovery: bool,
+    // Widgets nested more levels deep than this. Off when unset
+    pub max_nesting_depth: Option<usize>,
+    // Includes that name a file none of the places looked in has
+    pub unresolved_includes: bool
+}
+
+impl Default for DiagnosticSettings {
+    fn default() -> Self {
+        Self {
+            trigger: DiagnosticTrigger::default(),
+            redundant_defaults: false,
+            mixed_indentation: false,
+            unknown_properties: true,
+            missing_arguments: true,
+            properties_outside_widgets: true,
+            parser_recovery: false,
+            max_nesting_depth: None,
+            unresolved_includes: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoTrigger {
+    // Also after `.` and `(`, where setters and arguments start
+    Always,
+    #[default]
+    OnTriggerChar,
+    // Only when the user asks for completion
+    Manual
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompletionSettings {
+    pub auto_trigger: AutoTrigger,
+    // Long lists are cut to this many and marked incomplete, so clients ask again as more
+    // is typed
+    pub max_items: usize,
+    // Widgets and properties used more across the workspace are offered first
+    pub rank_by_usage: bool
+}
+
+impl Default for CompletionSettings {
+    fn default() -> Self {
+        Self {
+            auto_trigger: AutoTrigger::default(),
+            max_items: 100,
+            rank_by_usage: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CodeActionSettings {
+    // The widget that "wrap in widget" puts the selection into
+    pub wrap_container: String
+}
+
+impl Default for CodeActionSettings {
+    fn default() -> Self {
+        Self {
+            wrap_container: String::from("GtkBox")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InlayHintSettings {
+    // The type a setter's property takes
+    pub types: bool,
+    // The default of a property a setter changes
+    pub defaults: bool
+}
+
+impl Default for InlayHintSettings {
+    fn default() -> Self {
+        Self {
+            types: true,
+            defaults: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HoverSettings {
+    // Where a widget's reference lives, with `{widget}` standing for its name without the
+    // `Gtk` prefix
+    pub docs_url_template: String
+}
+
+impl Default for HoverSettings {
+    fn default() -> Self {
+        Self {
+            docs_url_template: String::from("https://docs.gtk.org/gtk4/class.{widget}.html")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LineEndings {
+    // Whichever the document already uses most
+    #[default]
+    Preserve,
+    Lf,
+    Crlf
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatSettings {
+    pub line_endings: LineEndings
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CustomWidget {
+    pub inherits: Vec<String>,
+    // Each property's type: "String", "Number" or "Bool"
+    pub properties: BTreeMap<String, String>
+}
+
+// Widgets from libraries that have no `.gui` definitions, keyed by name. Either the widgets
+// themselves or the path of a JSON file holding them, relative to the first workspace folder
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+pub enum CustomWidgets {
+    Path(String),
+    Inline(BTreeMap<String, CustomWidget>)
+}
+
+// How much the server writes to the client's log, each level including the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Error,
+    Warning,
+    #[default]
+    Info,
+    // Per change messages
+    Debug,
+    // Per request messages
+    Trace
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub semantic_tokens: bool,
+    // The semantic token type a kind of token is highlighted as, over the defaults
+    pub semantic_token_types: BTreeMap<String, String>,
+    pub diagnostics: DiagnosticSettings,
+    pub completion: CompletionSettings,
+    pub code_actions: CodeActionSettings,
+    pub format: FormatSettings,
+    pub inlay_hints: InlayHintSettings,
+    pub hover: HoverSettings,
+    pub log_level: LogLevel,
+    // Globs of files that are neither indexed nor validated, such as generated layouts
+    pub exclude: Vec<String>,
+    // Where includes are looked for after the document's own directory, in order. Relative
+    // ones are taken from the workspace folder the document is in
+    pub import_base_paths: Vec<String>,
+    // Documents bigger than this many KB are kept as text only, without being lexed
+    pub max_file_size_kb: usize,
+    // The GTK release properties are checked against, such as "4.6". Unchecked when unset
+    pub gtk_version: Option<String>,
+    pub custom_widgets: Option<CustomWidgets>
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            semantic_tokens: true,
+            semantic_token_types: BTreeMap::new(),
+            diagnostics: DiagnosticSettings::default(),
+            completion: CompletionSettings::default(),
+            code_actions: CodeActionSettings::default(),
+            format: FormatSettings::default(),
+            inlay_hints: InlayHintSettings::default(),
+            hover: HoverSettings::default(),
+            log_level: LogLevel::default(),
+            exclude: ["**/target/**", "**/build/**", "**/node_modules/**"].map(String::from).to_vec(),
+            import_base_paths: Vec::new(),
+            max_file_size_kb: 2048,
+            gtk_version: None,
+            custom_widgets: None
+        }
+    }
+}
+
+impl Settings {
+    // Clients send either the bare settings object or one nested under our section
+    pub fn from_value(value: Value) -> Self {
+        Self::parse(value).0
+    }
+
+    // Fields that can't be read are left at their defaults, with what was wrong with each, so
+    // one typo doesn't undo the rest of the settings
+    pub fn parse(value: Value) -> (Self, Vec<String>) {
+        let value = match value.get(SECTION) {
+            Some(section) => section.clone(),
+            None => value
+        };
+        let mut errors = Vec::new();
+        let value = match value {
+            Value::Null => return (Self::default(), errors),
+            Value::Object(fields) => Value::Object(valid_fields(fields, "", &Value::Object, &mut errors)),
+            value => value
+        };
+        let settings = serde_json::from_value(value).unwrap_or_else(|err| {
+            errors.push(err.to_string());
+            Self::default()
+        });
+        (settings, errors)
+    }
+
+    // Left unset when it isn't a version at all
+    pub fn gtk_version(&self) -> Option<GtkVersion> {
+        self.gtk_version.as_deref().and_then(GtkVersion::parse)
+    }
+}
+
+// The fields that `wrap` still makes settings of on their own. Groups are gone through field
+// by field, so a bad one doesn't take its neighbours with it
+fn valid_fields(fields: Map<String, Value>, path: &str, wrap: &dyn Fn(Map<String, Value>) -> Value, errors: &mut Vec<String>) -> Map<String, Value> {
+    let mut valid = Map::new();
+    for (key, value) in fields {
+        let name = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        let alone = |value: Value| wrap(Map::from_iter([(key.clone(), value)]));
+        match (serde_json::from_value::<Settings>(alone(value.clone())), value) {
+            (Ok(_), value) => {
+                valid.insert(key.clone(), value);
+            }
+            (Err(_), Value::Object(inner)) => {
+                let inner = valid_fields(inner, &name, &|inner| alone(Value::Object(inner)), errors);
+                valid.insert(key.clone(), Value::Object(inner));
+            }
+            (Err(err), _) => errors.push(format!("{}: {}", name, err))
+        }
+    }
+    valid
+}
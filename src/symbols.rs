@@ -0,0 +1,77 @@
+use ropey::Rope;
+use tower_lsp::lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind, Url};
+use crate::ast::{Ast, Node};
+use crate::database::literal;
+use crate::position::{range_to_lsp_in, PositionEncoding};
+
+// Construction of the lsp types needs the deprecated field, which is superseded by tags
+#[allow(deprecated)]
+fn symbol(name: String, detail: Option<String>, kind: SymbolKind, range: tower_lsp::lsp_types::Range, selection_range: tower_lsp::lsp_types::Range, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: if children.is_empty() { None } else { Some(children) }
+    }
+}
+
+// Definitions with their properties, and the object tree. Directives and setters are left out
+// so the outline stays readable
+pub fn document_symbols(rope: &Rope, encoding: PositionEncoding, ast: &Ast) -> Vec<DocumentSymbol> {
+    fn collect(rope: &Rope, encoding: PositionEncoding, nodes: &[Node]) -> Vec<DocumentSymbol> {
+        nodes.iter().filter_map(|node| match node {
+            Node::Definition(definition) => Some(symbol(
+                definition.name.clone(),
+                Some(definition.inherits.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ")).filter(|detail| !detail.is_empty()),
+                SymbolKind::CLASS,
+                range_to_lsp_in(rope, &definition.range, encoding)?,
+                range_to_lsp_in(rope, &definition.name_range, encoding)?,
+                collect(rope, encoding, &definition.children)
+            )),
+            // Selecting the name puts the cursor on it, rather than on the `@` of the whole line
+            Node::Property(property) => Some(symbol(
+                property.name.clone()?,
+                None,
+                SymbolKind::PROPERTY,
+                range_to_lsp_in(rope, &property.range, encoding)?,
+                range_to_lsp_in(rope, property.name_range.as_ref()?, encoding)?,
+                Vec::new()
+            )),
+            Node::Object(object) => Some(symbol(
+                object.name.clone(),
+                object.arguments.first().and_then(literal),
+                SymbolKind::OBJECT,
+                range_to_lsp_in(rope, &object.range, encoding)?,
+                range_to_lsp_in(rope, &object.name_range, encoding)?,
+                collect(rope, encoding, &object.children)
+            )),
+            Node::Directive(_) => None
+        }).collect()
+    }
+    collect(rope, encoding, &ast.nodes)
+}
+
+// For clients without hierarchical support: every symbol in document order, naming its parent
+#[allow(deprecated)]
+pub fn flatten(uri: &Url, symbols: Vec<DocumentSymbol>) -> Vec<SymbolInformation> {
+    fn visit(uri: &Url, symbols: Vec<DocumentSymbol>, container: Option<&str>, flat: &mut Vec<SymbolInformation>) {
+        for symbol in symbols {
+            flat.push(SymbolInformation {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), symbol.range),
+                container_name: container.map(String::from)
+            });
+            visit(uri, symbol.children.unwrap_or_default(), Some(&symbol.name), flat);
+        }
+    }
+    let mut flat = Vec::new();
+    visit(uri, symbols, None, &mut flat);
+    flat
+}
@@ -0,0 +1,110 @@
+// `gtkui.toggleBoolean`, for flipping `visible`, `sensitive` and the like from a keybinding, and
+// `gtkui.toggleComment` for commenting lines out and back in
+
+use ropey::Rope;
+use serde::Deserialize;
+use gtk_ui::lexer::{Token, TokenValue, TypeIdentifierType};
+use tower_lsp::lsp_types::{Position, Range, TextDocumentIdentifier, TextEdit};
+use crate::ast::{self, Ast, Node};
+use crate::database::WidgetDb;
+use crate::formatter::{detect_indent_unit, line_indent};
+use crate::lexer::LINE_COMMENT;
+use crate::position::{offset_to_position_in, range_to_lsp_in, PositionEncoding};
+
+pub const TOGGLE_BOOLEAN: &str = "gtkui.toggleBoolean";
+pub const TOGGLE_COMMENT: &str = "gtkui.toggleComment";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToggleBooleanParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    // Set to `true` on the widget at the position when it has no setter of it yet
+    #[serde(default)]
+    pub property: Option<String>
+}
+
+fn is_bool(db: &WidgetDb, widget: &str, property: &str) -> bool {
+    db.property(widget, property).is_some_and(|(_, info)| matches!(info.internal_type, Some(TypeIdentifierType::Bool)))
+}
+
+/// Flips the Bool setter at `offset`, or adds a `true` one for `property` to the widget at
+/// `offset` if that has none. Anything that isn't a Bool property is refused, with the reason.
+pub fn toggle_edit(source: &str, rope: &Rope, encoding: PositionEncoding, ast: &Ast, db: &WidgetDb, offset: usize, property: Option<&str>) -> Result<TextEdit, String> {
+    if let Some((object, setter)) = ast::setter_at(ast, offset) {
+        if !is_bool(db, &object.name, &setter.name) {
+            return Err(format!("`.{}` is not a Bool property of `{}`", setter.name, object.name));
+        }
+        return match &setter.value {
+            Some(value @ Token { value: TokenValue::Bool(current), .. }) => {
+                let range = range_to_lsp_in(rope, &value.range, encoding).ok_or_else(|| String::from("the setter is outside the document"))?;
+                Ok(TextEdit::new(range, String::from(if *current != 0 { "false" } else { "true" })))
+            },
+            _ => Err(format!("`.{}` isn't set to true or false", setter.name))
+        };
+    }
+
+    let property = property.ok_or_else(|| String::from("there is no setter here"))?;
+    let object = match ast::enclosing_definition(ast, offset) {
+        Some(Node::Object(object)) => object,
+        _ => return Err(String::from("there is no widget here"))
+    };
+    if !is_bool(db, &object.name, property) {
+        return Err(format!("`.{}` is not a Bool property of `{}`", property, object.name));
+    }
+    if object.setters.iter().any(|setter| setter.name == property) {
+        return Err(format!("`{}` already sets `.{}`", object.name, property));
+    }
+    // Lined up with the setters already there, or a level in from the widget
+    let indent = match object.setters.last() {
+        Some(setter) => line_indent(source, setter.range.start).to_string(),
+        None => format!("{}{}", line_indent(source, object.range.start), detect_indent_unit(source))
+    };
+    let end = offset_to_position_in(rope, object.range.end, encoding).ok_or_else(|| String::from("the widget is outside the document"))?;
+    Ok(TextEdit::new(Range::new(end, end), format!("\n{}.{}(true)", indent, property)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToggleCommentParams {
+    pub text_document: TextDocumentIdentifier,
+    // Every line it touches is toggled, except one it only ends at the start of
+    pub range: Range
+}
+
+/// Comments out the lines of `range`, or comments them back in if every one of them already
+/// is. Blank lines are left alone, and comments are added at the smallest indentation so they
+/// line up.
+pub fn comment_edits(rope: &Rope, range: Range) -> Vec<TextEdit> {
+    let first = range.start.line as usize;
+    let mut last = (range.end.line as usize).min(rope.len_lines().saturating_sub(1));
+    if last > first && range.end.character == 0 {
+        last -= 1;
+    }
+    let lines: Vec<(usize, String)> = (first..=last)
+        .filter_map(|line| Some((line, rope.get_line(line)?.to_string())))
+        .filter(|(_, text)| !text.trim().is_empty())
+        .collect();
+    let indent = |text: &str| text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+
+    if lines.iter().all(|(_, text)| text.trim_start().starts_with('/')) {
+        return lines
+            .iter()
+            .map(|(line, text)| {
+                let comment = &text[indent(text)..];
+                let marker = comment.len() - comment.trim_start_matches('/').len();
+                let space = comment[marker..].starts_with(' ') as usize;
+                let start = Position::new(*line as u32, indent(text) as u32);
+                TextEdit::new(Range::new(start, Position::new(start.line, start.character + (marker + space) as u32)), String::new())
+            })
+            .collect();
+    }
+    let column = lines.iter().map(|(_, text)| indent(text)).min().unwrap_or(0) as u32;
+    lines
+        .iter()
+        .map(|(line, _)| {
+            let at = Position::new(*line as u32, column);
+            TextEdit::new(Range::new(at, at), format!("{} ", LINE_COMMENT))
+        })
+        .collect()
+}
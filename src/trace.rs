@@ -0,0 +1,105 @@
+// `$/setTrace` and `$/logTrace`. While the client has tracing on, each request and
+// notification the server handles is reported as it comes in and again as it's answered, with
+// how long that took. `verbose` adds the parameters and results. `Traced` wraps the service to
+// see every message, the same way `Lifecycle` does to notice `exit`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower_lsp::Client;
+use tower_lsp::jsonrpc::{Request, Response};
+use tower_lsp::lsp_types::notification::LogTrace;
+use tower_lsp::lsp_types::{LogTraceParams, TraceValue};
+use tower_service::Service;
+
+#[derive(Debug)]
+pub struct Tracer {
+    value: RwLock<TraceValue>,
+    client: Client
+}
+
+impl Tracer {
+    pub fn new(client: Client) -> Self {
+        Self {
+            value: RwLock::new(TraceValue::Off),
+            client
+        }
+    }
+
+    pub fn set(&self, value: TraceValue) {
+        *self.value.write().unwrap() = value;
+    }
+
+    pub fn value(&self) -> TraceValue {
+        *self.value.read().unwrap()
+    }
+
+    /// Sends `message` if tracing is on, along with what `verbose` gives when it's verbose.
+    pub async fn log(&self, message: impl Into<String>, verbose: impl FnOnce() -> String) {
+        let verbose = match self.value() {
+            TraceValue::Off => return,
+            TraceValue::Messages => None,
+            TraceValue::Verbose => Some(verbose())
+        };
+        self.client.send_notification::<LogTrace>(LogTraceParams { message: message.into(), verbose }).await;
+    }
+}
+
+pub struct Traced<S> {
+    inner: S,
+    tracer: Arc<Tracer>
+}
+
+impl<S> Traced<S> {
+    pub fn new(inner: S, tracer: Arc<Tracer>) -> Self {
+        Self { inner, tracer }
+    }
+}
+
+impl<S> Service<Request> for Traced<S>
+where
+    S: Service<Request, Response = Option<Response>>,
+    S::Future: Send + 'static,
+    S::Error: Send
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let tracer = self.tracer.clone();
+        // Like the messages of VS Code's own client side tracing, so the two read alike
+        let name = match request.id() {
+            Some(id) => format!("request '{} - ({})'", request.method(), id),
+            None => format!("notification '{}'", request.method())
+        };
+        let params = match tracer.value() {
+            TraceValue::Verbose => request.params().map(|params| params.to_string()),
+            _ => None
+        };
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let params = params.unwrap_or_else(|| String::from("No parameters provided."));
+            tracer.log(format!("Received {}.", name), || format!("Params: {}", params)).await;
+            let started = Instant::now();
+            let response = response.await;
+            let took = started.elapsed().as_millis();
+            if let Ok(Some(answer)) = &response {
+                let result = match answer.error() {
+                    Some(error) => format!("Error: {}", error),
+                    None => format!("Result: {}", answer.result().map(|result| result.to_string()).unwrap_or_default())
+                };
+                tracer.log(format!("Sending response to {}. Processing took {}ms.", name, took), || result).await;
+            } else {
+                tracer.log(format!("Handled {} in {}ms.", name, took), String::new).await;
+            }
+            response
+        })
+    }
+}
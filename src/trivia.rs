@@ -0,0 +1,170 @@
+// Everything between the tokens that mean something: whitespace, which never becomes a
+// token, comments, which do when lexed with `include_trivia`, and text the lexer couldn't
+// read. Each piece belongs to one token. What follows a token on its own line trails it, and
+// everything from the next line on leads the token after, so a comment written above a
+// statement travels with that statement and one written after it stays on its line.
+
+use std::ops::Range;
+use gtk_ui::lexer::{Token, TokenValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    // Spaces and tabs
+    Whitespace,
+    // One line ending, `\r\n` included
+    Newline,
+    Comment,
+    // Text a lex error covers, which has no token
+    Skipped
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub range: Range<usize>
+}
+
+impl Trivia {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.range.clone()]
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attached {
+    range: Range<usize>,
+    leading: Vec<Trivia>,
+    trailing: Vec<Trivia>
+}
+
+// The trivia of a document, attached to its tokens
+#[derive(Debug, Clone, Default)]
+pub struct TriviaMap {
+    tokens: Vec<Attached>,
+    // After the last token, or all of it when there are none
+    end: Vec<Trivia>
+}
+
+// Splits a gap between tokens into whitespace, line endings and skipped text
+fn split_gap(source: &str, gap: Range<usize>, pieces: &mut Vec<Trivia>) {
+    let text = &source[gap.clone()];
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let start = gap.start + index;
+        let (kind, mut end) = match c {
+            '\r' if text[index + 1..].starts_with('\n') => {
+                chars.next();
+                (TriviaKind::Newline, start + 2)
+            },
+            '\n' | '\r' => (TriviaKind::Newline, start + 1),
+            ' ' | '\t' => (TriviaKind::Whitespace, start + 1),
+            _ => (TriviaKind::Skipped, start + c.len_utf8())
+        };
+        // Runs of the same kind are one piece, except line endings which are counted
+        if kind != TriviaKind::Newline {
+            while let Some((index, next)) = chars.next_if(|(_, next)| kind_of(*next) == kind) {
+                end = gap.start + index + next.len_utf8();
+            }
+        }
+        pieces.push(Trivia { kind, range: start..end });
+    }
+}
+
+fn kind_of(c: char) -> TriviaKind {
+    match c {
+        '\n' | '\r' => TriviaKind::Newline,
+        ' ' | '\t' => TriviaKind::Whitespace,
+        _ => TriviaKind::Skipped
+    }
+}
+
+/// Every piece of trivia in `source`, in order. `tokens` must be sorted and lexed from
+/// `source`; with their comments the pieces and the other tokens cover it exactly once.
+pub fn trivia(source: &str, tokens: &[Token]) -> Vec<Trivia> {
+    let mut pieces = Vec::new();
+    let mut at = 0;
+    for token in tokens {
+        if token.range.start < at || token.range.end > source.len() {
+            continue;
+        }
+        split_gap(source, at..token.range.start, &mut pieces);
+        if let TokenValue::Comment = token.value {
+            pieces.push(Trivia { kind: TriviaKind::Comment, range: token.range.clone() });
+        }
+        at = token.range.end;
+    }
+    split_gap(source, at..source.len(), &mut pieces);
+    pieces
+}
+
+impl TriviaMap {
+    pub fn new(source: &str, tokens: &[Token]) -> Self {
+        let mut map = Self::default();
+        let mut pieces = trivia(source, tokens).into_iter().peekable();
+        let significant = tokens.iter().filter(|token| !matches!(token.value, TokenValue::Comment) && token.range.end <= source.len());
+        for token in significant {
+            let mut leading = Vec::new();
+            while let Some(piece) = pieces.next_if(|piece| piece.range.end <= token.range.start) {
+                leading.push(piece);
+            }
+            // Up to this token, the trailing trivia of the one before is still being read
+            let split = match map.tokens.last() {
+                Some(_) => leading.iter().position(|piece| piece.kind == TriviaKind::Newline).map(|newline| newline + 1).unwrap_or(leading.len()),
+                None => 0
+            };
+            let rest = leading.split_off(split);
+            if let Some(previous) = map.tokens.last_mut() {
+                previous.trailing = leading;
+            }
+            map.tokens.push(Attached { range: token.range.clone(), leading: rest, trailing: Vec::new() });
+        }
+        let mut end: Vec<Trivia> = pieces.collect();
+        if let Some(last) = map.tokens.last_mut() {
+            let split = end.iter().position(|piece| piece.kind == TriviaKind::Newline).map(|newline| newline + 1).unwrap_or(end.len());
+            let rest = end.split_off(split);
+            last.trailing = end;
+            end = rest;
+        }
+        map.end = end;
+        map
+    }
+
+    fn token_starting(&self, byte: usize) -> Option<&Attached> {
+        let index = self.tokens.binary_search_by_key(&byte, |token| token.range.start).ok()?;
+        Some(&self.tokens[index])
+    }
+
+    fn token_ending(&self, byte: usize) -> Option<&Attached> {
+        let index = self.tokens.binary_search_by_key(&byte, |token| token.range.end).ok()?;
+        Some(&self.tokens[index])
+    }
+
+    // What comes before the token that starts at `byte`, from the line after the previous one
+    pub fn leading(&self, byte: usize) -> &[Trivia] {
+        self.token_starting(byte).map(|token| token.leading.as_slice()).unwrap_or_default()
+    }
+
+    // What follows the token that ends at `byte` on its line, the line ending included
+    pub fn trailing(&self, byte: usize) -> &[Trivia] {
+        self.token_ending(byte).map(|token| token.trailing.as_slice()).unwrap_or_default()
+    }
+
+    /// The trivia leading and trailing a node, given its range, such as the comments above an
+    /// object and the one after its last setter.
+    pub fn around(&self, range: &Range<usize>) -> (&[Trivia], &[Trivia]) {
+        (self.leading(range.start), self.trailing(range.end))
+    }
+
+    // After the last token
+    pub fn end(&self) -> &[Trivia] {
+        &self.end
+    }
+
+    // Every piece, in the order it appears in the source
+    pub fn iter(&self) -> impl Iterator<Item = &Trivia> {
+        self.tokens
+            .iter()
+            .flat_map(|token| token.leading.iter().chain(&token.trailing))
+            .chain(&self.end)
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tower_lsp::lsp_types::Url;
+use crate::ast::{self, Ast};
+use crate::glob::Glob;
+use crate::lexer::{self, LexOptions};
+
+// The `exclude` setting, matched against paths relative to the workspace root they are in,
+// or against the whole path for files outside of every root
+#[derive(Debug, Clone)]
+pub struct Exclusions {
+    globs: Vec<Glob>,
+    roots: Vec<PathBuf>
+}
+
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Exclusions {
+    pub fn new(patterns: &[String], roots: &[PathBuf]) -> Self {
+        Self {
+            globs: patterns.iter().map(|pattern| Glob::new(pattern)).collect(),
+            roots: roots.to_vec()
+        }
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let relative = self.roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        let relative = slash_path(relative);
+        self.globs.iter().any(|glob| glob.matches(&relative))
+    }
+}
+
+pub fn gui_files(roots: &[PathBuf], exclusions: &Exclusions) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = roots.to_vec();
+    while let Some(directory) = pending.pop() {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+        for entry in entries.flatten() {
+            // Symlinks aren't followed, so a link back up the tree can't loop forever
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue
+            };
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let path = entry.path();
+            if exclusions.matches(&path) {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !name.starts_with('.') {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() && is_gui_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+pub fn is_gui_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gui")
+}
+
+// Where editors show documents that can't be written back, like a file as it was at some
+// commit or one inside an archive. Edits to them could never be applied
+const READ_ONLY_SCHEMES: &[&str] = &["git", "gitlens", "zip", "tar", "jar", "output"];
+
+pub fn is_read_only(uri: &Url) -> bool {
+    READ_ONLY_SCHEMES.contains(&uri.scheme())
+}
+
+pub fn index_file(path: &Path) -> Option<Ast> {
+    let text = fs::read_to_string(path).ok()?;
+    let (tokens, _) = lexer::lex_with(&text, LexOptions { include_trivia: false, allow_recovery: true }).ok()?;
+    Some(ast::parse(&tokens))
+}
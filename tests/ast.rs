@@ -0,0 +1,80 @@
+// Finding the widget around an offset, which several features start from, both through the
+// tree and through the tokens alone for when the tree is broken
+
+use gtkui_language_server::{ast, lexer};
+use ast::Node;
+use lexer::LexOptions;
+
+const DOCUMENT: &str = "\
+@Card -> GtkBox {
+    @InlineProp(\"title\", String)
+}
+
+@Main -> GtkBox {
+    Card {
+        GtkLabel(\"a\") {}
+    }
+        .title(\"Hello\")
+}
+";
+
+fn parse(text: &str) -> (Vec<gtk_ui::lexer::Token>, ast::Ast) {
+    let (tokens, _) = lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }).unwrap();
+    let ast = ast::parse(&tokens);
+    (tokens, ast)
+}
+
+fn name(node: &Node) -> &str {
+    match node {
+        Node::Object(object) => &object.name,
+        Node::Definition(definition) => &definition.name,
+        _ => panic!("not a widget")
+    }
+}
+
+// Where `needle` starts in the document
+fn at(needle: &str) -> usize {
+    DOCUMENT.find(needle).expect("needle not in the document")
+}
+
+#[test]
+fn the_innermost_widget_encloses_an_offset() {
+    let (tokens, ast) = parse(DOCUMENT);
+    let cases = [
+        (at("@InlineProp"), Some("Card")),
+        (at("Card {"), Some("Card")),
+        (at("GtkLabel"), Some("GtkLabel")),
+        (at("\"a\""), Some("GtkLabel")),
+        (at("{}"), Some("GtkLabel")),
+        (at("{}") + 1, Some("GtkLabel")),
+        // A setter after the block still belongs to the widget
+        (at(".title(\"Hello"), Some("Card")),
+        (DOCUMENT.rfind('}').unwrap(), Some("Main")),
+        (at("\n\n@Main"), None),
+        (DOCUMENT.len() - 1, None)
+    ];
+    for (offset, expected) in cases {
+        assert_eq!(ast::enclosing_definition(&ast, offset).map(name), expected, "at {}", offset);
+    }
+    // The tokens can't see setters, but agree inside blocks and on their braces
+    for (offset, expected) in [
+        (at("@InlineProp"), Some("Card")),
+        (at("Card {") + 5, Some("Card")),
+        (at("{}"), Some("GtkLabel")),
+        (at("{}") + 1, Some("GtkLabel")),
+        (DOCUMENT.rfind('}').unwrap(), Some("Main")),
+        (at("\n\n@Main"), None)
+    ] {
+        assert_eq!(ast::enclosing_name(&tokens, offset), expected, "at {}", offset);
+    }
+}
+
+#[test]
+fn the_tokens_stand_in_for_a_broken_tree() {
+    let text = "@Main -> (GtkBox) {\n    Card {\n        .title(\n    }\n    GtkLabel {\n";
+    let (tokens, ast) = parse(text);
+    assert!(!ast.errors.is_empty());
+    assert_eq!(ast::enclosing_name(&tokens, text.find(".title").unwrap()), Some("Card"));
+    assert_eq!(ast::enclosing_name(&tokens, text.find("GtkLabel").unwrap()), Some("Main"));
+    assert_eq!(ast::enclosing_name(&tokens, text.len()), Some("GtkLabel"));
+}
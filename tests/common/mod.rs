@@ -0,0 +1,55 @@
+// What the test crates share: the files in tests/corpus, and the random edits made to them.
+// Each crate uses only some of it
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every .gui file in tests/corpus, sorted so failures come in the same order each run
+pub fn corpus() -> Vec<PathBuf> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<_> = fs::read_dir(&corpus)
+        .expect("tests/corpus is missing")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "gui"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "tests/corpus has no .gui files");
+    files
+}
+
+// Calls `run` with the name and text of each corpus file
+pub fn for_each_corpus_file(mut run: impl FnMut(&str, &str)) {
+    for path in corpus() {
+        let source = fs::read_to_string(&path).unwrap();
+        run(&path.file_name().unwrap().to_string_lossy(), &source);
+    }
+}
+
+// xorshift64*, so a failing seed reproduces without pulling in a dependency
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+// The closest character boundary at or before `index`
+pub fn char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
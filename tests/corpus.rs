@@ -0,0 +1,120 @@
+// Every file in tests/corpus next to a snapshot of what the server makes of it: how it
+// lexes, what the parser had to do to get through it, and the diagnostics it would publish.
+// A change in behaviour shows up as a changed snapshot, and a bug is pinned down by adding the
+// file that shows it. Run with `UPDATE_SNAPSHOTS=1` to write the snapshots afresh, then review
+// the difference before committing it.
+
+mod common;
+
+use std::fmt::Write;
+use std::fs;
+use gtkui_language_server::{ast, database, diagnostics, formatter, lexer, position, settings};
+use database::WidgetDb;
+use lexer::LexOptions;
+use position::PositionEncoding;
+use serde_json::json;
+use settings::{LineEndings, Settings};
+use tower_lsp::lsp_types::{DiagnosticSeverity, FormattingOptions, NumberOrString, Range};
+
+// Every rule on, so the snapshots cover all of them
+fn settings() -> Settings {
+    Settings::from_value(json!({
+        "diagnostics": {
+            "redundantDefaults": true,
+            "mixedIndentation": true,
+            "unknownProperties": true,
+            "missingArguments": true,
+            "propertiesOutsideWidgets": true,
+            "parserRecovery": true,
+            "maxNestingDepth": 3
+        },
+        "gtkVersion": "4.0"
+    }))
+}
+
+// Lines and characters count from 0, as the protocol does
+fn range(range: &Range) -> String {
+    format!("{}:{}-{}:{}", range.start.line, range.start.character, range.end.line, range.end.character)
+}
+
+fn byte_range(text: &str, bytes: &std::ops::Range<usize>) -> String {
+    let rope = ropey::Rope::from_str(text);
+    match position::range_to_lsp_in(&rope, bytes, PositionEncoding::Utf16) {
+        Some(converted) => range(&converted),
+        None => format!("{}..{}", bytes.start, bytes.end)
+    }
+}
+
+fn snapshot(text: &str) -> String {
+    let mut snapshot = String::new();
+    match lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }) {
+        Ok((tokens, errors)) => {
+            let ast = ast::parse(&tokens);
+            writeln!(snapshot, "tokens: {}", tokens.len()).unwrap();
+            writeln!(snapshot, "nodes: {}", ast.nodes.len()).unwrap();
+            for error in &errors {
+                writeln!(snapshot, "lex error {} {}", byte_range(text, &error.range), error.message).unwrap();
+            }
+            for error in &ast.errors {
+                writeln!(snapshot, "parse error {} {}", byte_range(text, &error.range), error.message).unwrap();
+            }
+        },
+        Err(failure) => writeln!(snapshot, "lex failure {}", failure.message).unwrap()
+    }
+    for diagnostic in diagnostics::compute_diagnostics(text, PositionEncoding::Utf16, &settings(), &WidgetDb::default()) {
+        let severity = match diagnostic.severity {
+            Some(DiagnosticSeverity::ERROR) => "error",
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "information",
+            _ => "hint"
+        };
+        let code = match &diagnostic.code {
+            Some(NumberOrString::String(code)) => code.clone(),
+            _ => String::new()
+        };
+        writeln!(snapshot, "{} {} {} {}", severity, code, range(&diagnostic.range), diagnostic.message).unwrap();
+    }
+    snapshot
+}
+
+#[test]
+fn corpus_matches_its_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut mismatches = Vec::new();
+    for path in common::corpus() {
+        let actual = snapshot(&fs::read_to_string(&path).unwrap());
+        let snapshot_path = path.with_extension("snap");
+        if update {
+            fs::write(&snapshot_path, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&snapshot_path) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => mismatches.push(format!("{}\n--- expected\n{}--- actual\n{}", path.display(), expected, actual)),
+            Err(_) => mismatches.push(format!("{} has no snapshot, run with UPDATE_SNAPSHOTS=1 to record one", path.display()))
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+fn format(text: &str) -> Option<String> {
+    let (tokens, errors) = lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: false }).ok()?;
+    // Formatting leaves a document it can't fully read alone
+    if !errors.is_empty() {
+        return None;
+    }
+    let options = FormattingOptions { tab_size: 4, insert_spaces: true, ..Default::default() };
+    Some(formatter::format(text, &tokens, &options, formatter::line_ending(text, LineEndings::Preserve)))
+}
+
+#[test]
+fn formatting_the_corpus_again_changes_nothing() {
+    let mut formatted_any = false;
+    for path in common::corpus() {
+        let Some(once) = format(&fs::read_to_string(&path).unwrap()) else { continue };
+        formatted_any = true;
+        let twice = format(&once).unwrap_or_else(|| panic!("{} doesn't lex once formatted:\n{}", path.display(), once));
+        assert_eq!(twice, once, "{} changed when formatted again", path.display());
+    }
+    assert!(formatted_any);
+}
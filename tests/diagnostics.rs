@@ -0,0 +1,141 @@
+// What gets reported for documents with something wrong in them, computed the same way the
+// server does but without one running
+
+use gtkui_language_server::{database, diagnostics, position, settings};
+use database::WidgetDb;
+use diagnostics::compute_diagnostics;
+use position::PositionEncoding;
+use serde_json::{json, Value};
+use settings::Settings;
+
+// Code, start and end as (line, character), and message
+type Reported = (String, (u32, u32), (u32, u32), String);
+
+fn report(text: &str, settings: Value) -> Vec<Reported> {
+    report_in(text, settings, PositionEncoding::Utf16)
+}
+
+fn report_in(text: &str, settings: Value, encoding: PositionEncoding) -> Vec<Reported> {
+    compute_diagnostics(text, encoding, &Settings::from_value(settings), &WidgetDb::default())
+        .into_iter()
+        .map(|diagnostic| {
+            let code = match diagnostic.code {
+                Some(tower_lsp::lsp_types::NumberOrString::String(code)) => code,
+                _ => String::new()
+            };
+            let range = diagnostic.range;
+            (code, (range.start.line, range.start.character), (range.end.line, range.end.character), diagnostic.message)
+        })
+        .collect()
+}
+
+fn expected(code: &str, start: (u32, u32), end: (u32, u32), message: &str) -> Reported {
+    (code.to_string(), start, end, message.to_string())
+}
+
+#[test]
+fn setters_of_unknown_properties() {
+    let text = "@Base {\n    @InlineProp(\"title\", String)\n}\n\n@Main -> GtkBox {\n    Base {}\n        .title(\"a\")\n        .nope(1)\n}\n";
+    assert_eq!(report(text, Value::Null), [expected("unknown-property", (7, 8), (7, 13), "`Base` has no property `nope`")]);
+}
+
+#[test]
+fn columns_count_what_was_negotiated() {
+    // Each emoji is two UTF-16 units and four bytes, but a single char
+    let text = "@Base {\n    @InlineProp(\"title\", String)\n}\n\n@Main -> GtkBox {\n    Base {}\n        .title(\"😀😀\") .nope(1)\n}\n";
+    let message = "`Base` has no property `nope`";
+    assert_eq!(report_in(text, Value::Null, PositionEncoding::Utf16), [expected("unknown-property", (6, 23), (6, 28), message)]);
+    assert_eq!(report_in(text, Value::Null, PositionEncoding::Utf32), [expected("unknown-property", (6, 21), (6, 26), message)]);
+    assert_eq!(report_in(text, Value::Null, PositionEncoding::Utf8), [expected("unknown-property", (6, 27), (6, 32), message)]);
+
+    // Text the lexer skips after them too
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀\") ~\n}\n";
+    assert_eq!(report(text, Value::Null), [expected("lex-error", (2, 21), (2, 22), "unrecognized character '~'")]);
+}
+
+#[test]
+fn widgets_whose_chain_is_unknown_could_have_any_property() {
+    let text = "@Main -> GtkBox {\n    AdwBin {}\n        .nope(1)\n}\n";
+    assert_eq!(report(text, Value::Null), []);
+}
+
+#[test]
+fn a_block_that_is_never_closed() {
+    let text = "@Main -> GtkBox {\n    GtkBox {\n        GtkLabel {}\n}\n";
+    // Recoveries are only reported when asked for
+    assert_eq!(report(text, Value::Null), []);
+    assert_eq!(
+        report(text, json!({ "diagnostics": { "parserRecovery": true } })),
+        [expected("parser-recovery", (3, 1), (3, 1), "Assumed a missing `}` here")]
+    );
+}
+
+#[test]
+fn an_argument_list_that_is_never_closed() {
+    let text = "@Main -> GtkBox {\n    GtkBox {}\n        .spacing(0\n    GtkLabel {}\n}\n";
+    assert_eq!(report(text, json!({ "diagnostics": { "parserRecovery": true, "redundantDefaults": true } })), [
+        expected("redundant-default", (2, 8), (2, 18), "`spacing` is already `0` by default"),
+        expected("parser-recovery", (2, 18), (2, 18), "Assumed a missing `)` here")
+    ]);
+}
+
+#[test]
+fn a_token_that_fits_nowhere() {
+    let text = "@Main -> GtkBox {\n    ) GtkBox {}\n}\n";
+    assert_eq!(
+        report(text, json!({ "diagnostics": { "parserRecovery": true } })),
+        [expected("parser-recovery", (1, 4), (1, 5), "Skipped `)` while recovering from an error")]
+    );
+}
+
+#[test]
+fn indentation_that_disagrees_with_the_file() {
+    let text = "@Main -> GtkBox {\n    GtkBox {}\n        .spacing(6)\n\tGtkLabel {}\n  \tGtkLabel {}\n}\n";
+    assert_eq!(report(text, json!({ "diagnostics": { "mixedIndentation": true } })), [
+        expected("mixed-indentation", (3, 0), (3, 1), "Indented with tabs, but the rest of the file uses spaces"),
+        expected("mixed-indentation", (4, 0), (4, 3), "Indentation mixes tabs and spaces")
+    ]);
+}
+
+#[test]
+fn nesting_past_the_limit() {
+    let text = "@Main -> GtkBox {\n    GtkBox {\n        GtkBox {\n            GtkLabel {}\n        }\n    }\n}\n";
+    assert_eq!(report(text, json!({ "diagnostics": { "maxNestingDepth": 2 } })), [
+        expected("deep-nesting", (3, 12), (3, 20), "`GtkLabel` is nested 3 levels deep. Consider extracting part of this tree into a definition of its own")
+    ]);
+}
+
+#[test]
+fn objects_missing_their_arguments() {
+    let text = "@Card {\n    @InlineArg(\"title\", String)\n    @InlineProp(\"subtitle\", String)\n    @ChildArg(\"icon\", String)\n}\n\n@Main -> GtkBox {\n    Card(\"a\", \"b\") {}\n    Card(\"a\") {}\n    Card {}\n}\n";
+    assert_eq!(report(text, Value::Null), [
+        expected("missing-arguments", (8, 4), (8, 8), "`Card` is missing the required argument `icon`"),
+        expected("missing-arguments", (9, 4), (9, 8), "`Card` is missing the required arguments `title`, `icon`")
+    ]);
+    assert_eq!(report(text, json!({ "diagnostics": { "missingArguments": false } })), []);
+}
+
+#[test]
+fn setters_outside_any_widget() {
+    let text = ".label(\"Hi\")\n\n@Main -> GtkBox {\n    .spacing(6)\n    GtkLabel {}\n        .label(\"a\")\n}\n";
+    assert_eq!(report(text, Value::Null), [
+        expected("property-outside-widget", (0, 0), (0, 12), "property outside of a widget"),
+        expected("property-outside-widget", (3, 4), (3, 15), "property outside of a widget")
+    ]);
+    assert_eq!(report(text, json!({ "diagnostics": { "propertiesOutsideWidgets": false } })), []);
+}
+
+#[test]
+fn properties_checked_against_the_targeted_gtk() {
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .natural-wrap-mode(\"word\")\n        .margin-left(4)\n    GtkPicture {}\n        .keep-aspect-ratio(true)\n}\n";
+    // Nothing is checked until a version is set
+    assert_eq!(report(text, Value::Null), []);
+    assert_eq!(report(text, json!({ "gtkVersion": "4.4" })), [
+        expected("property-version", (2, 8), (2, 26), "`natural-wrap-mode` was added in GTK 4.6, after the targeted 4.4"),
+        expected("property-version", (3, 8), (3, 20), "`margin-left` was removed in GTK 4.0, so the targeted 4.4 doesn't have it")
+    ]);
+    assert_eq!(report(text, json!({ "gtkVersion": "4.10" })), [
+        expected("property-version", (3, 8), (3, 20), "`margin-left` was removed in GTK 4.0, so the targeted 4.10 doesn't have it"),
+        expected("property-version", (5, 8), (5, 26), "`keep-aspect-ratio` is deprecated since GTK 4.8")
+    ]);
+}
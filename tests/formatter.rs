@@ -0,0 +1,29 @@
+// How formatting lays out objects, their blocks and their setters.
+
+use gtkui_language_server::{formatter, lexer};
+use lexer::LexOptions;
+use tower_lsp::lsp_types::FormattingOptions;
+
+fn format(text: &str) -> String {
+    let (tokens, _) = lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: false }).unwrap();
+    let options = FormattingOptions { tab_size: 4, insert_spaces: true, ..Default::default() };
+    formatter::format(text, &tokens, &options, "\n")
+}
+
+#[test]
+fn setters_after_an_empty_block_stay_under_their_object() {
+    let text = "@Main -> GtkBox {\nBase {}\n        .title(\"Hello\")\nGtkLabel\n.label(\"a\")\n}\n";
+    assert_eq!(format(text), "@Main -> GtkBox {\n    Base {}\n        .title(\"Hello\")\n    GtkLabel\n        .label(\"a\")\n}\n");
+}
+
+#[test]
+fn setters_after_a_block_line_up_with_its_end() {
+    let text = "GtkWindow {\nGtkBox {\nGtkLabel {}\n}\n.spacing(6)\n}\n.title(\"App\")\n";
+    assert_eq!(format(text), "GtkWindow {\n    GtkBox {\n        GtkLabel {}\n    }\n    .spacing(6)\n}\n.title(\"App\")\n");
+}
+
+#[test]
+fn a_collapsed_object_stays_on_its_line() {
+    let text = "@Main -> GtkBox {\n    GtkLabel(\"a\") {} .label(\"b\") .xalign(0)\n}\n";
+    assert_eq!(format(text), text);
+}
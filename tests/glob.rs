@@ -0,0 +1,55 @@
+// The patterns the `exclude` setting is written in, matched against workspace-relative paths.
+
+use gtkui_language_server::glob::Glob;
+
+#[test]
+fn patterns_match_the_paths_they_should() {
+    let table: &[(&str, &str, bool)] = &[
+        ("**/build/**", "build/x.gui", true),
+        ("**/build/**", "app/build/gen/x.gui", true),
+        ("**/build/**", "builds/x.gui", false),
+        ("**/build/**", "app/rebuild/x.gui", false),
+        ("*.gui", "x.gui", true),
+        ("*.gui", "app/x.gui", false),
+        ("**/*.gui", "x.gui", true),
+        ("**/*.gui", "a/b/c/x.gui", true),
+        ("**/*.gui", "a/b/x.ui", false),
+        ("app/*/main.gui", "app/one/main.gui", true),
+        ("app/*/main.gui", "app/one/two/main.gui", false),
+        ("app/**/main.gui", "app/main.gui", true),
+        ("x?.gui", "x1.gui", true),
+        ("x?.gui", "x.gui", false),
+        ("x?.gui", "x12.gui", false),
+        ("[ab].gui", "a.gui", true),
+        ("[ab].gui", "c.gui", false),
+        ("[!ab].gui", "c.gui", true),
+        ("[!ab].gui", "a.gui", false),
+        ("[a-c]x.gui", "bx.gui", true),
+        ("[a-c]x.gui", "dx.gui", false),
+        ("[^a-c]x.gui", "dx.gui", true),
+        ("[]]", "]", true),
+        ("[a-]", "-", true),
+        // An unclosed class is just a bracket
+        ("[a", "[a", true),
+        ("{build,out}/**", "out/x.gui", true),
+        ("{build,out}/**", "build/a/x.gui", true),
+        ("{build,out}/**", "src/x.gui", false),
+        ("x.{gui,ui}", "x.ui", true),
+        ("{a,{b,c}}.gui", "c.gui", true),
+        ("{a,{b,c}}.gui", "d.gui", false),
+        // An unclosed brace is taken literally
+        ("{a,b.gui", "{a,b.gui", true),
+        ("{a,b.gui", "a.gui", false),
+        // Empty segments don't count, on either side
+        ("build//*.gui", "build/x.gui", true),
+        ("build/*.gui", "/build/x.gui", true),
+        ("*", "", false),
+        ("**", "", true)
+    ];
+    let wrong: Vec<String> = table
+        .iter()
+        .filter(|(pattern, path, expected)| Glob::new(pattern).matches(path) != *expected)
+        .map(|(pattern, path, expected)| format!("{:?} on {:?} should be {}", pattern, path, expected))
+        .collect();
+    assert!(wrong.is_empty(), "{:#?}", wrong);
+}
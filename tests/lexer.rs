@@ -0,0 +1,73 @@
+// Looking tokens up by byte offset, which hover, go to definition and the rest rely on, and
+// what a crash while lexing is reported as.
+
+mod common;
+
+use gtkui_language_server::lexer;
+use gtk_ui::lexer::{Token, TokenValue};
+use lexer::{LexFailure, LexOptions, TokenExt};
+
+fn lex(text: &str) -> Vec<Token> {
+    lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }).unwrap().0
+}
+
+#[test]
+fn an_offset_finds_the_token_it_is_in() {
+    let text = "GtkLabel(\"Hi\") {}\n    .xalign(0.5)";
+    let tokens = lex(text);
+    let at = |byte: usize| lexer::token_at(&tokens, byte).map(|token| token.text(text));
+
+    assert_eq!(at(0), Some("GtkLabel"));
+    assert_eq!(at(7), Some("GtkLabel"));
+    // The end of a token is the start of whatever follows it
+    assert_eq!(at(8), Some("("));
+    assert_eq!(at(9), Some("\"Hi\""));
+    assert_eq!(at(13), Some(")"));
+    // Or of nothing, when whitespace follows it
+    assert_eq!(at(14), None);
+    assert_eq!(at(15), Some("{"));
+    assert_eq!(at(17), None);
+    assert_eq!(at(text.len() - 1), Some(")"));
+    assert_eq!(at(text.len()), None);
+    assert_eq!(at(text.len() + 10), None);
+    assert_eq!(lexer::token_at(&[], 0).map(|token| token.range.clone()), None);
+}
+
+#[test]
+fn lookups_agree_with_a_scan_over_every_token() {
+    common::for_each_corpus_file(|name, source| {
+        let tokens = lex(source);
+        for byte in 0..=source.len() {
+            let scanned = tokens.iter().find(|token| token.range.contains(&byte)).map(|token| token.range.clone());
+            let found = lexer::token_at(&tokens, byte).map(|token| token.range.clone());
+            assert_eq!(found, scanned, "{} at byte {}", name, byte);
+        }
+    });
+}
+
+#[test]
+fn a_dimension_is_a_number_and_the_unit_right_after_it() {
+    let text = ".margin(12px) .width(3 em)";
+    let tokens = lex(text);
+    let unit = |byte: usize| lexer::dimension_at(&tokens, byte).map(|(value, unit, range)| (value, unit.to_string(), range));
+
+    assert_eq!(unit(8), Some((12.0, String::from("px"), 8..12)));
+    assert_eq!(unit(11), Some((12.0, String::from("px"), 8..12)));
+    assert_eq!(unit(12), None);
+    // A space in between makes them separate values
+    assert_eq!(unit(21), None);
+    assert_eq!(unit(23), None);
+    assert!(matches!(lexer::token_at(&tokens, 23).map(|token| &token.value), Some(TokenValue::Identifier(_))));
+}
+
+#[test]
+fn a_crash_keeps_its_message_whether_it_was_formatted_or_not() {
+    // What `panic!("...")` and `panic!("{}", ...)` hand over respectively
+    let literal = LexFailure::from_panic(Box::new("unexpected end"));
+    let formatted = LexFailure::from_panic(Box::new(format!("no token at byte {}", 12)));
+    let other = LexFailure::from_panic(Box::new(12));
+
+    assert_eq!(literal.message, "the lexer crashed: unexpected end");
+    assert_eq!(formatted.message, "the lexer crashed: no token at byte 12");
+    assert_eq!(other.message, "the lexer crashed");
+}
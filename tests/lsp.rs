@@ -0,0 +1,1872 @@
+// Drives the built server over its stdin and stdout the way an editor does, to catch
+// regressions in capability advertisement and handler wiring that tests of the pieces
+// miss. Each test gets a server process of its own.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const DOCUMENT: &str = "\
+@Base {
+    @InlineProp(\"title\", String)
+    @InlineProp(\"count\", Number)
+}
+
+@Main -> GtkBox {
+    Base {}
+        .title(\"Hello\")
+        .nope(1)
+}
+";
+
+struct Server {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    // Notifications that arrived while waiting for something else
+    notifications: Vec<Value>,
+    // The methods and params of requests the server sent, which were answered straight away
+    server_requests: Vec<(String, Value)>,
+    next_id: u64,
+    // What `workspace/configuration` answers for each scope uri, `null` for the rest
+    folder_settings: Value,
+    // What the server's other requests are answered with by method, `null` for the rest
+    answers: HashMap<String, Value>
+}
+
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+impl Server {
+    fn start(initialization_options: Value) -> (Self, Value) {
+        Self::launch(json!({ "initializationOptions": initialization_options }), json!({}))
+    }
+
+    fn start_in(root: &Path) -> (Self, Value) {
+        Self::launch(json!({ "rootUri": format!("file://{}", root.display()) }), json!({}))
+    }
+
+    // A workspace folder per entry, with the settings the client has for it
+    fn start_with_folders(initialization_options: Value, folders: &[(&Path, Value)]) -> (Self, Value) {
+        let uri = |root: &Path| format!("file://{}/", root.display());
+        Self::launch(
+            json!({
+                "initializationOptions": initialization_options,
+                "workspaceFolders": folders.iter().map(|(root, _)| json!({ "uri": uri(root), "name": "folder" })).collect::<Vec<_>>(),
+                "capabilities": { "workspace": { "configuration": true, "workspaceFolders": true } }
+            }),
+            folders.iter().map(|(root, settings)| (uri(root), settings.clone())).collect::<serde_json::Map<_, _>>().into()
+        )
+    }
+
+    // `params` is added to the `initialize` request
+    fn launch(params: Value, folder_settings: Value) -> (Self, Value) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_gtkui-language-server"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the server didn't start");
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            while let Some(message) = read_message(&mut stdout) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut server = Self { child, stdin, messages, notifications: Vec::new(), server_requests: Vec::new(), next_id: 0, folder_settings, answers: HashMap::new() };
+        let mut initialize = json!({
+            "capabilities": {
+                "textDocument": {
+                    "documentSymbol": { "hierarchicalDocumentSymbolSupport": true }
+                }
+            }
+        });
+        for (key, value) in params.as_object().unwrap() {
+            initialize[key] = value.clone();
+        }
+        let result = server.request("initialize", initialize);
+        server.notify("initialized", json!({}));
+        (server, result)
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    // Whatever arrives next, answering the server's own requests on the way
+    fn receive(&mut self, deadline: Instant) -> Value {
+        loop {
+            if let Some(message) = self.next_message(deadline) {
+                return message;
+            }
+        }
+    }
+
+    // `None` for a request of the server's, which is answered right away
+    fn next_message(&mut self, deadline: Instant) -> Option<Value> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let message = self.messages.recv_timeout(timeout).expect("the server took too long to answer");
+        let (id, method) = match (message.get("id"), message.get("method").and_then(Value::as_str)) {
+            (Some(id), Some(method)) => (id.clone(), method.to_string()),
+            _ => return Some(message)
+        };
+        let result = match method.as_str() {
+            "workspace/configuration" => message["params"]["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["scopeUri"].as_str().and_then(|uri| self.folder_settings.get(uri)).cloned().unwrap_or(Value::Null))
+                .collect(),
+            other => self.answers.get(other).cloned().unwrap_or(Value::Null)
+        };
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+        self.server_requests.push((method, message["params"].clone()));
+        None
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.try_request(method, params).unwrap_or_else(|error| panic!("{} failed: {}", method, error))
+    }
+
+    // The error object when the request fails
+    fn try_request(&mut self, method: &str, params: Value) -> Result<Value, Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        self.response(id)
+    }
+
+    // `shutdown` takes no params at all, which isn't the same as `null`
+    fn shutdown(&mut self) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": "shutdown" }));
+        self.response(id).unwrap_or_else(|error| panic!("shutdown failed: {}", error))
+    }
+
+    fn response(&mut self, id: u64) -> Result<Value, Value> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let message = self.receive(deadline);
+            if message.get("id") == Some(&json!(id)) {
+                return match message.get("error") {
+                    Some(error) => Err(error.clone()),
+                    None => Ok(message["result"].clone())
+                };
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    // How the process ended, if it does by itself within `duration`
+    fn exit_status(&mut self, duration: Duration) -> Option<ExitStatus> {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return Some(status);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+
+    fn notification(&mut self, method: &str, matches: impl Fn(&Value) -> bool) -> Value {
+        if let Some(index) = self.notifications.iter().position(|message| message["method"] == method && matches(&message["params"])) {
+            return self.notifications.remove(index)["params"].clone();
+        }
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let message = self.receive(deadline);
+            if message["method"] == method && matches(&message["params"]) {
+                return message["params"].clone();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    // Waits for the server to send a request with this method, and gives its params
+    fn server_request(&mut self, method: &str) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.server_requests.iter().any(|(sent, _)| sent == method) {
+            if let Some(message) = self.next_message(deadline) {
+                self.notifications.push(message);
+            }
+        }
+        let params = self.server_requests.iter().find(|(sent, _)| sent == method).map(|(_, params)| params.clone());
+        self.server_requests.retain(|(sent, _)| sent != method);
+        params.unwrap()
+    }
+
+    // Everything the server sends within `duration`
+    fn collect(&mut self, duration: Duration) -> Vec<Value> {
+        let deadline = Instant::now() + duration;
+        let mut messages = std::mem::take(&mut self.notifications);
+        while let Ok(message) = self.messages.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn open(&mut self, uri: &str, text: &str) {
+        self.notify("textDocument/didOpen", json!({
+            "textDocument": { "uri": uri, "languageId": "gui", "version": 1, "text": text }
+        }));
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The line and character just past the first occurrence of `needle`
+// In UTF-16 units, which is what the server counts in unless a client offers something else
+fn position_after(text: &str, needle: &str) -> Value {
+    let offset = text.find(needle).expect("needle not in the text") + needle.len();
+    let line = text[..offset].matches('\n').count();
+    let character = text[..offset].rsplit('\n').next().unwrap().encode_utf16().count();
+    json!({ "line": line, "character": character })
+}
+
+#[test]
+fn initialize_advertises_capabilities() {
+    let (_server, result) = Server::start(Value::Null);
+    let capabilities = &result["capabilities"];
+    assert!(capabilities["hoverProvider"].as_bool().unwrap_or(false));
+    assert!(capabilities["completionProvider"].is_object());
+    assert!(capabilities["documentFormattingProvider"].as_bool().unwrap_or(false));
+    assert!(capabilities["semanticTokensProvider"]["legend"]["tokenTypes"].is_array());
+    let commands = capabilities["executeCommandProvider"]["commands"].as_array().unwrap();
+    assert!(commands.contains(&json!("gtkui.extractToFile")));
+    assert!(commands.contains(&json!("gtkui.wrapInWidget")));
+    assert!(commands.contains(&json!("gtkui.renameProperty")));
+    assert!(commands.contains(&json!("gtkui.toggleBoolean")));
+    assert!(commands.contains(&json!("gtkui.toggleComment")));
+}
+
+#[test]
+fn semantic_tokens_cover_the_document() {
+    let (mut server, result) = Server::start(Value::Null);
+    let legend: Vec<String> = serde_json::from_value(result["capabilities"]["semanticTokensProvider"]["legend"]["tokenTypes"].clone()).unwrap();
+    server.open("file:///test/tokens.gui", DOCUMENT);
+    let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": "file:///test/tokens.gui" } }));
+
+    // Back to (text, type) from the relative encoding
+    let lines: Vec<&str> = DOCUMENT.lines().collect();
+    let data: Vec<u64> = serde_json::from_value(tokens["data"].clone()).unwrap();
+    let (mut line, mut start) = (0, 0);
+    let decoded: Vec<(String, String)> = data
+        .chunks(5)
+        .map(|token| {
+            if token[0] > 0 {
+                line += token[0] as usize;
+                start = 0;
+            }
+            start += token[1] as usize;
+            let text: String = lines[line].chars().skip(start).take(token[2] as usize).collect();
+            (text, legend[token[3] as usize].clone())
+        })
+        .collect();
+
+    let expected = [
+        ("@Base", "class"),
+        ("@InlineProp", "class"),
+        ("\"title\"", "string"),
+        ("String", "keyword"),
+        ("@InlineProp", "class"),
+        ("\"count\"", "string"),
+        ("Number", "keyword"),
+        ("@Main", "class"),
+        ("->", "operator"),
+        (".title", "method"),
+        ("\"Hello\"", "string"),
+        (".nope", "method"),
+        ("1", "number")
+    ];
+    let expected: Vec<(String, String)> = expected.iter().map(|(text, kind)| (text.to_string(), kind.to_string())).collect();
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn completion_offers_the_widgets_properties() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = DOCUMENT.replace(".nope(1)", ".co");
+    server.open("file:///test/completion.gui", &text);
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/completion.gui" },
+        "position": position_after(&text, ".co")
+    }));
+
+    let items = items.as_array().expect("completion returned nothing");
+    let labels: Vec<&str> = items.iter().filter_map(|item| item["label"].as_str()).collect();
+    // `title` is set just above
+    assert!(labels.contains(&"count") && !labels.contains(&"title"), "{:?}", labels);
+    // The partly typed name is replaced rather than appended to
+    let count = items.iter().find(|item| item["label"] == "count").unwrap();
+    assert_eq!(count["textEdit"]["range"]["start"], json!({ "line": 8, "character": 9 }));
+    assert_eq!(count["textEdit"]["range"]["end"], json!({ "line": 8, "character": 11 }));
+}
+
+#[test]
+fn completion_leaves_out_properties_already_set() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Button {
+    @InlineProp(\"label\", String)
+    @InlineProp(\"icon-name\", String)
+}
+
+@Main -> GtkBox {
+    Button {}
+        .label(\"Save\")
+        .
+    Button {}
+        .la
+}
+";
+    server.open("file:///test/set.gui", text);
+    let labels = |server: &mut Server, position: Value| -> Vec<String> {
+        let items = server.request("textDocument/completion", json!({
+            "textDocument": { "uri": "file:///test/set.gui" },
+            "position": position
+        }));
+        items.as_array().expect("completion returned nothing").iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+    };
+
+    assert_eq!(labels(&mut server, json!({ "line": 8, "character": 9 })), ["icon-name"]);
+    // Another button hasn't set anything, and the setter being typed doesn't count
+    let other = labels(&mut server, json!({ "line": 10, "character": 11 }));
+    assert!(other.contains(&String::from("label")) && other.contains(&String::from("icon-name")), "{:?}", other);
+}
+
+#[test]
+fn completion_after_astral_characters_replaces_what_was_typed() {
+    let (mut server, _) = Server::start(Value::Null);
+    // Each emoji is one char but two UTF-16 units
+    let text = "\
+@Button {
+    @InlineProp(\"label\", String)
+    @InlineProp(\"icon-name\", String)
+}
+
+@Main -> GtkBox {
+    Button {}
+        .label(\"😀😀\") .ic
+}
+";
+    server.open("file:///test/emoji.gui", text);
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/emoji.gui" },
+        "position": position_after(text, ".ic")
+    }));
+
+    let items = items.as_array().expect("completion returned nothing");
+    let icon = items.iter().find(|item| item["label"] == "icon-name").expect("icon-name wasn't offered");
+    assert_eq!(icon["textEdit"]["range"], json!({ "start": { "line": 7, "character": 24 }, "end": { "line": 7, "character": 26 } }));
+}
+
+#[test]
+fn completion_puts_required_properties_first() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Card {
+    @InlineProp(\"subtitle\", String)
+    @InlineArg(\"title\", String)
+    @ChildProp(\"expanded\", Bool)
+    @ChildArg(\"icon\", String)
+}
+
+@Main -> GtkBox {
+    Card {}
+        .
+}
+";
+    server.open("file:///test/required.gui", text);
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/required.gui" },
+        "position": position_after(text, "        .")
+    }));
+
+    let mut items: Vec<&Value> = items.as_array().expect("completion returned nothing").iter().collect();
+    items.sort_by_key(|item| item["sortText"].as_str().unwrap().to_string());
+    let sorted: Vec<(&str, &str)> = items.iter().map(|item| (item["label"].as_str().unwrap(), item["detail"].as_str().unwrap())).collect();
+    assert_eq!(sorted, [
+        ("icon", "String (required)"),
+        ("title", "String (required)"),
+        ("expanded", "Bool"),
+        ("subtitle", "String")
+    ]);
+}
+
+#[test]
+fn completion_ranks_what_the_workspace_uses_most() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Card {
+    @InlineProp(\"badge\", String)
+    @InlineProp(\"subtitle\", String)
+}
+
+@Panel {}
+
+@Main -> GtkBox {
+    Card {}
+        .subtitle(\"One\")
+    Card {}
+        .
+}
+";
+    server.open("file:///test/ranked.gui", text);
+    server.open("file:///test/panels.gui", "@Other -> GtkBox {\n    Panel {}\n    Panel {}\n    Panel {}\n}\n");
+    let sorted = |server: &mut Server, position: Value, kind: u64| -> Vec<String> {
+        let items = server.request("textDocument/completion", json!({
+            "textDocument": { "uri": "file:///test/ranked.gui" },
+            "position": position
+        }));
+        let mut items: Vec<&Value> = items.as_array().expect("completion returned nothing").iter().filter(|item| item["kind"] == kind).collect();
+        items.sort_by_key(|item| item["sortText"].as_str().unwrap().to_string());
+        items.iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+    };
+    let setter = position_after(text, "        .");
+    let child = json!({ "line": 12, "character": 0 });
+
+    assert_eq!(sorted(&mut server, setter.clone(), 10), ["subtitle", "badge"]);
+    assert_eq!(sorted(&mut server, child.clone(), 7)[..2], ["Panel", "Card"]);
+
+    server.notify("workspace/didChangeConfiguration", json!({ "settings": { "completion": { "rankByUsage": false } } }));
+    assert_eq!(sorted(&mut server, setter, 10), ["badge", "subtitle"]);
+    assert_eq!(sorted(&mut server, child, 7)[..2], ["Card", "Main"]);
+}
+
+#[test]
+fn usage_counts_follow_open_documents_and_the_index() {
+    let workspace = Workspace::new("usage");
+    fs::write(workspace.0.join("panels.gui"), "@Other -> GtkBox {\n    Panel {}\n    Panel {}\n}\n").unwrap();
+    let (mut server, _) = Server::start_in(&workspace.0);
+    server.notification("window/logMessage", |params| params["message"].as_str().unwrap().starts_with("indexed"));
+    let main = format!("file://{}/main.gui", workspace.0.display());
+    let text = "@Card {}\n\n@Panel {}\n\n@Main -> GtkBox {\n    Card {}\n\n}\n";
+    server.open(&main, text);
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == main.as_str());
+    let first = |server: &mut Server| -> String {
+        let items = server.request("textDocument/completion", json!({
+            "textDocument": { "uri": main },
+            "position": { "line": 6, "character": 0 }
+        }));
+        let mut items: Vec<&Value> = items.as_array().unwrap().iter().filter(|item| item["kind"] == 7).collect();
+        items.sort_by_key(|item| item["sortText"].as_str().unwrap().to_string());
+        items[0]["label"].as_str().unwrap().to_string()
+    };
+
+    // The indexed file has two panels, the open one a single card
+    assert_eq!(first(&mut server), "Panel");
+    // Once open, the document counts instead of what was indexed
+    let panels = format!("file://{}/panels.gui", workspace.0.display());
+    server.open(&panels, "@Other -> GtkBox {}\n");
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == panels.as_str());
+    assert_eq!(first(&mut server), "Card");
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": panels, "version": 2 },
+        "contentChanges": [{ "text": "@Other -> GtkBox {\n    Panel {}\n    Panel {}\n}\n" }]
+    }));
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == panels.as_str() && params["version"] == 2);
+    assert_eq!(first(&mut server), "Panel");
+    // Closed without saving, what's on disk counts again
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": panels, "version": 3 },
+        "contentChanges": [{ "text": "@Other -> GtkBox {}\n" }]
+    }));
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == panels.as_str() && params["version"] == 3);
+    assert_eq!(first(&mut server), "Card");
+    server.notify("textDocument/didClose", json!({ "textDocument": { "uri": panels } }));
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == panels.as_str() && params["version"].is_null());
+    assert_eq!(first(&mut server), "Panel");
+}
+
+#[test]
+fn completion_after_a_dimension_offers_units() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Main -> GtkBox {
+    GtkBox {}
+        .spacing(12p
+        .opacity(1
+}
+";
+    server.open("file:///test/units.gui", text);
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/units.gui" },
+        "position": position_after(text, ".spacing(12p")
+    }));
+    let units: Vec<(&str, u64)> = items.as_array().expect("no units offered")
+        .iter()
+        .map(|item| (item["label"].as_str().unwrap(), item["kind"].as_u64().unwrap()))
+        .collect();
+    // Enum members, replacing what's typed of the unit
+    assert_eq!(units, [("px", 20), ("pt", 20), ("em", 20)]);
+    assert_eq!(items[0]["textEdit"]["range"], json!({ "start": { "line": 2, "character": 19 }, "end": { "line": 2, "character": 20 } }));
+
+    // Opacity is a plain number
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/units.gui" },
+        "position": position_after(text, ".opacity(1")
+    }));
+    assert_eq!(items, Value::Null);
+}
+
+#[test]
+fn hover_describes_the_setter() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/hover.gui", DOCUMENT);
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": "file:///test/hover.gui" },
+        "position": position_after(DOCUMENT, ".ti")
+    }));
+
+    let contents = hover["contents"]["value"].as_str().expect("no hover");
+    assert!(contents.contains("**title**: `\"Hello\"`"), "{}", contents);
+    assert!(contents.contains("String property of `Base`"), "{}", contents);
+    assert_eq!(hover["range"], json!({ "start": { "line": 7, "character": 8 }, "end": { "line": 7, "character": 14 } }));
+}
+
+#[test]
+fn hover_ranges_count_utf16_after_an_emoji() {
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-16");
+    server.open("file:///test/emoji.gui", "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀\").visible(true)\n}\n");
+    // The emoji is two UTF-16 units, so `.visible` starts at 20 rather than the 19 chars
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": "file:///test/emoji.gui" },
+        "position": { "line": 2, "character": 22 }
+    }));
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("**visible**"), "{}", hover);
+    assert_eq!(hover["range"], json!({ "start": { "line": 2, "character": 20 }, "end": { "line": 2, "character": 28 } }));
+}
+
+#[test]
+fn hover_shows_when_a_property_came_in() {
+    let (mut server, _) = Server::start(json!({ "gtkVersion": "4.4" }));
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .natural-wrap-mode(\"word\")\n}\n";
+    server.open("file:///test/versions.gui", text);
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": "file:///test/versions.gui" },
+        "position": position_after(text, ".nat")
+    }));
+
+    let contents = hover["contents"]["value"].as_str().expect("no hover");
+    assert!(contents.contains("Added in GTK 4.6. Not available in GTK 4.4, which the project targets."), "{}", contents);
+}
+
+#[test]
+fn hover_converts_dimensions_between_units() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .margin-top(12pt)\n        .margin-start(2vw)\n}\n";
+    server.open("file:///test/units.gui", text);
+    let hover = |server: &mut Server, needle| server.request("textDocument/hover", json!({
+        "textDocument": { "uri": "file:///test/units.gui" },
+        "position": position_after(text, needle)
+    }));
+
+    let converted = hover(&mut server, "(1");
+    let contents = converted["contents"]["value"].as_str().expect("no hover");
+    assert!(contents.starts_with("**12pt**"), "{}", contents);
+    assert!(contents.contains("- `16px`\n- `1em`"), "{}", contents);
+    assert_eq!(converted["range"], json!({ "start": { "line": 2, "character": 20 }, "end": { "line": 2, "character": 24 } }));
+
+    // Hovering the unit works as well as the number
+    let relative = hover(&mut server, "2v");
+    let contents = relative["contents"]["value"].as_str().expect("no hover");
+    assert!(contents.contains("`vw` depends on context"), "{}", contents);
+}
+
+#[test]
+fn hover_links_widgets_to_their_documentation() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/docs.gui", DOCUMENT);
+    let hover = |server: &mut Server, needle| {
+        let hover = server.request("textDocument/hover", json!({
+            "textDocument": { "uri": "file:///test/docs.gui" },
+            "position": position_after(DOCUMENT, needle)
+        }));
+        hover["contents"]["value"].as_str().expect("no hover").to_string()
+    };
+
+    let gtk = hover(&mut server, "-> Gtk");
+    assert!(gtk.contains("[See documentation](https://docs.gtk.org/gtk4/class.Box.html)"), "{}", gtk);
+    // A definition is documented by the GTK widget it builds on
+    let defined = hover(&mut server, "@Ma");
+    assert!(defined.contains("A `GtkBox`") && defined.contains("class.Box.html"), "{}", defined);
+    let unknown = hover(&mut server, "    Ba");
+    assert!(!unknown.contains("See documentation"), "{}", unknown);
+
+    let (mut server, _) = Server::start(json!({ "hover": { "docsUrlTemplate": "https://example.com/{widget}" } }));
+    server.open("file:///test/docs.gui", DOCUMENT);
+    let templated = hover(&mut server, "-> Gtk");
+    assert!(templated.contains("(https://example.com/Box)"), "{}", templated);
+}
+
+#[test]
+fn opening_a_document_publishes_its_diagnostics() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/diagnostics.gui", DOCUMENT);
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == "file:///test/diagnostics.gui");
+
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["code"], "unknown-property");
+    assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 8, "character": 8 }));
+}
+
+#[test]
+fn closing_a_document_clears_its_diagnostics_and_forgets_it() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/closed.gui";
+    server.open(uri, DOCUMENT);
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri);
+    assert_eq!(published["diagnostics"].as_array().unwrap().len(), 1);
+
+    server.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri } }));
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri);
+    assert_eq!(published["diagnostics"], json!([]));
+    // Nothing is answered from the text the editor no longer has
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": uri },
+        "position": position_after(DOCUMENT, ".ti")
+    }));
+    assert_eq!(hover, Value::Null);
+}
+
+#[test]
+fn files_over_the_size_limit_are_kept_as_text() {
+    let (mut server, _) = Server::start(json!({ "maxFileSizeKb": 1 }));
+    let text = DOCUMENT.repeat(1024 / DOCUMENT.len() + 1);
+    server.open("file:///test/large.gui", &text);
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == "file:///test/large.gui");
+
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["code"], "file-too-large");
+    assert_eq!(diagnostics[0]["severity"], 3);
+    let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": "file:///test/large.gui" } }));
+    assert_eq!(tokens["data"], json!([]));
+    // The text is still there for what doesn't need tokens
+    let commented = server.request("workspace/executeCommand", json!({
+        "command": "gtkui.toggleComment",
+        "arguments": [{ "textDocument": { "uri": "file:///test/large.gui" }, "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 1 } } }]
+    }));
+    assert_eq!(commented.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn malformed_ranges_leave_the_server_running() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/adversarial.gui";
+    server.open(uri, DOCUMENT);
+    let point = |line: u32, character: u32| json!({ "line": line, "character": character });
+    // Backwards, past the end of the document, and as far out as a position goes
+    let ranges = [
+        json!({ "start": point(5, 10), "end": point(1, 0) }),
+        json!({ "start": point(0, 4), "end": point(0, 2) }),
+        json!({ "start": point(400, 0), "end": point(900, 3) }),
+        json!({ "start": point(0, u32::MAX), "end": point(u32::MAX, u32::MAX) })
+    ];
+    for range in ranges {
+        let document = json!({ "uri": uri });
+        let _ = server.try_request("textDocument/semanticTokens/range", json!({ "textDocument": document, "range": range }));
+        let _ = server.try_request("textDocument/inlayHint", json!({ "textDocument": document, "range": range }));
+        let _ = server.try_request("textDocument/codeAction", json!({ "textDocument": document, "range": range, "context": { "diagnostics": [] } }));
+        let _ = server.try_request("textDocument/rangeFormatting", json!({
+            "textDocument": document,
+            "range": range,
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }));
+        let _ = server.try_request("workspace/executeCommand", json!({
+            "command": "gtkui.toggleComment",
+            "arguments": [{ "textDocument": document, "range": range }]
+        }));
+        let _ = server.try_request("textDocument/hover", json!({ "textDocument": document, "position": range["start"] }));
+        let _ = server.try_request("textDocument/completion", json!({ "textDocument": document, "position": range["end"] }));
+    }
+    // Text that's mostly unbalanced brackets and stray characters parses too
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": "@Main -> {{ ) .( }}} @ -> -> \"\n\t.x(,,)\u{0}\u{feff} }" }]
+    }));
+
+    // Still answering, and about the latest text
+    let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": uri } }));
+    assert!(!tokens["data"].as_array().unwrap().is_empty());
+    let symbols = server.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } }));
+    assert!(symbols.is_array() || symbols.is_null(), "{}", symbols);
+}
+
+#[test]
+fn lex_errors_are_published() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/typo.gui";
+    server.open(uri, "@Main -> GtkBox {\n    GtkBox {} ~\n}\n");
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri);
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["code"], "lex-error");
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"], json!({ "start": { "line": 1, "character": 14 }, "end": { "line": 1, "character": 15 } }));
+
+    // Fixing the typo clears it
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": "@Main -> GtkBox {\n    GtkBox {}\n}\n" }]
+    }));
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri && params["version"] == 2);
+    assert_eq!(published["diagnostics"], json!([]));
+}
+
+#[test]
+fn diagnostics_after_astral_characters_count_in_utf_16() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/emoji.gui";
+    // Each emoji is one char but two UTF-16 units, which is what the server said it counts
+    server.open(uri, "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀😀\") ~\n}\n");
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri);
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["range"], json!({ "start": { "line": 2, "character": 23 }, "end": { "line": 2, "character": 24 } }));
+}
+
+#[test]
+fn semantic_tokens_count_in_the_negotiated_encoding() {
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀\").visible(true)\n}\n";
+    let visible_column = |server: &mut Server| -> u64 {
+        server.open("file:///test/encoding.gui", text);
+        let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": "file:///test/encoding.gui" } }));
+        let data: Vec<u64> = tokens["data"].as_array().unwrap().iter().map(|value| value.as_u64().unwrap()).collect();
+        let (mut line, mut start) = (0, 0);
+        let mut columns = Vec::new();
+        for token in data.chunks(5) {
+            line += token[0];
+            start = if token[0] == 0 { start + token[1] } else { token[1] };
+            if line == 2 {
+                columns.push(start);
+            }
+        }
+        *columns.last().unwrap()
+    };
+
+    let (mut server, result) = Server::launch(json!({ "capabilities": { "general": { "positionEncodings": ["utf-16", "utf-32"] } } }), json!({}));
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-32");
+    let in_chars = visible_column(&mut server);
+    // A client that offers nothing counts UTF-16, where the emoji takes two units
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-16");
+    assert_eq!(visible_column(&mut server), in_chars + 1);
+}
+
+#[test]
+fn a_document_on_one_long_line_is_handled_quickly() {
+    let (mut server, _) = Server::start(Value::Null);
+    let labels = 8000;
+    let text = format!("@Main -> GtkBox {{ {}}}", "GtkLabel(\"é\") ".repeat(labels));
+    assert!(text.chars().count() > 100_000);
+    let started = Instant::now();
+    server.open("file:///test/minified.gui", &text);
+    let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": "file:///test/minified.gui" } }));
+    let data: Vec<u64> = tokens["data"].as_array().unwrap().iter().map(|value| value.as_u64().unwrap()).collect();
+    assert!(data.chunks(5).all(|token| token[0] == 0));
+
+    // Columns and lengths are still right at the far end of the line
+    let strings: Vec<(u64, u64)> = data
+        .chunks(5)
+        .scan(0, |start, token| {
+            *start += token[1];
+            Some((*start, token[2]))
+        })
+        .filter(|(_, length)| *length == 3)
+        .collect();
+    assert_eq!(strings.len(), labels);
+    let last = text.rfind("\"é\"").unwrap();
+    assert_eq!(strings.last(), Some(&(text[..last].chars().count() as u64, 3)));
+
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": "file:///test/minified.gui" },
+        "position": { "line": 0, "character": text.chars().count() - 10 }
+    }));
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("GtkLabel"), "{}", hover);
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn settings_reach_the_server() {
+    let (mut server, _) = Server::start(json!({ "semanticTokens": false, "diagnostics": { "mixedIndentation": true } }));
+    let features = server.request("gtkui/features", json!(null));
+    assert_eq!(features["semanticTokens"], false);
+    assert_eq!(features["mixedIndentation"], true);
+    assert_eq!(features["redundantDefaults"], false);
+}
+
+#[test]
+fn a_bad_setting_leaves_the_others_applied() {
+    let (mut server, _) = Server::start(json!({
+        "semanticTokens": "no",
+        "diagnostics": { "mixedIndentation": true, "redundantDefaults": "yes" }
+    }));
+    let features = server.request("gtkui/features", json!(null));
+    assert_eq!(features["semanticTokens"], true);
+    assert_eq!(features["mixedIndentation"], true);
+    assert_eq!(features["redundantDefaults"], false);
+
+    let mut ignored = Vec::new();
+    for _ in 0..2 {
+        let message = server.notification("window/logMessage", |params| params["message"].as_str().unwrap().starts_with("ignoring a setting"));
+        ignored.push(message["message"].as_str().unwrap().to_string());
+    }
+    ignored.sort();
+    assert_eq!(ignored, [
+        "ignoring a setting, diagnostics.redundantDefaults: invalid type: string \"yes\", expected a boolean",
+        "ignoring a setting, semanticTokens: invalid type: string \"no\", expected a boolean"
+    ]);
+}
+
+#[test]
+fn set_trace_reports_each_request() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/trace.gui", DOCUMENT);
+    // Nothing is traced until the client asks for it
+    server.request("gtkui/features", json!(null));
+    assert!(!server.collect(Duration::from_millis(100)).iter().any(|message| message["method"] == "$/logTrace"));
+
+    server.notify("$/setTrace", json!({ "value": "messages" }));
+    server.request("textDocument/foldingRange", json!({ "textDocument": { "uri": "file:///test/trace.gui" } }));
+    let received = server.notification("$/logTrace", |params| params["message"].as_str().unwrap().starts_with("Received request 'textDocument/foldingRange"));
+    assert!(received.get("verbose").is_none());
+    let answered = server.notification("$/logTrace", |params| params["message"].as_str().unwrap().starts_with("Sending response to request 'textDocument/foldingRange"));
+    assert!(answered["message"].as_str().unwrap().ends_with("ms."));
+
+    server.notify("$/setTrace", json!({ "value": "verbose" }));
+    server.request("gtkui/features", json!(null));
+    let verbose = server.notification("$/logTrace", |params| params["message"].as_str().unwrap().starts_with("Sending response to request 'gtkui/features"));
+    assert!(verbose["verbose"].as_str().unwrap().contains("\"semanticTokens\""));
+}
+
+#[test]
+fn the_diagnostic_catalog_lists_every_code() {
+    let (mut server, _) = Server::start(json!(null));
+    let catalog = server.request("gtkui/diagnosticCatalog", json!(null));
+    let codes: Vec<_> = catalog.as_array().unwrap().iter().map(|entry| entry["code"].as_str().unwrap()).collect();
+    assert_eq!(codes, [
+        "redundant-default",
+        "mixed-indentation",
+        "unknown-property",
+        "parser-recovery",
+        "deep-nesting",
+        "missing-arguments",
+        "property-outside-widget",
+        "property-version",
+        "file-too-large",
+        "unresolved-include",
+        "lex-error"
+    ]);
+    let unknown = &catalog[2];
+    assert_eq!(unknown["defaultSeverity"], 1);
+    assert_eq!(unknown["configurable"], true);
+    assert_eq!(unknown["setting"], "gtkui-language-server.diagnostics.unknownProperties");
+    assert!(unknown["documentation"].as_str().unwrap().ends_with("diagnostics.md#unknown-property"));
+    assert!(!unknown["description"].as_str().unwrap().is_empty());
+}
+
+#[test]
+fn custom_widgets_are_known() {
+    let (mut server, _) = Server::start(json!({
+        "customWidgets": {
+            "AdwBin": { "properties": { "child-name": "String" } },
+            "AdwHeaderBar": { "inherits": ["AdwBin"], "properties": { "centering-policy": "String" } }
+        }
+    }));
+    let text = "@Main -> GtkBox {\n    AdwHeaderBar {}\n        .child-name(\"a\")\n        .nope(1)\n        .c\n}\n";
+    server.open("file:///test/custom.gui", text);
+
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == "file:///test/custom.gui");
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    let messages: Vec<&str> = diagnostics.iter().filter_map(|diagnostic| diagnostic["message"].as_str()).collect();
+    assert_eq!(messages, ["`AdwHeaderBar` has no property `nope`", "`AdwHeaderBar` has no property `c`"]);
+
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/custom.gui" },
+        "position": position_after(text, "        .c")
+    }));
+    let labels: Vec<&str> = items.as_array().unwrap().iter().filter_map(|item| item["label"].as_str()).collect();
+    assert_eq!(labels, ["centering-policy", "child-name"]);
+}
+
+#[test]
+fn inlay_hints_follow_their_settings() {
+    let text = "@Base {\n    @InlineProp(\"title\", String)\n}\n\n@Main -> GtkBox {\n    Base {}\n        .title(\"Hi\")\n    GtkBox {}\n        .spacing(6)\n        .homogeneous(false)\n}\n";
+    let range = json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 11, "character": 0 } });
+    let labels = |hints: &Value| -> Vec<String> {
+        hints.as_array().unwrap().iter().map(|hint| hint["label"].as_str().unwrap().to_string()).collect()
+    };
+
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["inlayHintProvider"]["resolveProvider"], true);
+    server.open("file:///test/hints.gui", text);
+    let hints = server.request("textDocument/inlayHint", json!({ "textDocument": { "uri": "file:///test/hints.gui" }, "range": range }));
+    // `homogeneous` is set to its default, so there's nothing to point out
+    assert_eq!(labels(&hints), [": String", "default 0"]);
+    assert_eq!(hints[0]["position"], json!({ "line": 6, "character": 19 }));
+    assert!(hints[0].get("tooltip").is_none());
+
+    let resolved = server.request("inlayHint/resolve", hints[1].clone());
+    assert_eq!(resolved["tooltip"]["value"], "Without this setter, `spacing` on `GtkBox` would be `0`");
+
+    let (mut server, _) = Server::start(json!({ "inlayHints": { "types": false } }));
+    server.open("file:///test/hints.gui", text);
+    let hints = server.request("textDocument/inlayHint", json!({ "textDocument": { "uri": "file:///test/hints.gui" }, "range": range }));
+    assert_eq!(labels(&hints), ["default 0"]);
+}
+
+#[test]
+fn deeply_nested_widgets_are_flagged() {
+    // GtkBox inside GtkBox, twenty levels deep inside the definition
+    let mut text = String::from("@Main -> GtkBox {\n");
+    for depth in 1..=20 {
+        text.push_str(&format!("{}GtkBox {{\n", "    ".repeat(depth)));
+    }
+    for depth in (0..=20).rev() {
+        text.push_str(&format!("{}}}\n", "    ".repeat(depth)));
+    }
+
+    let (mut server, _) = Server::start(json!({ "diagnostics": { "maxNestingDepth": 10 } }));
+    server.open("file:///test/nested.gui", &text);
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == "file:///test/nested.gui");
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    let lines: Vec<u64> = diagnostics.iter().map(|diagnostic| diagnostic["range"]["start"]["line"].as_u64().unwrap()).collect();
+    assert_eq!(lines, (11..=20).collect::<Vec<u64>>());
+    assert!(diagnostics.iter().all(|diagnostic| diagnostic["code"] == "deep-nesting" && diagnostic["severity"] == 4));
+    assert_eq!(diagnostics[0]["message"], "`GtkBox` is nested 11 levels deep. Consider extracting part of this tree into a definition of its own");
+
+    // Off unless a depth is configured
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/nested.gui", &text);
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == "file:///test/nested.gui");
+    assert_eq!(published["diagnostics"], json!([]));
+}
+
+#[test]
+fn completion_offers_booleans_for_bool_properties() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "@Base {\n    @InlineProp(\"shown\", Bool)\n    @InlineProp(\"count\", Number)\n}\n\n@Main -> GtkBox {\n    Base {}\n        .shown(tr\n        .count(\n}\n";
+    server.open("file:///test/bools.gui", text);
+    let complete = |server: &mut Server, after: &str| server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/bools.gui" },
+        "position": position_after(text, after)
+    }));
+
+    let items = complete(&mut server, ".shown(tr");
+    let labels: Vec<&str> = items.as_array().unwrap().iter().filter_map(|item| item["label"].as_str()).collect();
+    assert_eq!(labels, ["true", "false"]);
+    assert_eq!(items[0]["textEdit"]["range"]["start"], json!({ "line": 7, "character": 15 }));
+    assert_eq!(items[0]["textEdit"]["newText"], "true");
+
+    // Numbers have no fixed set of values to offer
+    assert_eq!(complete(&mut server, ".count("), Value::Null);
+}
+
+#[test]
+fn references_stream_in_batches() {
+    // One definition, inherited once and used 250 times
+    let mut text = String::from("@Item -> GtkBox {}\n\n@Special -> Item {}\n\n@Main -> GtkBox {\n");
+    for _ in 0..250 {
+        text.push_str("    Item {}\n");
+    }
+    text.push_str("}\n");
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["referencesProvider"], true);
+    server.open("file:///test/references.gui", &text);
+    let position = json!({ "line": 0, "character": 2 });
+
+    let all = server.request("textDocument/references", json!({
+        "textDocument": { "uri": "file:///test/references.gui" },
+        "position": position,
+        "context": { "includeDeclaration": true }
+    }));
+    assert_eq!(all.as_array().unwrap().len(), 252);
+
+    let streamed = server.request("textDocument/references", json!({
+        "textDocument": { "uri": "file:///test/references.gui" },
+        "position": position,
+        "context": { "includeDeclaration": false },
+        "partialResultToken": "references"
+    }));
+    assert_eq!(streamed, json!([]));
+    let mut batches = Vec::new();
+    while batches.iter().map(Vec::len).sum::<usize>() < 251 {
+        let progress = server.notification("$/progress", |params| params["token"] == "references");
+        batches.push(progress["value"].as_array().unwrap().clone());
+    }
+    assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [100, 100, 51]);
+    assert_eq!(batches[0][0]["range"]["start"], json!({ "line": 2, "character": 12 }));
+
+    let highlights = server.request("textDocument/documentHighlight", json!({
+        "textDocument": { "uri": "file:///test/references.gui" },
+        "position": { "line": 5, "character": 5 }
+    }));
+    assert_eq!(highlights.as_array().unwrap().len(), 252);
+    assert_eq!(highlights[0]["kind"], 3);
+    assert_eq!(highlights[1]["kind"], 2);
+
+    let symbols = server.request("workspace/symbol", json!({ "query": "spc" }));
+    let names: Vec<&str> = symbols.as_array().unwrap().iter().filter_map(|symbol| symbol["name"].as_str()).collect();
+    assert_eq!(names, ["Special"]);
+}
+
+#[test]
+fn implementations_go_down_the_hierarchy() {
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["implementationProvider"], true);
+    server.open("file:///test/base.gui", "@Base {}\n");
+    server.open("file:///test/widgets.gui", "@Mid -> Base {}\n\n@Leaf -> Mid {}\n\n@Other -> GtkBox {}\n");
+
+    let locations = server.request("textDocument/implementation", json!({
+        "textDocument": { "uri": "file:///test/base.gui" },
+        "position": { "line": 0, "character": 2 }
+    }));
+    let mut found: Vec<(String, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| (location["uri"].as_str().unwrap().to_string(), location["range"]["start"]["line"].as_u64().unwrap()))
+        .collect();
+    found.sort();
+    assert_eq!(found, [("file:///test/widgets.gui".to_string(), 0), ("file:///test/widgets.gui".to_string(), 2)]);
+}
+
+// A fresh directory to use as a workspace, removed again when dropped
+struct Workspace(PathBuf);
+
+impl Workspace {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("gtkui-lsp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn the_index_is_cached_between_runs() {
+    let workspace = Workspace::new("cache");
+    fs::write(workspace.0.join("a.gui"), "@A -> GtkBox {}\n").unwrap();
+    fs::write(workspace.0.join("b.gui"), "@B -> A {}\n").unwrap();
+    let indexed = |server: &mut Server| -> String {
+        let message = server.notification("window/logMessage", |params| params["message"].as_str().unwrap().starts_with("indexed"));
+        message["message"].as_str().unwrap().to_string()
+    };
+
+    let (mut server, _) = Server::start_in(&workspace.0);
+    assert_eq!(indexed(&mut server), "indexed 2 files, 0 of them from the cache");
+    drop(server);
+    assert!(workspace.0.join(".cache/gtkui-language-server/index.json").exists());
+
+    let (mut server, _) = Server::start_in(&workspace.0);
+    assert_eq!(indexed(&mut server), "indexed 2 files, 2 of them from the cache");
+    // What came from the cache works like a fresh parse
+    server.open("file:///test/c.gui", "@C -> B {}\n");
+    let symbols = server.request("workspace/symbol", json!({ "query": "" }));
+    let mut names: Vec<&str> = symbols.as_array().unwrap().iter().filter_map(|symbol| symbol["name"].as_str()).collect();
+    names.sort();
+    assert_eq!(names, ["A", "B", "C"]);
+    drop(server);
+
+    fs::write(workspace.0.join("b.gui"), "@B -> A {}\n\n@D {}\n").unwrap();
+    let (mut server, _) = Server::start_in(&workspace.0);
+    assert_eq!(indexed(&mut server), "indexed 2 files, 1 of them from the cache");
+    drop(server);
+
+    fs::write(workspace.0.join(".cache/gtkui-language-server/index.json"), "{ not json").unwrap();
+    let (mut server, _) = Server::start_in(&workspace.0);
+    let warning = server.notification("window/logMessage", |params| params["type"] == 2);
+    assert!(warning["message"].as_str().unwrap().contains("is corrupt"), "{}", warning);
+    assert_eq!(indexed(&mut server), "indexed 2 files, 0 of them from the cache");
+}
+
+#[test]
+fn a_large_workspace_is_indexed_with_progress() {
+    let workspace = Workspace::new("stress");
+    // Names can't have digits in them
+    let letters = |number: usize| format!("{}{}", (b'a' + (number / 10) as u8) as char, (b'a' + (number % 10) as u8) as char);
+    for directory in 0..20 {
+        let directory_path = workspace.0.join(format!("module{}", directory));
+        fs::create_dir_all(&directory_path).unwrap();
+        for file in 0..100 {
+            let name = format!("Widget{}{}", letters(directory), letters(file));
+            fs::write(directory_path.join(format!("{}.gui", name)), format!("@{} -> GtkBox {{\n    GtkLabel(\"{}\")\n}}\n", name, file)).unwrap();
+        }
+    }
+    let (mut server, _) = Server::launch(json!({
+        "rootUri": format!("file://{}", workspace.0.display()),
+        "capabilities": { "window": { "workDoneProgress": true } }
+    }), json!({}));
+
+    let mut percentages = Vec::new();
+    loop {
+        let progress = server.notification("$/progress", |params| params["token"] == "gtkui-indexing");
+        match progress["value"]["kind"].as_str() {
+            Some("end") => {
+                assert_eq!(progress["value"]["message"], "Indexed 2000 files");
+                break;
+            },
+            _ => percentages.push(progress["value"]["percentage"].as_u64().unwrap())
+        }
+    }
+    assert_eq!(percentages.first(), Some(&0));
+    assert_eq!(percentages.last(), Some(&100));
+    assert!(percentages.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", percentages);
+    server.notification("window/logMessage", |params| params["message"] == "indexed 2000 files, 0 of them from the cache");
+
+    // Every file made it into the index
+    for name in ["Widgetaaaa", "Widgetahec", "Widgetbjjj"] {
+        let symbols = server.request("workspace/symbol", json!({ "query": name }));
+        assert!(symbols.as_array().unwrap().iter().any(|symbol| symbol["name"] == name), "{} is missing: {}", name, symbols);
+    }
+}
+
+#[test]
+fn a_definition_is_extracted_to_a_file_it_is_included_from() {
+    let workspace = Workspace::new("extract");
+    let (mut server, _) = Server::start(Value::Null);
+    let document = format!("file://{}/main.gui", workspace.0.display());
+    let text = "#include \"gtk-4.0\"\n\n@Card -> GtkBox {\n    GtkLabel {}\n}\n\n@Main -> GtkBox {\n    Card {}\n}\n";
+    server.open(&document, text);
+    let actions = server.request("textDocument/codeAction", json!({
+        "textDocument": { "uri": document },
+        "range": { "start": { "line": 2, "character": 2 }, "end": { "line": 2, "character": 2 } },
+        "context": { "diagnostics": [] }
+    }));
+    let extract = actions.as_array().unwrap().iter().find(|action| action["title"] == "Extract `Card` to Card.gui").expect("no extract action").clone();
+
+    server.answers.insert(String::from("workspace/applyEdit"), json!({ "applied": true }));
+    server.request("workspace/executeCommand", extract["command"].clone());
+    let applied = server.server_request("workspace/applyEdit");
+    let changes = applied["edit"]["documentChanges"].as_array().unwrap();
+    let card = format!("file://{}/Card.gui", workspace.0.display());
+    assert_eq!(changes[0]["kind"], "create");
+    assert_eq!(changes[0]["uri"], card.as_str());
+    assert_eq!(changes[1]["edits"][0]["newText"], "#include \"gtk-4.0\"\n\n@Card -> GtkBox {\n    GtkLabel {}\n}\n");
+    let edits = changes[2]["edits"].as_array().unwrap();
+    assert!(edits.iter().any(|edit| edit["newText"] == "\n#include \"Card.gui\""), "{:?}", edits);
+}
+
+#[test]
+fn excluded_files_are_neither_indexed_nor_diagnosed() {
+    let workspace = Workspace::new("excluded");
+    fs::create_dir_all(workspace.0.join("build")).unwrap();
+    fs::write(workspace.0.join("a.gui"), "@A -> GtkBox {}\n").unwrap();
+    fs::write(workspace.0.join("build/x.gui"), "#include \"missing\"\n\n@Generated -> GtkBox {}\n").unwrap();
+    let (mut server, _) = Server::start_in(&workspace.0);
+    // `**/build/**` is excluded by default
+    server.notification("window/logMessage", |params| params["message"] == "indexed 1 files, 0 of them from the cache");
+    let symbols = server.request("workspace/symbol", json!({ "query": "" }));
+    let names: Vec<&str> = symbols.as_array().unwrap().iter().filter_map(|symbol| symbol["name"].as_str()).collect();
+    assert_eq!(names, ["A"]);
+
+    // Opening it still doesn't report the include it can't find
+    let generated = format!("file://{}/build/x.gui", workspace.0.display());
+    server.open(&generated, &fs::read_to_string(workspace.0.join("build/x.gui")).unwrap());
+    let published = server.notification("textDocument/publishDiagnostics", |params| params["uri"] == generated.as_str());
+    assert_eq!(published["diagnostics"], json!([]));
+}
+
+#[test]
+fn workspace_folders_have_their_own_settings() {
+    let (tabs, spaces) = (Workspace::new("tabs"), Workspace::new("spaces"));
+    let (mut server, _) = Server::start_with_folders(json!({}), &[
+        (&tabs.0, json!({ "diagnostics": { "mixedIndentation": true } })),
+        (&spaces.0, json!({ "format": { "lineEndings": "crlf" } }))
+    ]);
+    let text = "@Main -> GtkBox {\n    GtkBox {}\n\tGtkBox {}\n}\n";
+    let (in_tabs, in_spaces) = (format!("file://{}/a.gui", tabs.0.display()), format!("file://{}/b.gui", spaces.0.display()));
+    server.open(&in_tabs, text);
+    server.open(&in_spaces, text);
+    server.open("file:///elsewhere/c.gui", text);
+
+    // Only the folder that asked for it checks indentation
+    let published = server.notification("textDocument/publishDiagnostics", |params| {
+        params["uri"] == in_tabs.as_str() && !params["diagnostics"].as_array().unwrap().is_empty()
+    });
+    assert_eq!(published["diagnostics"][0]["code"], "mixed-indentation");
+
+    let format = |server: &mut Server, uri: &str| server.request("textDocument/formatting", json!({
+        "textDocument": { "uri": uri },
+        "options": { "tabSize": 4, "insertSpaces": true }
+    }));
+    assert!(format(&mut server, &in_spaces)[0]["newText"].as_str().unwrap().contains("\r\n"));
+    // Outside every folder, the global settings keep the file's own line endings
+    assert!(!format(&mut server, "file:///elsewhere/c.gui")[0]["newText"].as_str().unwrap().contains('\r'));
+    assert!(!format(&mut server, &in_tabs)[0]["newText"].as_str().unwrap().contains('\r'));
+}
+
+#[test]
+fn includes_are_looked_for_under_the_base_paths() {
+    let workspace = Workspace::new("bases");
+    fs::create_dir_all(workspace.0.join("shared")).unwrap();
+    fs::create_dir_all(workspace.0.join("app")).unwrap();
+    fs::write(workspace.0.join("shared/buttons.gui"), "@Fancy -> GtkButton {}\n").unwrap();
+    let (mut server, _) = Server::start_with_folders(json!({}), &[(&workspace.0, json!({ "importBasePaths": ["shared"] }))]);
+    let document = format!("file://{}/app/main.gui", workspace.0.display());
+    server.open(&document, "#include \"buttons\"\n#include \"missing\"\n\n@Main -> GtkBox {\n    Fancy {}\n}\n");
+
+    // Only the include that isn't under `shared` either is reported
+    let published = server.notification("textDocument/publishDiagnostics", |params| {
+        params["uri"] == document.as_str() && params["diagnostics"].as_array().unwrap().len() == 1
+    });
+    assert_eq!(published["diagnostics"][0]["code"], "unresolved-include");
+    assert_eq!(published["diagnostics"][0]["range"]["start"]["line"], 1);
+
+    // Definitions are found through the same lookup
+    let definition = server.request("textDocument/definition", json!({
+        "textDocument": { "uri": document },
+        "position": { "line": 4, "character": 5 }
+    }));
+    assert_eq!(definition[0]["uri"], format!("file://{}/shared/buttons.gui", workspace.0.display()));
+    let hover = server.request("textDocument/hover", json!({
+        "textDocument": { "uri": document },
+        "position": { "line": 1, "character": 12 }
+    }));
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("shared/missing.gui"), "{}", hover);
+}
+
+#[test]
+fn a_change_that_breaks_the_document_cancels_its_pending_tree() {
+    let (mut server, _) = Server::launch(json!({ "capabilities": { "experimental": { "gtkuiAstChanged": true } } }), json!({}));
+    server.open("file:///test/broken_tree.gui", DOCUMENT);
+    // Within the delay, so the tree of version 1 hasn't been sent yet
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": "file:///test/broken_tree.gui", "version": 2 },
+        "contentChanges": [{ "text": "@Main -> GtkBox {\n    GtkLabel(\n" }]
+    }));
+    server.notification("textDocument/publishDiagnostics", |params| params["version"] == 2);
+    let sent: Vec<Value> = server
+        .collect(Duration::from_millis(400))
+        .into_iter()
+        .filter(|message| message["method"] == "gtkui/astChanged")
+        .collect();
+    assert!(sent.is_empty(), "{:?}", sent);
+}
+
+#[test]
+fn nothing_is_sent_after_shutdown() {
+    let (mut server, _) = Server::launch(json!({ "capabilities": { "experimental": { "gtkuiAstChanged": true } } }), json!({}));
+    server.open("file:///test/shutdown.gui", DOCUMENT);
+    // Both the debounced diagnostics and the `gtkui/astChanged` for this are still pending
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": "file:///test/shutdown.gui", "version": 2 },
+        "contentChanges": [{ "text": DOCUMENT.replace("Hello", "Bye") }]
+    }));
+    assert_eq!(server.shutdown(), Value::Null);
+
+    // What came before the answer to `shutdown` is fine, anything after it is not
+    server.notifications.clear();
+    let late = server.collect(Duration::from_millis(400));
+    assert!(late.is_empty(), "{:?}", late);
+}
+
+#[test]
+fn indexing_stops_at_shutdown() {
+    let workspace = Workspace::new("shutdown");
+    for index in 0..2000 {
+        fs::write(workspace.0.join(format!("{}.gui", index)), DOCUMENT).unwrap();
+    }
+    let (mut server, _) = Server::start_in(&workspace.0);
+    assert_eq!(server.shutdown(), Value::Null);
+
+    server.notifications.clear();
+    let late = server.collect(Duration::from_millis(400));
+    assert!(late.is_empty(), "{:?}", late);
+}
+
+#[test]
+fn requests_after_shutdown_are_refused_and_exit_stops_the_server() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/lifecycle.gui", DOCUMENT);
+    assert_eq!(server.shutdown(), Value::Null);
+
+    let error = server
+        .try_request("textDocument/hover", json!({
+            "textDocument": { "uri": "file:///test/lifecycle.gui" },
+            "position": position_after(DOCUMENT, ".tit")
+        }))
+        .expect_err("hover was answered after shutdown");
+    // InvalidRequest
+    assert_eq!(error["code"], -32600);
+
+    server.notify("exit", Value::Null);
+    let status = server.exit_status(TIMEOUT).expect("the server kept running after exit");
+    assert!(status.success(), "{}", status);
+}
+
+#[test]
+fn text_is_validated_without_a_document() {
+    let (mut server, _) = Server::start(Value::Null);
+    let validate = |server: &mut Server, text: &str| server.request("gtkui/validateText", json!({ "text": text, "languageId": "gui" }));
+
+    let valid = validate(&mut server, &DOCUMENT.replace("        .nope(1)\n", ""));
+    assert_eq!(valid["success"], true, "{}", valid);
+    assert_eq!(valid["syntaxErrors"], json!([]));
+
+    let unknown = validate(&mut server, DOCUMENT);
+    assert_eq!(unknown["success"], false);
+    let codes: Vec<&Value> = unknown["diagnostics"].as_array().unwrap().iter().map(|diagnostic| &diagnostic["code"]).collect();
+    assert_eq!(codes, [&json!("unknown-property")]);
+
+    let broken = validate(&mut server, "@Main -> GtkBox {\n    GtkLabel {\n");
+    assert_eq!(broken["success"], false);
+    assert!(!broken["syntaxErrors"].as_array().unwrap().is_empty(), "{}", broken);
+
+    assert!(server.try_request("gtkui/validateText", json!({ "text": "", "languageId": "rust" })).is_err());
+}
+
+#[test]
+fn cached_outlines_follow_changes() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/outline.gui";
+    server.open(uri, DOCUMENT);
+    let outline = |server: &mut Server| {
+        let symbols = server.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } }));
+        let folds = server.request("textDocument/foldingRange", json!({ "textDocument": { "uri": uri } }));
+        let names: Vec<String> = symbols.as_array().unwrap().iter().map(|symbol| symbol["name"].as_str().unwrap().to_string()).collect();
+        (names, folds.as_array().unwrap().len())
+    };
+
+    let first = outline(&mut server);
+    // Served from the cache this time
+    assert_eq!(outline(&mut server), first);
+
+    let changed = DOCUMENT.replace("@Base", "@Card").replace("    Base {}", "    Card {}") + "\n@Extra {\n    GtkLabel {}\n}\n";
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": changed }]
+    }));
+    let (names, folds) = outline(&mut server);
+    assert!(names.iter().any(|name| name.contains("Card")) && !names.iter().any(|name| name.contains("Base")), "{:?}", names);
+    assert_eq!(names.len(), first.0.len() + 1, "{:?}", names);
+    assert_eq!(folds, first.1 + 1);
+}
+
+#[test]
+fn document_symbols_select_just_the_name() {
+    let (mut server, _) = Server::start(Value::Null);
+    server.open("file:///test/symbols.gui", DOCUMENT);
+    let symbols = server.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": "file:///test/symbols.gui" } }));
+
+    let base = &symbols[0];
+    assert_eq!(base["range"], json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 3, "character": 1 } }));
+    assert_eq!(base["selectionRange"], json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 5 } }));
+    let title = &base["children"][0];
+    assert_eq!(title["name"], "title");
+    assert_eq!(title["range"], json!({ "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 32 } }));
+    assert_eq!(title["selectionRange"], json!({ "start": { "line": 1, "character": 16 }, "end": { "line": 1, "character": 23 } }));
+}
+
+#[test]
+fn changes_are_applied_one_after_another() {
+    let (mut server, result) = Server::start(Value::Null);
+    assert_eq!(result["capabilities"]["textDocumentSync"]["change"], 2);
+    let uri = "file:///test/incremental.gui";
+    server.open(uri, "@Main -> GtkBox {\n    GtkBox {}\n}\n");
+    let range = |start: (u32, u32), end: (u32, u32)| json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 }
+    });
+    // Like a multi-cursor edit, last in the document first, and then a line added after what
+    // the first change wrote
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [
+            { "range": range((1, 4), (1, 10)), "text": "GtkLabel" },
+            { "range": range((0, 1), (0, 5)), "text": "Window" },
+            { "range": range((1, 15), (1, 15)), "text": "\n    GtkGrid {}" }
+        ]
+    }));
+    let tokens = server.request("gtkui/debugTokensPretty", json!({ "textDocument": { "uri": uri } }));
+    let tokens: Vec<&str> = tokens.as_array().unwrap().iter().map(|token| token.as_str().unwrap()).collect();
+    assert!(tokens[0].starts_with("L1:C1-L1:C8 ") && tokens[0].ends_with("\"@Window\""), "{:?}", tokens);
+    assert!(tokens.iter().any(|token| token.starts_with("L2:C5-L2:C13 ") && token.ends_with("\"GtkLabel\"")), "{:?}", tokens);
+    assert!(tokens.iter().any(|token| token.starts_with("L3:C5-L3:C12 ") && token.ends_with("\"GtkGrid\"")), "{:?}", tokens);
+
+    // A change without a range replaces everything
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 3 },
+        "contentChanges": [{ "text": "@Other {}\n" }]
+    }));
+    let tokens = server.request("gtkui/debugTokensPretty", json!({ "textDocument": { "uri": uri } }));
+    assert!(tokens[0].as_str().unwrap().ends_with("\"@Other\""), "{}", tokens);
+}
+
+#[test]
+fn changes_after_an_emoji_count_utf16() {
+    let (mut server, _) = Server::start(Value::Null);
+    let uri = "file:///test/emoji-edit.gui";
+    server.open(uri, "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀\"y)\n}\n");
+    // Just after the closing quote, which is 18 chars but 19 UTF-16 units in
+    server.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "range": { "start": { "line": 2, "character": 19 }, "end": { "line": 2, "character": 19 } }, "text": "x" }]
+    }));
+    let tokens = server.request("gtkui/debugTokensPretty", json!({ "textDocument": { "uri": uri } }));
+    let tokens: Vec<&str> = tokens.as_array().unwrap().iter().map(|token| token.as_str().unwrap()).collect();
+    assert!(tokens.iter().any(|token| token.ends_with(" \"xy\"")), "{:?}", tokens);
+}
+
+#[test]
+fn quick_keystrokes_are_all_kept() {
+    // Debug logging gives each change something to wait on while the next one comes in
+    let (mut server, _) = Server::start(json!({ "logLevel": "debug" }));
+    let uri = "file:///test/typing.gui";
+    server.open(uri, "@Main -> GtkBox {\n    GtkLabel {}\n}\n");
+    let typed = "abcdefghijklmnopqrstuvwxyz";
+    for (version, (column, letter)) in typed.chars().enumerate().map(|(index, letter)| (index + 15, letter)).enumerate() {
+        server.notify("textDocument/didChange", json!({
+            "textDocument": { "uri": uri, "version": version + 2 },
+            "contentChanges": [{ "range": { "start": { "line": 1, "character": column }, "end": { "line": 1, "character": column } }, "text": letter.to_string() }]
+        }));
+    }
+    // Once the last version is checked, it's what is stored
+    server.notification("textDocument/publishDiagnostics", |params| params["uri"] == uri && params["version"] == typed.len() + 1);
+    let tokens = server.request("gtkui/debugTokensPretty", json!({ "textDocument": { "uri": uri } }));
+    let tokens: Vec<&str> = tokens.as_array().unwrap().iter().map(|token| token.as_str().unwrap()).collect();
+    assert!(tokens.iter().any(|token| token.ends_with(&format!(" \"{}\"", typed))), "{:?}", tokens);
+}
+
+#[test]
+fn settings_changes_refresh_pulled_features() {
+    let (mut server, _) = Server::launch(json!({
+        "capabilities": { "workspace": { "inlayHint": { "refreshSupport": true }, "semanticTokens": { "refreshSupport": true } } }
+    }), json!({}));
+    let change = |server: &mut Server, settings: Value| server.notify("workspace/didChangeConfiguration", json!({ "settings": settings }));
+
+    change(&mut server, json!({ "inlayHints": { "types": false } }));
+    server.server_request("workspace/inlayHint/refresh");
+    change(&mut server, json!({ "inlayHints": { "types": false }, "semanticTokens": false }));
+    server.server_request("workspace/semanticTokens/refresh");
+    // Nothing either of them shows changed this time
+    change(&mut server, json!({ "inlayHints": { "types": false }, "semanticTokens": false, "diagnostics": { "mixedIndentation": true } }));
+    assert_eq!(server.request("gtkui/features", json!(null))["mixedIndentation"], true);
+    assert!(server.server_requests.is_empty(), "{:?}", server.server_requests);
+    let late: Vec<Value> = server.collect(Duration::from_millis(200)).into_iter().filter(|message| message.get("id").is_some()).collect();
+    assert!(late.is_empty(), "{:?}", late);
+}
+
+#[test]
+fn read_only_documents_get_no_edits() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "@Main -> GtkBox {\nGtkLabel {}\n}\n";
+    let edits = |server: &mut Server, uri: &str| {
+        server.open(uri, text);
+        let actions = server.request("textDocument/codeAction", json!({
+            "textDocument": { "uri": uri },
+            "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 11 } },
+            "context": { "diagnostics": [] }
+        }));
+        let formatting = server.request("textDocument/formatting", json!({
+            "textDocument": { "uri": uri },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }));
+        (actions, formatting)
+    };
+
+    let (actions, formatting) = edits(&mut server, "file:///test/writable.gui");
+    assert!(!actions.as_array().unwrap().is_empty());
+    assert!(!formatting.as_array().unwrap().is_empty());
+    assert_eq!(edits(&mut server, "git:/test/writable.gui?%7B%22ref%22%3A%22HEAD%22%7D"), (Value::Null, Value::Null));
+    // Still readable, so everything else keeps working
+    let symbols = server.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": "git:/test/writable.gui?%7B%22ref%22%3A%22HEAD%22%7D" } }));
+    assert!(!symbols.as_array().unwrap().is_empty());
+}
+
+#[test]
+fn completion_in_a_block_offers_children_and_setters() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Card {
+    @InlineArg(\"title\", String)
+    @InlineProp(\"subtitle\", String)
+}
+
+@Main -> GtkBox {
+    Card(\"a\") {}
+    Ca
+}
+";
+    server.open("file:///test/children.gui", text);
+    let items = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/children.gui" },
+        "position": { "line": 7, "character": 6 }
+    }));
+
+    let items = items.as_array().expect("completion returned nothing");
+    let find = |label: &str| items.iter().find(|item| item["label"] == label).unwrap_or_else(|| panic!("no {} in {:?}", label, items));
+    let card = find("Card");
+    // CLASS
+    assert_eq!(card["kind"], 7);
+    assert_eq!(card["textEdit"]["newText"], "Card(${1:title}) {\n\t$0\n}");
+    assert_eq!(card["textEdit"]["range"]["start"], json!({ "line": 7, "character": 4 }));
+    let subtitle = find(".subtitle");
+    // PROPERTY
+    assert_eq!(subtitle["kind"], 10);
+    assert_eq!(subtitle["filterText"], "subtitle");
+    assert!(subtitle["textEdit"]["newText"].as_str().unwrap().starts_with(".subtitle("));
+
+    // Between top-level blocks there's no block to add to, so nothing is offered
+    let outside = server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/children.gui" },
+        "position": { "line": 4, "character": 0 }
+    }));
+    assert_eq!(outside, Value::Null);
+}
+
+#[test]
+fn small_widgets_collapse_onto_one_line() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Main -> GtkBox {
+    GtkLabel {
+    }
+        .label(\"Hi\")
+        .visible(false)
+    GtkLabel {}
+        // Says hello
+        .label(\"Hello\")
+    GtkBox {
+        GtkLabel {}
+    }
+        .spacing(6)
+}
+";
+    server.open("file:///test/collapse.gui", text);
+    let collapse = |server: &mut Server, line: u32| -> Option<Value> {
+        let actions = server.request("textDocument/codeAction", json!({
+            "textDocument": { "uri": "file:///test/collapse.gui" },
+            "range": { "start": { "line": line, "character": 6 }, "end": { "line": line, "character": 6 } },
+            "context": { "diagnostics": [] }
+        }));
+        actions.as_array().unwrap().iter().find(|action| action["title"].as_str().unwrap().starts_with("Collapse")).cloned()
+    };
+
+    let action = collapse(&mut server, 1).expect("no collapse offered");
+    assert_eq!(action["title"], "Collapse `GtkLabel` onto one line");
+    assert_eq!(action["kind"], "refactor");
+    let edit = &action["edit"]["changes"]["file:///test/collapse.gui"][0];
+    assert_eq!(edit["newText"], "GtkLabel {} .label(\"Hi\") .visible(false)");
+    assert_eq!(edit["range"], json!({ "start": { "line": 1, "character": 4 }, "end": { "line": 4, "character": 23 } }));
+    // The comment would be lost, and the box has a child
+    assert_eq!(collapse(&mut server, 5), None);
+    assert_eq!(collapse(&mut server, 8), None);
+}
+
+#[test]
+fn definition_follows_properties_that_name_a_widget() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@NameEntry -> GtkEntry {}
+
+@Main -> GtkBox {
+    GtkLabel {}
+        .mnemonic-widget(\"NameEntry\")
+        .label(\"NameEntry\")
+    GtkLabel {}
+        .mnemonic-widget(\"Missing\")
+}
+";
+    server.open("file:///test/ids.gui", text);
+    let definition = |server: &mut Server, after: &str| server.request("textDocument/definition", json!({
+        "textDocument": { "uri": "file:///test/ids.gui" },
+        "position": position_after(text, after)
+    }));
+
+    assert_eq!(definition(&mut server, ".mnemonic-widget(\"Name"), json!([{
+        "uri": "file:///test/ids.gui",
+        "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 10 } }
+    }]));
+    // A label's text is only text, even when it happens to be a widget's name
+    assert_eq!(definition(&mut server, ".label(\"Name"), Value::Null);
+    assert_eq!(definition(&mut server, ".mnemonic-widget(\"Miss"), Value::Null);
+}
+
+#[test]
+fn positions_after_astral_characters_count_utf16_everywhere() {
+    let (mut server, _) = Server::start(Value::Null);
+    // Each emoji is one char but two UTF-16 units. The last line has no newline, so the
+    // formatting edit has to end after them
+    let text = "@NameEntry -> GtkEntry {}\n\n@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"😀😀\") .mnemonic-widget(\"NameEntry\")\n}\n// 😀😀";
+    server.open("file:///test/astral.gui", text);
+
+    let definition = server.request("textDocument/definition", json!({
+        "textDocument": { "uri": "file:///test/astral.gui" },
+        // Counting chars would put this past the string
+        "position": position_after(text, ".mnemonic-widget(\"NameEntr")
+    }));
+    assert_eq!(definition[0]["range"], json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 10 } }));
+
+    let formatting = server.request("textDocument/formatting", json!({
+        "textDocument": { "uri": "file:///test/astral.gui" },
+        "options": { "tabSize": 2, "insertSpaces": true }
+    }));
+    assert_eq!(formatting[0]["range"]["end"], json!({ "line": 6, "character": 7 }));
+}
+
+#[test]
+fn a_name_defined_twice_goes_to_both_definitions() {
+    let workspace = Workspace::new("twice");
+    fs::write(workspace.0.join("cards.gui"), "@Card -> GtkFrame {}\n").unwrap();
+    let (mut server, _) = Server::start(Value::Null);
+    let document = format!("file://{}/main.gui", workspace.0.display());
+    let text = "#include \"cards\"\n\n@Card -> GtkBox {}\n\n@Card -> GtkGrid {}\n\n@Main -> GtkBox {\n    Card {}\n}\n";
+    server.open(&document, text);
+
+    let definition = server.request("textDocument/definition", json!({
+        "textDocument": { "uri": document },
+        "position": position_after(text, "    Ca")
+    }));
+    let found: Vec<(String, Value)> = definition
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| (location["uri"].as_str().unwrap().to_string(), location["range"]["start"]["line"].clone()))
+        .collect();
+    let cards = format!("file://{}/cards.gui", workspace.0.display());
+    assert_eq!(found, [(document.clone(), json!(2)), (document.clone(), json!(4)), (cards, json!(0))]);
+}
+
+#[test]
+fn token_types_can_be_remapped() {
+    let (mut server, result) = Server::launch(json!({
+        "capabilities": { "workspace": { "semanticTokens": { "refreshSupport": true } } }
+    }), json!({}));
+    let legend: Vec<String> = serde_json::from_value(result["capabilities"]["semanticTokensProvider"]["legend"]["tokenTypes"].clone()).unwrap();
+    server.open("file:///test/remap.gui", DOCUMENT);
+    let setter_types = |server: &mut Server| -> Vec<String> {
+        let tokens = server.request("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": "file:///test/remap.gui" } }));
+        let data: Vec<u64> = serde_json::from_value(tokens["data"].clone()).unwrap();
+        let types: Vec<String> = data.chunks(5).map(|token| legend[token[3] as usize].clone()).collect();
+        types.into_iter().filter(|token_type| token_type == "method" || token_type == "property").collect()
+    };
+    assert!(setter_types(&mut server).iter().all(|token_type| token_type == "method"));
+
+    server.notify("workspace/didChangeConfiguration", json!({ "settings": {
+        "semanticTokenTypes": { "setter": "property", "number": "sparkle" }
+    } }));
+    let warning = server.notification("window/logMessage", |params| params["type"] == 2);
+    assert!(warning["message"].as_str().unwrap().contains("`sparkle` is not a semantic token type"), "{}", warning);
+    server.server_request("workspace/semanticTokens/refresh");
+    let types = setter_types(&mut server);
+    assert!(!types.is_empty() && types.iter().all(|token_type| token_type == "property"), "{:?}", types);
+}
+
+#[test]
+fn toggle_boolean_flips_and_adds_bool_setters() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Toggle -> GtkBox {
+    @InlineProp(\"active\", Bool)
+    @InlineProp(\"caption\", String)
+}
+
+@Main -> GtkBox {
+    Toggle {}
+        .active(true)
+        .caption(\"On\")
+    Toggle {}
+}
+";
+    server.open("file:///test/toggle.gui", text);
+    let toggle = |server: &mut Server, position: Value, property: Value| server.try_request("workspace/executeCommand", json!({
+        "command": "gtkui.toggleBoolean",
+        "arguments": [{ "textDocument": { "uri": "file:///test/toggle.gui" }, "position": position, "property": property }]
+    }));
+
+    let flipped = toggle(&mut server, position_after(text, ".act"), Value::Null).unwrap();
+    assert_eq!(flipped, json!({
+        "range": { "start": { "line": 7, "character": 16 }, "end": { "line": 7, "character": 20 } },
+        "newText": "false"
+    }));
+    let error = toggle(&mut server, position_after(text, ".capt"), Value::Null).unwrap_err();
+    assert!(error["message"].as_str().unwrap().contains("`.caption` is not a Bool property"), "{}", error);
+
+    // The second widget doesn't set it yet
+    let added = toggle(&mut server, json!({ "line": 9, "character": 6 }), json!("active")).unwrap();
+    assert_eq!(added, json!({
+        "range": { "start": { "line": 9, "character": 13 }, "end": { "line": 9, "character": 13 } },
+        "newText": "\n        .active(true)"
+    }));
+    assert!(toggle(&mut server, json!({ "line": 9, "character": 6 }), json!("caption")).is_err());
+}
+
+#[test]
+fn wrap_in_widget_asks_for_the_container() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n    GtkButton {}\n}\n";
+    server.open("file:///test/wrap.gui", text);
+    let actions = server.request("textDocument/codeAction", json!({
+        "textDocument": { "uri": "file:///test/wrap.gui" },
+        "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 2, "character": 16 } },
+        "context": { "diagnostics": [] }
+    }));
+    let wrap = actions.as_array().unwrap().iter().find(|action| action["title"] == "Wrap in widget...").expect("no wrap action").clone();
+    assert_eq!(wrap["command"]["command"], "gtkui.wrapInWidget");
+    let wrap = |server: &mut Server| server.request("workspace/executeCommand", json!({
+        "command": "gtkui.wrapInWidget",
+        "arguments": wrap["command"]["arguments"]
+    }));
+
+    server.answers.insert(String::from("window/showMessageRequest"), json!({ "title": "GtkFrame" }));
+    server.answers.insert(String::from("workspace/applyEdit"), json!({ "applied": true }));
+    wrap(&mut server);
+    let asked = server.server_request("window/showMessageRequest");
+    // The configured container comes first
+    assert_eq!(asked["actions"][0]["title"], "GtkBox");
+    let applied = server.server_request("workspace/applyEdit");
+    let edit = &applied["edit"]["changes"]["file:///test/wrap.gui"][0];
+    assert_eq!(edit["newText"], "GtkFrame {\n        GtkLabel {}\n        GtkButton {}\n    }");
+
+    // Dismissing the question leaves the document alone
+    server.answers.insert(String::from("window/showMessageRequest"), Value::Null);
+    wrap(&mut server);
+    server.server_request("window/showMessageRequest");
+    assert!(!server.server_requests.iter().any(|(method, _)| method == "workspace/applyEdit"), "{:?}", server.server_requests);
+}
+
+#[test]
+fn toggle_comment_comments_and_uncomments_lines() {
+    let (mut server, _) = Server::start(Value::Null);
+    let text = "\
+@Main -> GtkBox {
+    GtkLabel {}
+        .label(\"On\")
+}
+";
+    server.open("file:///test/comment.gui", text);
+    let range = json!({ "start": { "line": 1, "character": 0 }, "end": { "line": 3, "character": 0 } });
+    let commented = server.request("workspace/executeCommand", json!({
+        "command": "gtkui.toggleComment",
+        "arguments": [{ "textDocument": { "uri": "file:///test/comment.gui" }, "range": range }]
+    }));
+    assert_eq!(commented, json!([
+        { "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 4 } }, "newText": "// " },
+        { "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 2, "character": 4 } }, "newText": "// " }
+    ]));
+
+    let text = "\
+@Main -> GtkBox {
+    // GtkLabel {}
+    //     .label(\"On\")
+}
+";
+    server.open("file:///test/commented.gui", text);
+    let uncommented = server.request("workspace/executeCommand", json!({
+        "command": "gtkui.toggleComment",
+        "arguments": [{ "textDocument": { "uri": "file:///test/commented.gui" }, "range": range }]
+    }));
+    assert_eq!(uncommented, json!([
+        { "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 7 } }, "newText": "" },
+        { "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 2, "character": 7 } }, "newText": "" }
+    ]));
+
+    // The two comment lines fold together
+    let folds = server.request("textDocument/foldingRange", json!({ "textDocument": { "uri": "file:///test/commented.gui" } }));
+    assert!(folds.as_array().unwrap().iter().any(|fold| fold["kind"] == "comment" && fold["startLine"] == 1 && fold["endLine"] == 2), "{}", folds);
+}
+
+#[test]
+fn a_document_that_does_not_parse_still_folds() {
+    let (mut server, _) = Server::start(Value::Null);
+    // `GtkLabel(` is never closed
+    let text = "\
+@Main -> GtkBox {
+    GtkBox {
+        GtkLabel(
+            \"a\"
+        .label(\"x\")
+    }
+    GtkButton {}
+        .label(\"b\")
+// a
+// b
+";
+    server.open("file:///test/broken_folds.gui", text);
+    let folds = server.request("textDocument/foldingRange", json!({ "textDocument": { "uri": "file:///test/broken_folds.gui" } }));
+    let mut folds: Vec<(u64, u64, &str)> = folds
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|fold| (fold["startLine"].as_u64().unwrap(), fold["endLine"].as_u64().unwrap(), fold["kind"].as_str().unwrap()))
+        .collect();
+    folds.sort();
+    // By indentation, the way an editor folds plain text
+    assert_eq!(folds, [(0, 7, "region"), (1, 4, "region"), (2, 3, "region"), (6, 7, "region"), (8, 9, "comment")]);
+}
+
+#[test]
+fn long_completion_lists_are_capped_and_incomplete() {
+    let (mut server, _) = Server::start(json!({ "completion": { "maxItems": 3 } }));
+    let mut text: String = ["ThemeArea", "Header", "HeaderBar", "Heap", "Hero", "Footer"].iter().map(|name| format!("@{} {{}}\n", name)).collect();
+    text.push_str("\n@Main -> GtkBox {\n    Hea\n}\n");
+    server.open("file:///test/capped.gui", &text);
+    let line = text.lines().count() as u32 - 2;
+    let complete = |server: &mut Server, character: u32| server.request("textDocument/completion", json!({
+        "textDocument": { "uri": "file:///test/capped.gui" },
+        "position": { "line": line, "character": character }
+    }));
+
+    // Filtered by what's typed, the ones starting with it first, so `ThemeArea` is cut
+    let list = complete(&mut server, 7);
+    assert_eq!(list["isIncomplete"], true);
+    let labels: Vec<&str> = list["items"].as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap()).collect();
+    assert_eq!(labels, ["Header", "HeaderBar", "Heap"]);
+    // Nothing typed yet, and the cap still holds
+    let list = complete(&mut server, 4);
+    assert_eq!(list["items"].as_array().unwrap().len(), 3);
+    assert_eq!(list["isIncomplete"], true);
+}
@@ -0,0 +1,139 @@
+// Documents are edited a little at a time, and whatever keeps their tokens up to date has
+// to end up exactly where lexing the whole new text would. This applies random edits to the
+// files in tests/corpus and checks that after every one of them.
+
+mod common;
+
+use std::ops::Range;
+use gtkui_language_server::lexer;
+use gtk_ui::lexer::Token;
+use common::{char_boundary, Rng};
+use lexer::{LexError, LexOptions, TokenExt};
+
+const SEEDS: u64 = 16;
+const EDITS_PER_SEED: usize = 200;
+
+// Pieces of text an edit inserts, weighted towards the ones that change how the text
+// around them lexes
+const FRAGMENTS: &[&str] = &[
+    "",
+    " ",
+    "\t",
+    "\n",
+    "\r\n",
+    "\"",
+    "\\",
+    "{",
+    "}",
+    "(",
+    ")",
+    ",",
+    ".",
+    "-",
+    "->",
+    "/",
+    "//",
+    "@",
+    "#",
+    "#include",
+    "#inc",
+    "true",
+    "String",
+    "GtkLabel",
+    ".label(\"x\")",
+    "@InlineProp(\"name\", Bool)",
+    "12",
+    "1.2.3",
+    "0.5",
+    "$",
+    "é",
+    "🖼"
+];
+
+#[derive(Debug)]
+struct Edit {
+    range: Range<usize>,
+    text: &'static str
+}
+
+fn random_edit(rng: &mut Rng, text: &str) -> Edit {
+    let start = char_boundary(text, rng.below(text.len() + 1));
+    let end = char_boundary(text, (start + rng.below(8)).min(text.len())).max(start);
+    Edit {
+        range: start..end,
+        text: FRAGMENTS[rng.below(FRAGMENTS.len())]
+    }
+}
+
+fn full_lex(text: &str) -> (Vec<Token>, Vec<LexError>) {
+    lexer::lex_with(text, LexOptions::default()).expect("the lexer crashed")
+}
+
+// What the server does to bring a document's tokens up to date after an edit. Until
+// documents are relexed incrementally this is a full relex, so the comparison below holds
+// trivially, but the invariant is the one an incremental version has to keep
+fn relex(_previous: &[Token], _edit: &Edit, text: &str) -> (Vec<Token>, Vec<LexError>) {
+    full_lex(text)
+}
+
+// Invariants of any token stream, independent of how it was produced
+fn check_tokens(text: &str, tokens: &[Token], context: &str) {
+    let mut end = 0;
+    for token in tokens {
+        assert!(token.range.start >= end, "{}: {:?} overlaps the token before it", context, token);
+        assert!(token.range.start < token.range.end, "{}: {:?} is empty", context, token);
+        assert!(token.range.end <= text.len(), "{}: {:?} runs past the end of the text", context, token);
+        assert!(
+            text.is_char_boundary(token.range.start) && text.is_char_boundary(token.range.end),
+            "{}: {:?} splits a character",
+            context,
+            token
+        );
+        assert!(!token.text(text).trim().is_empty(), "{}: {:?} is only whitespace", context, token);
+        end = token.range.end;
+    }
+}
+
+fn run(name: &str, source: &str) {
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut text = source.to_string();
+        let (mut tokens, _) = full_lex(&text);
+
+        for step in 0..EDITS_PER_SEED {
+            let edit = random_edit(&mut rng, &text);
+            text.replace_range(edit.range.clone(), edit.text);
+
+            let context = format!("{} seed {} step {} ({:?})", name, seed, step, edit);
+            let (relexed, relexed_errors) = relex(&tokens, &edit, &text);
+            let (expected, expected_errors) = full_lex(&text);
+            check_tokens(&text, &expected, &context);
+            assert!(
+                lexer::same_tokens(&relexed, &expected),
+                "{}: tokens differ from a full relex\n  relexed: {:?}\n  expected: {:?}\n  text: {:?}",
+                context,
+                relexed,
+                expected,
+                text
+            );
+            assert_eq!(relexed_errors, expected_errors, "{}: errors differ from a full relex", context);
+            tokens = relexed;
+        }
+    }
+}
+
+#[test]
+fn relex_matches_full_lex() {
+    common::for_each_corpus_file(run);
+}
+
+// Unchanged text must relex to the very same tokens, whatever the corpus file contains
+#[test]
+fn lexing_is_deterministic() {
+    common::for_each_corpus_file(|name, source| {
+        let (first, _) = full_lex(source);
+        let (second, _) = full_lex(source);
+        check_tokens(source, &first, name);
+        assert!(lexer::same_tokens(&first, &second));
+    });
+}
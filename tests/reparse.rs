@@ -0,0 +1,102 @@
+// Reparsing after an edit only redoes the part of the tree the edit touched, and has to end
+// up exactly where parsing the whole new text would. This applies random edits to the files
+// in tests/corpus, like tests/relex.rs, and checks the tree after every one of them.
+
+mod common;
+
+use gtkui_language_server::{ast, lexer};
+use gtk_ui::lexer::Token;
+use common::{char_boundary, Rng};
+use lexer::LexOptions;
+
+const SEEDS: u64 = 16;
+const EDITS_PER_SEED: usize = 200;
+
+// Mostly whole statements and the pieces that open or close them, so edits move the
+// boundaries between top-level blocks as well as change what is inside them
+const FRAGMENTS: &[&str] = &[
+    "",
+    " ",
+    "\n",
+    "{",
+    "}",
+    "(",
+    ")",
+    ",",
+    "\"",
+    "//",
+    "->",
+    "@Card",
+    "@Card -> GtkBox {}\n",
+    "@InlineProp(\"name\", Bool)",
+    "#include \"a.gui\"\n",
+    "GtkLabel",
+    "GtkLabel {}",
+    ".label(\"x\")",
+    "12",
+    "true"
+];
+
+fn lex(text: &str) -> Vec<Token> {
+    lexer::lex_with(text, LexOptions { include_trivia: true, allow_recovery: true }).expect("the lexer crashed").0
+}
+
+// The tree's types are foreign or hold foreign tokens, so they are compared as printed
+fn printed(ast: &ast::Ast) -> String {
+    format!("{:?}", ast)
+}
+
+fn run(name: &str, source: &str) {
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut text = source.to_string();
+        let mut tokens = lex(&text);
+        let mut tree = ast::parse(&tokens);
+
+        for step in 0..EDITS_PER_SEED {
+            let start = char_boundary(&text, rng.below(text.len() + 1));
+            let end = char_boundary(&text, (start + rng.below(12)).min(text.len())).max(start);
+            let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+            text.replace_range(start..end, fragment);
+
+            let relexed = lex(&text);
+            let reparsed = ast::reparse(&tree, &tokens, &relexed);
+            let expected = ast::parse(&relexed);
+            assert_eq!(
+                printed(&reparsed),
+                printed(&expected),
+                "{} seed {} step {} (replaced {:?} with {:?}): the tree differs from a full parse\n  text: {:?}",
+                name,
+                seed,
+                step,
+                start..end,
+                fragment,
+                text
+            );
+            tokens = relexed;
+            tree = reparsed;
+        }
+    }
+}
+
+#[test]
+fn reparse_matches_full_parse() {
+    common::for_each_corpus_file(run);
+}
+
+// An edit inside one block leaves the steps of every other block as they were
+#[test]
+fn an_edit_inside_one_block_keeps_the_others() {
+    let before = "@A -> GtkBox {\n    GtkLabel {}\n}\n\n@B -> GtkBox {\n    GtkLabel {}\n}\n\n@C -> GtkBox {}\n";
+    let after = before.replace("@B -> GtkBox {\n    GtkLabel {}", "@B -> GtkBox {\n    GtkLabel {}\n        .label(\"x\")");
+    let (old_tokens, new_tokens) = (lex(before), lex(&after));
+    let previous = ast::parse(&old_tokens);
+    let reparsed = ast::reparse(&previous, &old_tokens, &new_tokens);
+
+    assert_eq!(printed(&reparsed), printed(&ast::parse(&new_tokens)));
+    assert_eq!(reparsed.steps.len(), 3);
+    assert_eq!(reparsed.steps[0].tokens, previous.steps[0].tokens);
+    assert_eq!(reparsed.steps[2].tokens, previous.steps[2].tokens.start + 4..previous.steps[2].tokens.end + 4);
+    let shift = after.len() - before.len();
+    assert_eq!(reparsed.nodes[2].range(), &(previous.nodes[2].range().start + shift..previous.nodes[2].range().end + shift));
+}
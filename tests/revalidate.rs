@@ -0,0 +1,101 @@
+// Diagnostics are only recomputed for the top-level blocks an edit changed, and what gets
+// published has to be exactly what validating the whole new text would give. This applies
+// random edits to the files in tests/corpus, like tests/reparse.rs, keeping one cache across
+// all of them, and compares against a validation from an empty cache after every edit.
+
+mod common;
+
+use gtkui_language_server::{ast, database, diagnostics, lexer, position, settings};
+use common::{char_boundary, Rng};
+use database::WidgetDb;
+use diagnostics::DiagnosticCache;
+use lexer::LexOptions;
+use position::PositionEncoding;
+use ropey::Rope;
+use serde_json::json;
+use settings::Settings;
+
+const SEEDS: u64 = 4;
+const EDITS_PER_SEED: usize = 100;
+
+// Edits inside blocks as well as ones that change a definition, and so everything
+const FRAGMENTS: &[&str] = &[
+    "",
+    " ",
+    "\n",
+    "\t",
+    "{",
+    "}",
+    "(",
+    ")",
+    "\"",
+    "GtkLabel {}",
+    "    GtkBox {}\n        .spacing(0)\n",
+    ".nope(1)",
+    ".label(\"x\")",
+    ".margin-left(4)",
+    "@Card -> GtkBox {}\n",
+    "@InlineArg(\"title\", String)",
+    "Card {}",
+    "#include \"a.gui\"\n"
+];
+
+// Every rule on, so every kind of diagnostic is compared
+fn settings() -> Settings {
+    Settings::from_value(json!({
+        "diagnostics": {
+            "redundantDefaults": true,
+            "mixedIndentation": true,
+            "unknownProperties": true,
+            "missingArguments": true,
+            "propertiesOutsideWidgets": true,
+            "parserRecovery": true,
+            "maxNestingDepth": 3
+        },
+        "gtkVersion": "4.4"
+    }))
+}
+
+fn run(name: &str, source: &str) {
+    let settings = settings();
+    let widgets = WidgetDb::default();
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut text = source.to_string();
+        let mut cache = DiagnosticCache::default();
+
+        for step in 0..EDITS_PER_SEED {
+            let start = char_boundary(&text, rng.below(text.len() + 1));
+            // Removing about as much as the fragments add keeps the text near its size
+            let end = char_boundary(&text, (start + rng.below(24)).min(text.len())).max(start);
+            let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+            text.replace_range(start..end, fragment);
+
+            // Text that can't be lexed is published without validating anything
+            let Ok((tokens, _)) = lexer::lex_with(&text, LexOptions { include_trivia: true, allow_recovery: true }) else { continue };
+            let ast = ast::parse(&tokens);
+            let mut db = widgets.clone();
+            db.add_definitions(&ast);
+            let rope = Rope::from_str(&text);
+
+            let incremental = diagnostics::validate_incremental(&rope, PositionEncoding::Utf16, &ast, &db, &settings, &mut cache);
+            let full = diagnostics::validate_incremental(&rope, PositionEncoding::Utf16, &ast, &db, &settings, &mut DiagnosticCache::default());
+            assert_eq!(
+                incremental,
+                full,
+                "{} seed {} step {} (replaced {:?} with {:?}): the diagnostics differ from a full validation\n  text: {:?}",
+                name,
+                seed,
+                step,
+                start..end,
+                fragment,
+                text
+            );
+        }
+    }
+}
+
+#[test]
+fn revalidation_matches_full_validation() {
+    common::for_each_corpus_file(run);
+}
@@ -0,0 +1,111 @@
+// The encoder only ever sees tokens and a rope that are stored apart, so it has to cope with
+// tokens that were lexed from a different version of the text than the rope holds.
+
+use gtkui_language_server::{lexer, position, semantic_tokens};
+use lexer::LexOptions;
+use tower_lsp::lsp_types::SemanticToken;
+use position::PositionEncoding;
+use semantic_tokens::TokenTypes;
+use ropey::Rope;
+
+const TEXT: &str = "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"Hello, world\")\n        .margin-top(12)\n}\n";
+
+#[test]
+fn stale_tokens_against_a_shorter_rope_are_skipped() {
+    let (tokens, _) = lexer::lex_with(TEXT, LexOptions::default()).unwrap();
+    // The text as it was before the last two lines were typed
+    let shorter = &TEXT[..TEXT.find("        .label").unwrap()];
+    let rope = Rope::from_str(shorter);
+
+    let stale = semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false);
+    let (current, _) = lexer::lex_with(shorter, LexOptions::default()).unwrap();
+    let expected = semantic_tokens::encode_semantic_tokens(&current, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false);
+    // What still fits is encoded as it would be from the rope's own tokens, and nothing is
+    // placed past its last line
+    assert_eq!(stale, expected);
+    let last_line: u32 = stale.iter().map(|token| token.delta_line).sum();
+    assert!((last_line as usize) < rope.len_lines());
+}
+
+#[test]
+fn tokens_reaching_past_the_end_are_skipped() {
+    let (tokens, _) = lexer::lex_with(TEXT, LexOptions::default()).unwrap();
+    // Cut in the middle of the string, so that token starts inside the rope but ends outside it
+    let cut = TEXT.find("world").unwrap();
+    let rope = Rope::from_str(&TEXT[..cut]);
+
+    let encoded = semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false);
+    let inside = tokens.iter()
+        .enumerate()
+        .filter(|(_, token)| token.range.end <= cut)
+        .count();
+    assert!(!encoded.is_empty());
+    assert!(encoded.len() <= inside);
+}
+
+// Where each token starts and how long it is, with its type's name
+fn decoded(encoded: &[SemanticToken]) -> Vec<(u32, u32, u32, &'static str)> {
+    let (mut line, mut start) = (0, 0);
+    encoded.iter()
+        .map(|token| {
+            line += token.delta_line;
+            start = if token.delta_line == 0 { start + token.delta_start } else { token.delta_start };
+            (line, start, token.length, semantic_tokens::LEGEND_TYPE[token.token_type as usize].as_str())
+        })
+        .collect()
+}
+
+#[test]
+fn escapes_are_highlighted_on_top_of_their_string() {
+    let text = "GtkLabel {}\n    .label(\"a\\tb \\\"c\\\"\")\n";
+    let (tokens, _) = lexer::lex_with(text, LexOptions::default()).unwrap();
+    let rope = Rope::from_str(text);
+
+    let strings: Vec<_> = decoded(&semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), true))
+        .into_iter()
+        .filter(|(line, _, _, _)| *line == 1)
+        .collect();
+    assert_eq!(strings, [
+        (1, 4, 6, "method"),
+        (1, 11, 12, "string"),
+        (1, 13, 2, "escapeSequence"),
+        (1, 17, 2, "escapeSequence"),
+        (1, 20, 2, "escapeSequence")
+    ]);
+
+    // Without overlapping tokens the string stays whole
+    let plain = decoded(&semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false));
+    assert!(plain.iter().all(|(_, _, _, token_type)| *token_type != "escapeSequence"));
+}
+
+#[test]
+fn booleans_and_type_names_are_told_apart() {
+    let text = "@Toggle -> GtkBox {\n    @InlineProp(\"active\", Bool)\n}\n\nToggle {}\n    .active(true)\n";
+    let (tokens, _) = lexer::lex_with(text, LexOptions::default()).unwrap();
+    let rope = Rope::from_str(text);
+
+    let encoded = decoded(&semantic_tokens::encode_semantic_tokens(&tokens, &rope, PositionEncoding::Utf16, &TokenTypes::default(), false));
+    assert!(encoded.contains(&(1, 26, 4, "keyword")), "{:?}", encoded);
+    assert!(encoded.contains(&(5, 12, 4, "enumMember")), "{:?}", encoded);
+}
+
+#[test]
+fn columns_and_lengths_count_in_the_encoding() {
+    let text = "@Main -> GtkBox {\n    GtkLabel {}\n        .label(\"é😀\").visible(true)\n        .margin-top(12)\n}\n";
+    let (tokens, _) = lexer::lex_with(text, LexOptions::default()).unwrap();
+    let rope = Rope::from_str(text);
+    // The string is 4 chars, 5 UTF-16 code units and 8 bytes long
+    for (encoding, length) in [(PositionEncoding::Utf8, 8), (PositionEncoding::Utf16, 5), (PositionEncoding::Utf32, 4)] {
+        let encoded = decoded(&semantic_tokens::encode_semantic_tokens(&tokens, &rope, encoding, &TokenTypes::default(), false));
+        let on_line = |line: u32| -> Vec<(u32, u32, &str)> {
+            encoded.iter().filter(|token| token.0 == line).map(|token| (token.1, token.2, token.3)).collect()
+        };
+        let string = on_line(2).into_iter().find(|token| token.2 == "string").unwrap();
+        assert_eq!(string, (15, length, "string"), "{:?}", encoding);
+        // What follows on the same line moves along with the string's length
+        let visible = on_line(2).into_iter().filter(|token| token.0 > string.0).map(|token| token.0).min().unwrap();
+        assert_eq!(visible, 15 + length + 1, "{:?}", encoding);
+        // A line after it starts counting afresh
+        assert_eq!(on_line(3)[0].0, 8, "{:?}", encoding);
+    }
+}
@@ -0,0 +1,61 @@
+// The whitespace and comments between tokens, which formatting has to write back out exactly
+// where they were.
+
+use gtkui_language_server::{ast, lexer, trivia};
+use ast::Node;
+use gtk_ui::lexer::TokenValue;
+use lexer::LexOptions;
+use trivia::{TriviaKind, TriviaMap};
+
+const TEXT: &str = "\
+// The main window
+@Main -> GtkBox {
+    GtkLabel {} // greeting
+        .label(\"Hi\")\r
+
+    // Last
+    GtkButton {}
+}
+// The end
+";
+
+#[test]
+fn trivia_and_tokens_make_up_the_whole_source() {
+    let (tokens, _) = lexer::lex_with(TEXT, LexOptions::default()).unwrap();
+    let map = TriviaMap::new(TEXT, &tokens);
+    let mut pieces: Vec<_> = map.iter().map(|piece| piece.range.clone()).collect();
+    pieces.extend(tokens.iter().filter(|token| !matches!(token.value, TokenValue::Comment)).map(|token| token.range.clone()));
+    pieces.sort_by_key(|range| range.start);
+
+    let mut at = 0;
+    for range in pieces {
+        assert_eq!(range.start, at, "a gap or overlap before {:?}", range);
+        at = range.end;
+    }
+    assert_eq!(at, TEXT.len());
+    // The same pieces in the same order as the flat list
+    assert_eq!(map.iter().cloned().collect::<Vec<_>>(), trivia::trivia(TEXT, &tokens));
+    assert!(map.iter().any(|piece| piece.kind == TriviaKind::Newline && piece.text(TEXT) == "\r\n"));
+}
+
+#[test]
+fn comments_lead_the_statement_below_and_trail_the_one_beside() {
+    let (tokens, _) = lexer::lex_with(TEXT, LexOptions::default()).unwrap();
+    let ast = ast::parse(&tokens);
+    let map = TriviaMap::new(TEXT, &tokens);
+    let comments = |pieces: &[trivia::Trivia]| -> Vec<String> {
+        pieces.iter().filter(|piece| piece.kind == TriviaKind::Comment).map(|piece| piece.text(TEXT).to_string()).collect()
+    };
+
+    let main = &ast.nodes[0];
+    let (leading, _) = map.around(main.range());
+    assert_eq!(comments(leading), ["// The main window"]);
+    let children = main.children();
+    let Node::Object(label) = &children[0] else { panic!("{:?}", children[0]) };
+    assert_eq!(comments(map.trailing(label.block.as_ref().unwrap().end)), ["// greeting"]);
+    let (leading, _) = map.around(children[1].range());
+    assert_eq!(comments(leading), ["// Last"]);
+    // The blank line stays with `// Last`, after the line ending that trails `.label("Hi")`
+    assert_eq!(leading.iter().filter(|piece| piece.kind == TriviaKind::Newline).count(), 2);
+    assert_eq!(comments(map.end()), ["// The end"]);
+}